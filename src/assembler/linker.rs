@@ -29,17 +29,53 @@ error_chain!{
 }
 
 pub fn link(ast: &[ParsedItem]) -> Result<(Vec<u16>, Globals)> {
+    link_items(ast).map(|(bin, labels, _)| (bin, labels))
+}
+
+/// Link items annotated with their source line, also returning the address
+/// range generated by each line.
+pub fn link_with_debug_info(ast: &[(usize, ParsedItem)])
+                            -> Result<(Vec<u16>, Globals, DebugInfo)> {
+    let items: Vec<ParsedItem> = ast.iter()
+                                    .map(|&(_, ref item)| item.clone())
+                                    .collect();
+    let (bin, labels, addresses) = try!(link_items(&items));
+
+    let mut debug_info = DebugInfo::default();
+    for (i, &(line, ref item)) in ast.iter().enumerate() {
+        let end = addresses.get(i + 1).cloned().unwrap_or(bin.len() as u16);
+        let addr = addresses[i];
+        if end > addr {
+            debug_info.lines.push(LineInfo {
+                line: line,
+                addr: addr,
+                size: end - addr,
+                code: match *item {
+                    ParsedItem::Instruction(_) => true,
+                    _ => false,
+                },
+            });
+        }
+    }
+
+    Ok((bin, labels, debug_info))
+}
 
+/// Also returns the address of each item.
+fn link_items(ast: &[ParsedItem]) -> Result<(Vec<u16>, Globals, Vec<u16>)> {
     let mut bin = Vec::new();
     let mut labels = try!(extract_labels(ast));
+    let mut addresses = Vec::with_capacity(ast.len());
     let mut changed = true;
 
     while changed {
         bin.clear();
+        addresses.clear();
         changed = false;
         let mut index = 0u16;
         let mut last_global = None;
         for item in ast {
+            addresses.push(index);
             match *item {
                 ParsedItem::Directive(Directive::Lcomm(ref symbol, size)) => {
                     let label = labels.get_mut(symbol).unwrap();
@@ -84,7 +120,7 @@ pub fn link(ast: &[ParsedItem]) -> Result<(Vec<u16>, Globals)> {
         }
     }
 
-    Ok((bin, labels))
+    Ok((bin, labels, addresses))
 }
 
 fn extract_labels(ast: &[ParsedItem]) -> Result<Globals> {
@@ -120,3 +156,37 @@ fn extract_labels(ast: &[ParsedItem]) -> Result<Globals> {
 
     Ok(labels)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::parser::parse_with_lines;
+
+    #[test]
+    fn test_debug_info() {
+        let ast = parse_with_lines(":start\n\
+                                    SET A, 0x1234\n\
+                                    \n\
+                                    ADD A, 1\n\
+                                    .dat 1 2 3\n\
+                                    SUB PC, 1\n")
+                      .unwrap();
+        let (bin, _, debug_info) = link_with_debug_info(&ast).unwrap();
+        assert_eq!(bin.len(), 7);
+        assert_eq!(debug_info.lines,
+                   vec![LineInfo { line: 2, addr: 0, size: 2, code: true },
+                        LineInfo { line: 4, addr: 2, size: 1, code: true },
+                        LineInfo { line: 5, addr: 3, size: 3, code: false },
+                        LineInfo { line: 6, addr: 6, size: 1, code: true }]);
+
+        assert_eq!(debug_info.addr_of_line(1), Some((2, 0)));
+        assert_eq!(debug_info.addr_of_line(3), Some((4, 2)));
+        assert_eq!(debug_info.addr_of_line(6), Some((6, 6)));
+        assert_eq!(debug_info.addr_of_line(7), None);
+
+        assert_eq!(debug_info.line_of_addr(1), Some(2));
+        assert_eq!(debug_info.line_of_addr(4), Some(5));
+        assert_eq!(debug_info.line_of_addr(6), Some(6));
+        assert_eq!(debug_info.line_of_addr(7), None);
+    }
+}
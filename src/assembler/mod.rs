@@ -4,9 +4,9 @@ pub mod parser;
 mod preprocessor;
 pub mod types;
 
-pub use self::preprocessor::preprocess;
-pub use self::linker::link;
-pub use self::parser::parse;
+pub use self::preprocessor::{preprocess, preprocess_with_lines, LineMap};
+pub use self::linker::{link, link_with_debug_info};
+pub use self::parser::{parse, parse_with_lines};
 pub use self::cleaner::{clean, print_unused};
 
 error_chain! {
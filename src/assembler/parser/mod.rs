@@ -1,8 +1,9 @@
 pub mod nom_parser;
 
+use std::fmt;
 use std::str;
 
-use nom::IResult;
+use nom::{IResult, Offset};
 
 use assembler::types::*;
 
@@ -16,7 +17,34 @@ error_chain! {
 }
 
 pub fn parse(source: &str) -> Result<Vec<ParsedItem>> {
-    match nom_parser::parse(source.as_bytes()) {
+    check_result(source, nom_parser::parse(source.as_bytes()))
+}
+
+/// Parse `source`, returning the line of each item along with it.
+pub fn parse_with_lines(source: &str) -> Result<Vec<(usize, ParsedItem)>> {
+    let raw = source.as_bytes();
+    let located = try!(check_result(source, nom_parser::parse_located(raw)));
+    let newlines: Vec<usize> = raw.iter()
+                                  .enumerate()
+                                  .filter(|&(_, &c)| c == b'\n')
+                                  .map(|(i, _)| i)
+                                  .collect();
+    Ok(located.into_iter()
+              .map(|(i, item)| (line_at(&newlines, raw.offset(i)), item))
+              .collect())
+}
+
+/// Line of the byte at `offset`, a newline being counted in the line it
+/// ends like `nom_parser::line_number`.
+fn line_at(newlines: &[usize], offset: usize) -> usize {
+    match newlines.binary_search(&offset) {
+        Ok(n) => n + 2,
+        Err(n) => n + 1,
+    }
+}
+
+fn check_result<O: fmt::Debug>(source: &str, res: IResult<&[u8], O>) -> Result<O> {
+    match res {
         IResult::Done(i, o) => if i.len() == 0 {
             Ok(o)
         } else {
@@ -34,3 +62,15 @@ pub fn parse(source: &str) -> Result<Vec<ParsedItem>> {
         e => try!(Err(format!("Error: {:?}", e))),
     }
 }
+
+#[cfg(test)]
+#[test]
+fn test_line_at() {
+    let source = "SET A, 1\n\n:loop\n  ADD A, 1\n";
+    let raw = source.as_bytes();
+    let newlines = [8, 9, 15, 26];
+    for offset in 0..raw.len() {
+        assert_eq!(line_at(&newlines, offset),
+                   nom_parser::line_number(raw, &raw[offset..]).0);
+    }
+}
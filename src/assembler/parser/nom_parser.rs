@@ -86,7 +86,7 @@ named!(basic_op<BasicOp>,
     )
 );
 
-named!(instruction<Instruction<Expression> >,
+named!(pub instruction<Instruction<Expression> >,
     alt_complete!(basic_instruction | special_instruction)
 );
 
@@ -178,7 +178,7 @@ named!(value<Value<Expression> >,
     )
 );
 
-named!(raw_label<String>,
+named!(pub raw_label<String>,
     map_res!(
         recognize!(
             preceded!(
@@ -190,7 +190,7 @@ named!(raw_label<String>,
     )
 );
 
-named!(raw_local_label<String>,
+named!(pub raw_local_label<String>,
     chain!(alt!(char!('.') | char!('_')) ~
            l: raw_label,
            || l)
@@ -419,19 +419,35 @@ named!(directive<Directive>,
            || d)
 );
 
+named!(item<ParsedItem>,
+    alt_complete!(
+        map!(directive, ParsedItem::Directive) |
+        map!(instruction, ParsedItem::Instruction) |
+        comment |
+        local_label_decl |
+        label_decl
+    )
+);
+
 named!(pub parse< Vec<ParsedItem> >,
     delimited!(
         opt!(multispace),
-        separated_list!(multispace,
-                        alt_complete!(
-                            map!(directive, ParsedItem::Directive) |
-                            map!(instruction,
-                                 ParsedItem::Instruction) |
-                            comment |
-                            local_label_decl |
-                            label_decl
-                        )
-        ),
+        separated_list!(multispace, item),
+        opt!(multispace)
+    )
+);
+
+/// Returns the input without consuming it.
+fn position(i: &[u8]) -> IResult<&[u8], &[u8]> {
+    IResult::Done(i, i)
+}
+
+// Same as `parse`, but keeps the input starting at each item so that its
+// line can be found with `line_number`.
+named!(pub parse_located<&[u8], Vec<(&[u8], ParsedItem)> >,
+    delimited!(
+        opt!(multispace),
+        separated_list!(multispace, pair!(position, item)),
         opt!(multispace)
     )
 );
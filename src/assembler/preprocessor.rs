@@ -1,14 +1,64 @@
 use std::io::Write;
 use std::process::*;
 
+use assembler::types::DebugInfo;
+
 error_chain! {}
 
+/// Name given by cpp to its input in the linemarkers.
+const STDIN: &'static str = "\"<stdin>\"";
+
 pub fn preprocess(asm: &str) -> Result<String> {
-    let mut process = Command::new("cpp")
-        .arg("-Wall")
-        .args(&["-x", "assembler-with-cpp"])
-        .arg("-nostdinc")
-        .arg("-P")
+    run_cpp(asm, false)
+}
+
+/// Preprocess `asm`, also returning where each line of the output comes
+/// from.
+pub fn preprocess_with_lines(asm: &str) -> Result<(String, LineMap)> {
+    let output = try!(run_cpp(asm, true));
+    Ok(strip_linemarkers(&output))
+}
+
+/// Mapping between the lines of the preprocessor output and the lines of its
+/// input.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct LineMap {
+    lines: Vec<Option<usize>>,
+}
+
+impl LineMap {
+    /// Line of the input which produced `line` of the output, or `None` if it
+    /// comes from an included file.
+    pub fn source_line(&self, line: usize) -> Option<usize> {
+        line.checked_sub(1)
+            .and_then(|i| self.lines.get(i).cloned())
+            .and_then(|l| l)
+    }
+
+    /// Rewrite `debug_info` to use the lines of the input, dropping the code
+    /// generated by included files.
+    pub fn apply(&self, debug_info: &mut DebugInfo) {
+        let lines = debug_info.lines.drain(..).collect::<Vec<_>>();
+        debug_info.lines = lines.into_iter()
+                                .filter_map(|mut l| {
+                                    self.source_line(l.line).map(|line| {
+                                        l.line = line;
+                                        l
+                                    })
+                                })
+                                .collect();
+    }
+}
+
+fn run_cpp(asm: &str, linemarkers: bool) -> Result<String> {
+    let mut command = Command::new("cpp");
+    command.arg("-Wall")
+           .args(&["-x", "assembler-with-cpp"])
+           .arg("-nostdinc");
+    if !linemarkers {
+        command.arg("-P");
+    }
+    let mut process = command
         .stdin(Stdio::piped())
         .stdout(Stdio::piped())
         .stderr(Stdio::inherit())
@@ -27,3 +77,79 @@ pub fn preprocess(asm: &str) -> Result<String> {
         Err("preprocessor error".into())
     }
 }
+
+/// Remove the `# <line> "<file>" <flags>` lines from the cpp output, using
+/// them to build the line map.
+fn strip_linemarkers(output: &str) -> (String, LineMap) {
+    let mut stripped = String::with_capacity(output.len());
+    let mut map = LineMap::default();
+    let mut current = None;
+    for line in output.lines() {
+        if let Some(marker) = parse_linemarker(line) {
+            current = marker;
+            continue;
+        }
+        map.lines.push(current);
+        current = current.map(|l| l + 1);
+        stripped.push_str(line);
+        stripped.push('\n');
+    }
+    (stripped, map)
+}
+
+/// `Some(Some(line))` for a linemarker in the input, `Some(None)` for one in
+/// another file.
+fn parse_linemarker(line: &str) -> Option<Option<usize>> {
+    if !line.starts_with("# ") {
+        return None;
+    }
+    let mut parts = line[2..].splitn(2, ' ');
+    let number = match parts.next().and_then(|n| n.parse::<usize>().ok()) {
+        Some(n) => n,
+        None => return None,
+    };
+    let file = parts.next().unwrap_or("");
+    if file == STDIN || file.starts_with(&format!("{} ", STDIN)) {
+        Some(Some(number))
+    } else {
+        Some(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::types::{DebugInfo, LineInfo};
+
+    #[test]
+    fn test_strip_linemarkers() {
+        let output = "# 0 \"<stdin>\"\n\
+                      # 0 \"<built-in>\"\n\
+                      # 1 \"<stdin>\"\n\
+                      SET A, 1\n\
+                      # 1 \"lib.asm\" 1\n\
+                      SET B, 1\n\
+                      # 3 \"<stdin>\" 2\n\
+                      \n\
+                      SET C, 2\n";
+        let (stripped, map) = strip_linemarkers(output);
+        assert_eq!(stripped, "SET A, 1\nSET B, 1\n\nSET C, 2\n");
+        assert_eq!(map.source_line(1), Some(1));
+        assert_eq!(map.source_line(2), None);
+        assert_eq!(map.source_line(4), Some(4));
+        assert_eq!(map.source_line(5), None);
+
+        let line = |line, addr| LineInfo {
+            line: line,
+            addr: addr,
+            size: 1,
+            code: true,
+        };
+        let mut debug_info = DebugInfo {
+            file: None,
+            lines: vec![line(1, 0), line(2, 1), line(4, 2)],
+        };
+        map.apply(&mut debug_info);
+        assert_eq!(debug_info.lines, vec![line(1, 0), line(4, 2)]);
+    }
+}
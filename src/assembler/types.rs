@@ -13,6 +13,51 @@ pub struct LabelInfos {
 }
 pub type Globals = HashMap<String, LabelInfos>;
 
+/// Mapping between source lines and generated code.
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct DebugInfo {
+    /// Assembled file, if known.
+    pub file: Option<String>,
+    /// One entry per line generating code, in source order.
+    pub lines: Vec<LineInfo>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineInfo {
+    pub line: usize,
+    pub addr: u16,
+    pub size: u16,
+    /// `false` for data directives.
+    #[serde(default = "default_code")]
+    pub code: bool,
+}
+
+fn default_code() -> bool {
+    true
+}
+
+impl DebugInfo {
+    /// Address of the code generated by `line`, or by the first following
+    /// line generating code.
+    pub fn addr_of_line(&self, line: usize) -> Option<(usize, u16)> {
+        self.lines
+            .iter()
+            .filter(|l| l.line >= line)
+            .min_by_key(|l| l.line)
+            .map(|l| (l.line, l.addr))
+    }
+
+    /// Line which generated the word at `addr`.
+    pub fn line_of_addr(&self, addr: u16) -> Option<usize> {
+        self.lines
+            .iter()
+            .find(|l| l.addr <= addr && (addr - l.addr) < l.size)
+            .map(|l| l.line)
+    }
+}
+
 #[derive(Debug, Clone, PartialEq, Eq, Hash)]
 pub enum ParsedItem {
     Directive(Directive),
@@ -5,6 +5,7 @@ extern crate docopt;
 extern crate error_chain;
 #[cfg(feature = "bins")]
 extern crate rustc_serialize;
+#[cfg(feature = "bins")]
 extern crate serde_json;
 #[cfg(feature = "bins")]
 extern crate simplelog;
@@ -33,6 +34,7 @@ Options:
   --hex         Show in hexadecimal instead of binary.
   --remove-unused  Remove unused labels and associated code.
   --symbols <f>  Write the resolved symbols to this file.
+  --debug-info <f>  Write the line to address mapping to this file.
   <file>        File to use instead of stdin.
   -o <file>     File to use instead of stdout.
   -h --help     Show this screen.
@@ -47,6 +49,7 @@ struct Args {
     flag_hex: bool,
     flag_remove_unused: bool,
     flag_symbols: Option<String>,
+    flag_debug_info: Option<String>,
     arg_file: Option<String>,
     flag_o: Option<String>,
 }
@@ -65,36 +68,58 @@ quick_main!(|| -> assembler::Result<()> {
 
     let asm = {
         let mut asm = String::new();
-        let mut input = utils::get_input(args.arg_file).chain_err(||
+        let mut input = utils::get_input(args.arg_file.clone()).chain_err(||
             "input file opening"
         )?;
         input.read_to_string(&mut asm).chain_err(|| "input reading")?;
         asm
     };
 
-    let preprocessed = {
+    let (preprocessed, line_map) = {
         if args.flag_no_cpp {
-            asm
+            (asm, None)
+        } else if args.flag_debug_info.is_some() {
+            let (preprocessed, line_map) = assembler::preprocess_with_lines(&asm)?;
+            (preprocessed, Some(line_map))
         } else {
-            assembler::preprocess(&asm)?
+            (assembler::preprocess(&asm)?, None)
         }
     };
-    let ast = assembler::parse(&preprocessed)?;
+    // The lines of the items are only needed for the debug info
+    let (ast, lines) = if args.flag_debug_info.is_some() {
+        let located = assembler::parse_with_lines(&preprocessed)?;
+        let lines: Vec<usize> = located.iter().map(|&(line, _)| line).collect();
+        (located.into_iter().map(|(_, item)| item).collect(), Some(lines))
+    } else {
+        (assembler::parse(&preprocessed)?, None)
+    };
 
     if args.flag_ast {
         println!("{:?}", ast);
         return Ok(());
     }
 
-    let ast = if args.flag_remove_unused {
-        assembler::clean(ast)
-    } else {
-        assembler::print_unused(&ast);
-        ast
+    let (bin, symbols, mut debug_info) = match lines {
+        Some(lines) => {
+            if args.flag_remove_unused {
+                bail!("--debug-info can't be used with --remove-unused");
+            }
+            assembler::print_unused(&ast);
+            let located: Vec<_> = lines.into_iter().zip(ast).collect();
+            assembler::link_with_debug_info(&located)?
+        }
+        None => {
+            let ast = if args.flag_remove_unused {
+                assembler::clean(ast)
+            } else {
+                assembler::print_unused(&ast);
+                ast
+            };
+            let (bin, symbols) = assembler::link(&ast)?;
+            (bin, symbols, Default::default())
+        }
     };
 
-    let (bin, symbols) = assembler::link(&ast)?;
-
     let mut output = utils::get_output(args.flag_o).chain_err(||
         "Error while opening the output"
     )?;
@@ -112,14 +137,23 @@ quick_main!(|| -> assembler::Result<()> {
         write_symbols(path, &symbols)?;
     }
 
+    if let Some(path) = args.flag_debug_info {
+        if let Some(line_map) = line_map {
+            line_map.apply(&mut debug_info);
+        }
+        debug_info.file = args.arg_file;
+        write_debug_info(path, &debug_info)?;
+    }
+
     Ok(())
 });
 
 #[cfg(not(feature = "bins"))]
 quick_main!(|| -> assembler::Result<i32> {
-    "The feature \"bins\" must be activated to use this binary"
+    Err("The feature \"bins\" must be activated to use this binary".into())
 });
 
+#[cfg(feature = "bins")]
 fn write_symbols(path: String,
                  symbols: &assembler::types::Globals) -> assembler::Result<()> {
     let mut o = utils::get_output(Some(path))
@@ -127,3 +161,13 @@ fn write_symbols(path: String,
     serde_json::to_writer_pretty(&mut o, symbols).unwrap();
     Ok(())
 }
+
+#[cfg(feature = "bins")]
+fn write_debug_info(path: String,
+                    debug_info: &assembler::types::DebugInfo)
+                    -> assembler::Result<()> {
+    let mut o = utils::get_output(Some(path))
+                      .chain_err(|| "Error while opening the debug info file")?;
+    serde_json::to_writer_pretty(&mut o, debug_info).unwrap();
+    Ok(())
+}
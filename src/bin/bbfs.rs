@@ -0,0 +1,195 @@
+extern crate dcpu;
+#[cfg(feature = "bins")]
+extern crate docopt;
+#[cfg(feature = "bins")]
+extern crate rustc_serialize;
+#[cfg(feature = "bins")]
+extern crate serde_json;
+#[cfg(feature = "bins")]
+extern crate simplelog;
+
+#[macro_use]
+mod utils;
+
+#[cfg(feature = "bins")]
+use std::fs;
+#[cfg(feature = "bins")]
+use std::io::{Read, Write};
+#[cfg(feature = "bins")]
+use std::path::Path;
+
+#[cfg(feature = "bins")]
+use docopt::Docopt;
+
+#[cfg(feature = "bins")]
+use dcpu::byteorder::{LittleEndian, WriteBytesExt};
+#[cfg(feature = "bins")]
+use dcpu::emulator::bbfs::{EntryKind, Image};
+
+#[cfg(feature = "bins")]
+const USAGE: &'static str = "
+Build and inspect BBFS floppy images for the m35fd.
+
+Host files are read and written as little endian words, like the output of
+the assembler. Paths in the image are separated by `/`.
+
+Usage:
+  bbfs format <image> [--boot <file>]
+  bbfs build <image> <dir> [--boot <file>]
+  bbfs ls <image> [<path>]
+  bbfs extract <image> <path> [-o <file>]
+  bbfs add <image> <file> [<path>]
+  bbfs mkdir <image> <path>
+  bbfs boot <image> <file>
+  bbfs (--help | --version)
+
+Commands:
+  format             Write a blank formatted image.
+  build              Write a formatted image with the content of <dir>.
+  ls                 List a directory of the image, the root by default.
+  extract            Copy a file of the image to <file> or stdout.
+  add                Copy <file> to <path>, which defaults to its name in the
+                     root directory. An existing file is replaced.
+  mkdir              Create a directory in the image.
+  boot               Write the bootloader <file> to sector 0 and mark the
+                     image bootable.
+
+Options:
+  --boot <file>      Bootloader to write to sector 0.
+  -o <file>          File to use instead of stdout.
+  -h, --help         Show this message.
+  --version          Show the version of bbfs.
+";
+
+#[cfg(feature = "bins")]
+#[derive(RustcDecodable)]
+struct Args {
+    cmd_format: bool,
+    cmd_build: bool,
+    cmd_ls: bool,
+    cmd_extract: bool,
+    cmd_add: bool,
+    cmd_mkdir: bool,
+    cmd_boot: bool,
+    arg_image: String,
+    arg_dir: String,
+    arg_path: Option<String>,
+    arg_file: Option<String>,
+    flag_boot: Option<String>,
+    flag_o: Option<String>,
+}
+
+#[cfg(feature = "bins")]
+fn read_words<P: AsRef<Path>>(path: P) -> Result<Vec<u16>, String> {
+    let mut bytes = vec![];
+    try!(fs::File::open(&path)
+             .and_then(|mut f| f.read_to_end(&mut bytes))
+             .map_err(|e| format!("{}: {}", path.as_ref().display(), e)));
+    Ok(bytes.chunks(2)
+            .map(|b| b[0] as u16 | (*b.get(1).unwrap_or(&0) as u16) << 8)
+            .collect())
+}
+
+/// Copy the content of `dir` to `path` in the image.
+#[cfg(feature = "bins")]
+fn add_dir(image: &mut Image, dir: &Path, path: &str) -> Result<(), String> {
+    let mut entries = try!(fs::read_dir(dir)
+                               .and_then(|d| d.collect::<Result<Vec<_>, _>>())
+                               .map_err(|e| format!("{}: {}", dir.display(), e)));
+    entries.sort_by_key(|e| e.file_name());
+    for entry in entries {
+        let name = entry.file_name().to_string_lossy().into_owned();
+        let sub_path = format!("{}/{}", path, name);
+        if entry.path().is_dir() {
+            try!(image.mkdir(&sub_path).map_err(|e| e.to_string()));
+            try!(add_dir(image, &entry.path(), &sub_path));
+        } else {
+            let data = try!(read_words(entry.path()));
+            try!(image.write(&sub_path, &data).map_err(|e| e.to_string()));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "bins")]
+fn run(args: Args) -> Result<(), String> {
+    if args.cmd_format || args.cmd_build {
+        let mut image = Image::format();
+        if args.cmd_build {
+            try!(add_dir(&mut image, Path::new(&args.arg_dir), ""));
+        }
+        if let Some(ref boot) = args.flag_boot {
+            let code = try!(read_words(boot));
+            try!(image.set_bootloader(&code).map_err(|e| e.to_string()));
+        }
+        return image.save(&args.arg_image).map_err(|e| e.to_string());
+    }
+
+    let mut image = try!(Image::load(&args.arg_image).map_err(|e| e.to_string()));
+    let path = args.arg_path.unwrap_or_else(|| "/".into());
+    if args.cmd_ls {
+        let entries = try!(image.list(&path).map_err(|e| e.to_string()));
+        for entry in entries {
+            match entry.kind {
+                EntryKind::Directory => println!("{}/", entry.name),
+                EntryKind::File => println!("{:<17} {} words", entry.name, entry.size),
+            }
+        }
+    } else if args.cmd_extract {
+        let data = try!(image.read(&path).map_err(|e| e.to_string()));
+        let mut output = try!(utils::get_output(args.flag_o).map_err(|e| e.to_string()));
+        try!(output.write_all_items::<u16, LittleEndian>(&data)
+                   .and_then(|_| output.flush())
+                   .map_err(|e| e.to_string()));
+    } else {
+        if args.cmd_add {
+            let file = args.arg_file.unwrap();
+            let path = if path == "/" {
+                Path::new(&file)
+                    .file_name()
+                    .map(|n| n.to_string_lossy().into_owned())
+                    .unwrap_or(file.clone())
+            } else {
+                path
+            };
+            let data = try!(read_words(&file));
+            try!(image.write(&path, &data).map_err(|e| e.to_string()));
+        } else if args.cmd_mkdir {
+            try!(image.mkdir(&path).map_err(|e| e.to_string()));
+        } else if args.cmd_boot {
+            let code = try!(read_words(args.arg_file.unwrap()));
+            try!(image.set_bootloader(&code).map_err(|e| e.to_string()));
+        }
+        try!(image.save(&args.arg_image).map_err(|e| e.to_string()));
+    }
+    Ok(())
+}
+
+#[cfg(feature = "bins")]
+fn main_ret() -> i32 {
+    simplelog::TermLogger::init(simplelog::LogLevelFilter::Info,
+                                Default::default()).unwrap();
+
+    let version = option_env!("CARGO_PKG_VERSION").map(|s| s.into());
+    let args: Args = Docopt::new(USAGE)
+                            .map(|d| d.version(version))
+                            .and_then(|d| d.decode())
+                            .unwrap_or_else(|e| e.exit());
+
+    if let Err(e) = run(args) {
+        die!(1, "Error: {}", e);
+    }
+    0
+}
+
+#[cfg(not(feature = "bins"))]
+fn main_ret() -> i32 {
+    use std::io::Write;
+    writeln!(std::io::stderr(),
+             "The feature \"bins\" must be activated to use this binary").unwrap();
+    1
+}
+
+fn main() {
+    std::process::exit(main_ret());
+}
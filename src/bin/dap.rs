@@ -0,0 +1,695 @@
+extern crate dcpu;
+#[cfg(feature = "bins")]
+extern crate docopt;
+#[cfg(feature = "bins")]
+#[macro_use]
+extern crate serde_json;
+
+mod utils;
+
+#[cfg(feature = "bins")]
+use std::collections::HashMap;
+#[cfg(feature = "bins")]
+use std::fs;
+#[cfg(feature = "bins")]
+use std::io::{self, BufRead, Write};
+#[cfg(feature = "bins")]
+use std::num::Wrapping;
+#[cfg(feature = "bins")]
+use std::sync::mpsc;
+#[cfg(feature = "bins")]
+use std::thread;
+
+#[cfg(feature = "bins")]
+use docopt::Docopt;
+#[cfg(feature = "bins")]
+use serde_json::Value;
+
+#[cfg(feature = "bins")]
+use dcpu::assembler::types::{DebugInfo, Expression, Globals, Num};
+#[cfg(feature = "bins")]
+use dcpu::byteorder::{LittleEndian, ReadBytesExt};
+#[cfg(feature = "bins")]
+use dcpu::emulator::{cpu, debugger, Cpu, Debugger};
+#[cfg(feature = "bins")]
+use dcpu::emulator::debugger::Until;
+#[cfg(feature = "bins")]
+use dcpu::emulator::device::Device;
+#[cfg(feature = "bins")]
+use dcpu::types::Register;
+
+#[cfg(feature = "bins")]
+const USAGE: &'static str = "
+Debug adapter, speaking the Debug Adapter Protocol on stdin/stdout.
+
+Usage:
+  dap
+  dap (--help | --version)
+
+Options:
+  -h, --help         Show this message.
+  --version          Show the version of dap.
+
+The launch request accepts the following arguments:
+  program            The binary file to execute.
+  symbols            Symbol map file, defaults to <program>.sym.
+  debugInfo          Debug info file from `assembler --debug-info`,
+                     defaults to <program>.dbg.
+  devices            List of devices, as the emulator `--device` option.
+  stopOnEntry        Stop before the first instruction.
+  logLitterals       Print `LOG n` messages as `(char*)n`.
+";
+
+/// Number of instructions executed between two checks for new requests.
+#[cfg(feature = "bins")]
+const POLL_INTERVAL: u32 = 4096;
+#[cfg(feature = "bins")]
+const THREAD_ID: u64 = 1;
+#[cfg(feature = "bins")]
+const REGISTERS_REF: u64 = 1;
+#[cfg(feature = "bins")]
+const STACK_REF: u64 = 2;
+#[cfg(feature = "bins")]
+const DEVICES_REF: u64 = 3;
+/// The state of device `i` uses the reference `DEVICE_STATE_REF + i`.
+#[cfg(feature = "bins")]
+const DEVICE_STATE_REF: u64 = 16;
+/// Number of stack words shown in the variables view.
+#[cfg(feature = "bins")]
+const STACK_SIZE: u16 = 16;
+
+#[cfg(feature = "bins")]
+fn main_ret() -> i32 {
+    let version = option_env!("CARGO_PKG_VERSION").map(|s| s.into());
+    Docopt::new(USAGE)
+           .and_then(|d| d.version(version).parse())
+           .unwrap_or_else(|e| e.exit());
+
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let stdin = io::stdin();
+        let mut input = stdin.lock();
+        while let Some(msg) = read_message(&mut input) {
+            if tx.send(msg).is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut session = Session::new(io::stdout());
+    match session.serve(rx) {
+        Ok(()) => 0,
+        Err(e) => {
+            let _ = writeln!(io::stderr(), "Error while writing a message: {}", e);
+            1
+        }
+    }
+}
+
+
+#[cfg(not(feature = "bins"))]
+fn main_ret() -> i32 {
+    use std::io::Write;
+    writeln!(std::io::stderr(),
+             "The feature \"bins\" must be activated to use this binary").unwrap();
+    1
+}
+
+fn main() {
+    std::process::exit(main_ret());
+}
+
+/// Read one `Content-Length` framed message, `None` on EOF or invalid input.
+#[cfg(feature = "bins")]
+fn read_message<R: BufRead>(input: &mut R) -> Option<Value> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        match input.read_line(&mut line) {
+            Ok(0) | Err(_) => return None,
+            Ok(_) => (),
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        let mut parts = line.splitn(2, ':');
+        if let (Some("Content-Length"), Some(l)) = (parts.next(),
+                                                    parts.next()) {
+            len = l.trim().parse().ok();
+        }
+    }
+    let mut body = match len {
+        Some(len) => vec![0; len],
+        None => return None,
+    };
+    if input.read_exact(&mut body).is_err() {
+        return None;
+    }
+    serde_json::from_slice(&body).ok()
+}
+
+#[cfg(feature = "bins")]
+struct Session<W: Write> {
+    /// Where the responses and events are written.
+    output: W,
+    debugger: Option<Debugger>,
+    symbols: Globals,
+    debug_info: DebugInfo,
+    /// Breakpoint addresses set for each source path.
+    breakpoints: HashMap<String, Vec<u16>>,
+    stop_on_entry: bool,
+    /// Where the execution stops if it is running.
+    running: Option<Until>,
+    seq: u64,
+}
+
+#[cfg(feature = "bins")]
+impl<W: Write> Session<W> {
+    fn new(output: W) -> Session<W> {
+        Session {
+            output: output,
+            debugger: None,
+            symbols: Globals::default(),
+            debug_info: DebugInfo::default(),
+            breakpoints: HashMap::new(),
+            stop_on_entry: false,
+            running: None,
+            seq: 0,
+        }
+    }
+
+    /// Handle the requests until the client disconnects or the output is
+    /// closed.
+    fn serve(&mut self, requests: mpsc::Receiver<Value>) -> io::Result<()> {
+        loop {
+            let msg = if self.running.is_some() {
+                match requests.try_recv() {
+                    Ok(msg) => Some(msg),
+                    Err(mpsc::TryRecvError::Empty) => None,
+                    Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+                }
+            } else {
+                match requests.recv() {
+                    Ok(msg) => Some(msg),
+                    Err(_) => return Ok(()),
+                }
+            };
+            if let Some(msg) = msg {
+                if !try!(self.handle(&msg)) {
+                    return Ok(());
+                }
+            }
+            if self.running.is_some() {
+                try!(self.run_slice());
+            }
+        }
+    }
+
+    /// Handle one message, returning `false` when the session is over.
+    fn handle(&mut self, msg: &Value) -> io::Result<bool> {
+        if msg["type"].as_str() != Some("request") {
+            return Ok(true);
+        }
+        let command = msg["command"].as_str().unwrap_or("");
+        let args = &msg["arguments"];
+
+        if self.debugger.is_none() &&
+           !["initialize", "launch", "disconnect"].contains(&command) {
+            try!(self.respond(msg, Err("No program launched".into())));
+            return Ok(true);
+        }
+
+        let res = match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsReadMemoryRequest": true,
+                "supportsRestartRequest": true,
+            })),
+            "launch" => self.launch(args),
+            "setBreakpoints" => Ok(self.set_breakpoints(args)),
+            "configurationDone" => Ok(Value::Null),
+            "threads" => Ok(json!({
+                "threads": [{"id": THREAD_ID, "name": "DCPU"}],
+            })),
+            "stackTrace" => Ok(self.stack_trace()),
+            "scopes" => Ok(json!({
+                "scopes": [
+                    {"name": "Registers",
+                     "variablesReference": REGISTERS_REF,
+                     "expensive": false},
+                    {"name": "Stack",
+                     "variablesReference": STACK_REF,
+                     "expensive": false},
+                    {"name": "Devices",
+                     "variablesReference": DEVICES_REF,
+                     "expensive": false},
+                ],
+            })),
+            "variables" => Ok(self.variables(args)),
+            "continue" => Ok(json!({"allThreadsContinued": true})),
+            "next" | "stepIn" | "stepOut" | "pause" => Ok(Value::Null),
+            "readMemory" => self.read_memory(args),
+            "restart" => self.debugger
+                            .as_mut()
+                            .unwrap()
+                            .power_cycle()
+                            .map(|_| Value::Null)
+                            .map_err(|e| e.to_string()),
+            "disconnect" => {
+                try!(self.respond(msg, Ok(Value::Null)));
+                return Ok(false);
+            }
+            _ => Err(format!("Unsupported request \"{}\"", command)),
+        };
+        let success = res.is_ok();
+        try!(self.respond(msg, res));
+        if !success {
+            return Ok(true);
+        }
+
+        match command {
+            // Breakpoints can only be set once the debug info is loaded
+            "launch" => try!(self.event("initialized", Value::Null)),
+            "configurationDone" | "restart" => if self.stop_on_entry {
+                try!(self.stopped("entry", None));
+            } else {
+                self.running = Some(Until::Never);
+            },
+            "continue" => self.running = Some(Until::Never),
+            "pause" => if self.running.is_some() {
+                self.running = None;
+                try!(self.stopped("pause", None));
+            },
+            "next" => {
+                self.running = Some(self.debugger.as_ref().unwrap().step_over_until())
+            }
+            "stepIn" => self.running = Some(Until::Step),
+            "stepOut" => {
+                self.running = Some(self.debugger.as_ref().unwrap().step_out_until())
+            }
+            _ => (),
+        }
+        Ok(true)
+    }
+
+    fn launch(&mut self, args: &Value) -> Result<Value, String> {
+        let program = try!(args["program"]
+                               .as_str()
+                               .ok_or("Missing \"program\" argument"));
+        let rom: Vec<u16> = {
+            let mut input = try!(utils::get_input(Some(program.into())).map_err(|e| {
+                format!("Error while opening \"{}\": {}", program, e)
+            }));
+            input.iter_items::<u16, LittleEndian>().collect()
+        };
+
+        let mut cpu = Cpu::default();
+        cpu.load(&rom, 0);
+
+        let mut devices: Vec<Box<Device>> = vec![];
+        if let Some(devs) = args["devices"].as_array() {
+            for d in devs {
+                let d = try!(d.as_str().ok_or("Invalid device"));
+                try!(utils::add_device(&mut devices, d));
+            }
+        }
+
+        let symbols_path = args["symbols"]
+                               .as_str()
+                               .map(|s| s.to_string())
+                               .unwrap_or(format!("{}.sym", program));
+        self.symbols = utils::get_input(Some(symbols_path))
+                             .ok()
+                             .and_then(|i| serde_json::from_reader(i).ok())
+                             .unwrap_or_default();
+        let debug_info_path = args["debugInfo"]
+                                  .as_str()
+                                  .map(|s| s.to_string())
+                                  .unwrap_or(format!("{}.dbg", program));
+        self.debug_info = utils::get_debug_info(&debug_info_path).unwrap_or_default();
+        // The editor uses absolute paths
+        self.debug_info.file = self.debug_info.file.take().map(|f| canonical(&f));
+
+        let mut debugger = Debugger::new(cpu, devices);
+        debugger.symbols(self.symbols.clone());
+        debugger.rom(rom);
+        debugger.log_litterals(args["logLitterals"].as_bool().unwrap_or(false));
+        self.debugger = Some(debugger);
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        Ok(Value::Null)
+    }
+
+    fn set_breakpoints(&mut self, args: &Value) -> Value {
+        let path = args["source"]["path"].as_str().unwrap_or("").to_string();
+        let lines = args["breakpoints"].as_array().cloned().unwrap_or_default();
+        if self.debug_info.file != Some(canonical(&path)) {
+            let results: Vec<Value> = lines.iter().map(|b| json!({
+                "verified": false,
+                "line": b["line"],
+                "message": "No debug info for this file",
+            })).collect();
+            return json!({"breakpoints": results});
+        }
+
+        let debugger = self.debugger.as_mut().unwrap();
+        for addr in self.breakpoints.remove(&path).unwrap_or_default() {
+            debugger.delete_breakpoint_at(addr);
+        }
+
+        let mut addresses = vec![];
+        let mut results = vec![];
+        for b in lines {
+            let line = b["line"].as_u64().unwrap_or(0) as usize;
+            match self.debug_info.addr_of_line(line) {
+                Some((line, addr)) => {
+                    debugger.add_breakpoint(addr, Expression::Num(Num::U(addr)));
+                    addresses.push(addr);
+                    results.push(json!({"verified": true, "line": line}));
+                }
+                None => results.push(json!({
+                    "verified": false,
+                    "line": line,
+                    "message": "No code at this line",
+                })),
+            }
+        }
+        self.breakpoints.insert(path, addresses);
+        json!({"breakpoints": results})
+    }
+
+    fn stack_trace(&self) -> Value {
+        let pc = self.debugger.as_ref().unwrap().cpu.pc.0;
+        let mut frame = json!({
+            "id": 0,
+            "name": self.symbol_of(pc),
+            "line": 0,
+            "column": 0,
+            "instructionPointerReference": format!("0x{:0>4x}", pc),
+        });
+        if let Some(line) = self.debug_info.line_of_addr(pc) {
+            frame["line"] = json!(line);
+            frame["column"] = json!(1);
+            if let Some(ref file) = self.debug_info.file {
+                frame["source"] = json!({"path": file});
+            }
+        }
+        json!({"stackFrames": [frame], "totalFrames": 1})
+    }
+
+    fn variables(&self, args: &Value) -> Value {
+        let debugger = self.debugger.as_ref().unwrap();
+        let cpu = &debugger.cpu;
+        let mut vars = vec![];
+        match args["variablesReference"].as_u64() {
+            Some(REGISTERS_REF) => {
+                let regs = [Register::A, Register::B, Register::C,
+                            Register::X, Register::Y, Register::Z,
+                            Register::I, Register::J];
+                for r in &regs {
+                    vars.push(variable(&format!("{:?}", r), cpu.registers[*r]));
+                }
+                vars.push(variable("PC", cpu.pc.0));
+                vars.push(variable("SP", cpu.sp.0));
+                vars.push(variable("EX", cpu.ex));
+                vars.push(variable("IA", cpu.ia));
+            }
+            Some(STACK_REF) => {
+                let mut addr = cpu.sp;
+                // The stack grows down from 0xffff
+                while addr.0 != 0xffff && addr - cpu.sp < Wrapping(STACK_SIZE) {
+                    vars.push(variable(&format!("[0x{:0>4x}]", addr),
+                                       cpu.ram[addr.0]));
+                    addr += Wrapping(1);
+                }
+            }
+            Some(DEVICES_REF) => {
+                for (i, d) in debugger.devices().iter().enumerate() {
+                    vars.push(json!({
+                        "name": format!("{}", i),
+                        "value": d.inspect().name,
+                        "variablesReference": DEVICE_STATE_REF + i as u64,
+                    }));
+                }
+            }
+            Some(r) if r >= DEVICE_STATE_REF => {
+                let i = (r - DEVICE_STATE_REF) as usize;
+                if let Some(d) = debugger.devices().get(i) {
+                    let info = d.inspect();
+                    let mut state = vec![
+                        ("hardware id".into(),
+                         format!("0x{:0>8x}", info.hardware_id)),
+                        ("hardware version".into(),
+                         format!("0x{:0>4x}", info.hardware_version)),
+                        ("manufacturer".into(),
+                         format!("0x{:0>8x}", info.manufacturer)),
+                    ];
+                    state.extend(info.state);
+                    for (key, value) in state {
+                        vars.push(json!({
+                            "name": key,
+                            "value": value,
+                            "variablesReference": 0,
+                        }));
+                    }
+                }
+            }
+            _ => (),
+        }
+        json!({"variables": vars})
+    }
+
+    /// `memoryReference` is a word address, `offset` and `count` are in
+    /// bytes, the offset being even to start on a word. Words are sent in
+    /// little-endian order.
+    fn read_memory(&self, args: &Value) -> Result<Value, String> {
+        let reference = args["memoryReference"].as_str().unwrap_or("");
+        let base = try!(utils::parse_number(reference).ok_or_else(|| {
+            format!("Invalid memory reference \"{}\"", reference)
+        }));
+        let offset = args["offset"].as_i64().unwrap_or(0);
+        if offset % 2 != 0 {
+            return Err(format!("Odd offset {}, the memory is read by words", offset));
+        }
+        let count = args["count"].as_u64().unwrap_or(0);
+        let start = (base as i64) * 2 + offset;
+        let end = ::std::cmp::min(start + count as i64, 0x20000);
+        let start = ::std::cmp::max(start, 0);
+
+        let ram = &self.debugger.as_ref().unwrap().cpu.ram;
+        let bytes: Vec<u8> = (start..end).map(|b| {
+            let word = ram[(b / 2) as u16];
+            if b % 2 == 0 { word as u8 } else { (word >> 8) as u8 }
+        }).collect();
+        Ok(json!({
+            "address": format!("0x{:x}", start / 2),
+            "data": base64(&bytes),
+            "unreadableBytes": count as i64 - bytes.len() as i64,
+        }))
+    }
+
+    /// Run a bounded number of instructions, so that new requests like
+    /// `pause` are handled while a continue or a step is in progress.
+    fn run_slice(&mut self) -> io::Result<()> {
+        let until = self.running.unwrap();
+        let res = self.debugger
+                      .as_mut()
+                      .unwrap()
+                      .run_until(until, POLL_INTERVAL);
+        try!(self.flush_logs());
+        match res {
+            Ok(false) => Ok(()),
+            Ok(true) => {
+                self.running = None;
+                self.stopped("step", None)
+            }
+            Err(e) => {
+                self.running = None;
+                self.stop_on_error(&e)
+            }
+        }
+    }
+
+    fn stop_on_error(&mut self, e: &debugger::Error) -> io::Result<()> {
+        match *e.kind() {
+            debugger::ErrorKind::Breakpoint(..) => self.stopped("breakpoint", None),
+            debugger::ErrorKind::Watchpoint(..) =>
+                self.stopped("data breakpoint", Some(e.to_string())),
+            debugger::ErrorKind::Cpu(cpu::ErrorKind::Break(_)) =>
+                self.stopped("breakpoint", Some(e.to_string())),
+            _ => self.stopped("exception", Some(e.to_string())),
+        }
+    }
+
+    fn stopped(&mut self, reason: &str, text: Option<String>) -> io::Result<()> {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": THREAD_ID,
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["text"] = json!(text);
+        }
+        self.event("stopped", body)
+    }
+
+    fn flush_logs(&mut self) -> io::Result<()> {
+        let logs = self.debugger.as_mut().unwrap().take_logs();
+        for msg in logs {
+            try!(self.event("output",
+                            json!({"category": "stdout",
+                                   "output": format!("LOG {}\n", msg)})));
+        }
+        Ok(())
+    }
+
+    /// Nearest symbol before `addr`.
+    fn symbol_of(&self, addr: u16) -> String {
+        self.symbols
+            .iter()
+            .filter(|&(_, infos)| infos.addr <= addr)
+            .max_by_key(|&(_, infos)| infos.addr)
+            .map(|(name, infos)| if infos.addr == addr {
+                name.clone()
+            } else {
+                format!("{}+0x{:x}", name, addr - infos.addr)
+            })
+            .unwrap_or(format!("0x{:0>4x}", addr))
+    }
+
+    fn respond(&mut self, request: &Value, res: Result<Value, String>) -> io::Result<()> {
+        let mut msg = json!({
+            "type": "response",
+            "request_seq": request["seq"],
+            "command": request["command"],
+            "success": res.is_ok(),
+        });
+        match res {
+            Ok(Value::Null) => (),
+            Ok(body) => msg["body"] = body,
+            Err(e) => msg["message"] = json!(e),
+        }
+        self.send(msg)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut msg = json!({"type": "event", "event": event});
+        if !body.is_null() {
+            msg["body"] = body;
+        }
+        self.send(msg)
+    }
+
+    /// Write `msg` with its `Content-Length` header.
+    fn send(&mut self, mut msg: Value) -> io::Result<()> {
+        self.seq += 1;
+        msg["seq"] = json!(self.seq);
+        let data = try!(serde_json::to_vec(&msg).map_err(|e| {
+            io::Error::new(io::ErrorKind::InvalidData, e)
+        }));
+        try!(write!(self.output, "Content-Length: {}\r\n\r\n", data.len()));
+        try!(self.output.write_all(&data));
+        self.output.flush()
+    }
+}
+
+/// Absolute path of `path` if it exists.
+#[cfg(feature = "bins")]
+fn canonical(path: &str) -> String {
+    fs::canonicalize(path)
+        .map(|p| p.to_string_lossy().into_owned())
+        .unwrap_or(path.into())
+}
+
+#[cfg(feature = "bins")]
+fn variable(name: &str, value: u16) -> Value {
+    json!({
+        "name": name,
+        "value": format!("0x{:0>4x}", value),
+        "variablesReference": 0,
+        "memoryReference": format!("0x{:0>4x}", value),
+    })
+}
+
+#[cfg(feature = "bins")]
+fn base64(data: &[u8]) -> String {
+    const CHARS: &'static [u8] =
+        b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut res = String::new();
+    for chunk in data.chunks(3) {
+        let n = (chunk[0] as u32) << 16 |
+                (*chunk.get(1).unwrap_or(&0) as u32) << 8 |
+                *chunk.get(2).unwrap_or(&0) as u32;
+        for i in 0..4 {
+            if i <= chunk.len() {
+                res.push(CHARS[(n >> (18 - 6 * i) & 0x3f) as usize] as char);
+            } else {
+                res.push('=');
+            }
+        }
+    }
+    res
+}
+
+#[cfg(all(test, feature = "bins"))]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn test_base64() {
+        assert_eq!(base64(b""), "");
+        assert_eq!(base64(b"f"), "Zg==");
+        assert_eq!(base64(b"fo"), "Zm8=");
+        assert_eq!(base64(b"foo"), "Zm9v");
+        assert_eq!(base64(b"foob"), "Zm9vYg==");
+        assert_eq!(base64(&[0xff, 0xfe]), "//4=");
+    }
+
+    #[test]
+    fn test_framing() {
+        let mut session = Session::new(vec![]);
+        session.event("initialized", Value::Null).unwrap();
+        session.event("output", json!({"output": "é"})).unwrap();
+        let mut input = Cursor::new(session.output);
+        assert_eq!(read_message(&mut input),
+                   Some(json!({"type": "event", "event": "initialized", "seq": 1})));
+        assert_eq!(read_message(&mut input).unwrap()["body"]["output"], "é");
+        assert_eq!(read_message(&mut input), None);
+
+        let mut input = Cursor::new(&b"Content-Type: json\r\n\r\n{}"[..]);
+        assert_eq!(read_message(&mut input), None);
+        let mut input = Cursor::new(&b"Content-Length: 8\r\n\r\n{}"[..]);
+        assert_eq!(read_message(&mut input), None);
+    }
+
+    #[test]
+    fn test_read_memory() {
+        let mut cpu = Cpu::default();
+        cpu.load(&[0x1234, 0x5678], 0x100);
+        let mut session = Session::new(vec![]);
+        session.debugger = Some(Debugger::new(cpu, vec![]));
+
+        let res = session.read_memory(&json!({
+            "memoryReference": "0x100",
+            "offset": 2,
+            "count": 2,
+        })).unwrap();
+        assert_eq!(res["address"], "0x101");
+        assert_eq!(res["data"], base64(&[0x78, 0x56]));
+        assert_eq!(res["unreadableBytes"], 0);
+
+        let res = session.read_memory(&json!({
+            "memoryReference": "0xffff",
+            "count": 4,
+        })).unwrap();
+        assert_eq!(res["unreadableBytes"], 2);
+        assert!(session.read_memory(&json!({
+            "memoryReference": "0x100",
+            "offset": 1,
+            "count": 2,
+        })).is_err());
+        assert!(session.read_memory(&json!({"memoryReference": "here"})).is_err());
+    }
+}
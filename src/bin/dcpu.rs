@@ -0,0 +1,231 @@
+extern crate dcpu;
+#[cfg(feature = "bins")]
+extern crate docopt;
+#[cfg(feature = "bins")]
+extern crate rustc_serialize;
+#[cfg(feature = "bins")]
+extern crate serde_json;
+#[cfg(feature = "bins")]
+extern crate simplelog;
+
+#[macro_use]
+mod utils;
+
+#[cfg(feature = "bins")]
+use std::io::Write;
+#[cfg(feature = "bins")]
+use std::path::Path;
+
+#[cfg(feature = "bins")]
+use docopt::Docopt;
+
+#[cfg(feature = "bins")]
+use dcpu::assembler::types::{DebugInfo, Globals};
+#[cfg(feature = "bins")]
+use dcpu::emulator::ComputerBuilder;
+#[cfg(feature = "bins")]
+use dcpu::emulator::coverage::Coverage;
+#[cfg(feature = "bins")]
+use dcpu::emulator::machine::Machine;
+#[cfg(feature = "bins")]
+use dcpu::emulator::tester::{self, Runner};
+
+#[cfg(feature = "bins")]
+const USAGE: &'static str = "
+Tools for DCPU programs.
+
+`dcpu test` runs the unit tests of an assembled program. Each label starting
+with the test prefix is called with `JSR` on a freshly reset machine, after
+the setup label if it exists. A test passes when it returns or executes
+`BRK 0`, and fails on `BRK <n>` with another message, `HLT`, an emulation
+error or when it runs for too long.
+
+Usage:
+  dcpu test [options] [--load <fixture>]... <file>
+  dcpu (--help | --version)
+
+Options:
+  <file>             The binary file to test, loaded at address 0.
+  --symbols <s>      Symbol map file, defaults to <file>.sym.
+  --prefix <p>       Prefix of the test labels [default: test_].
+  --setup <label>    Label called before each test [default: setup].
+  --filter <s>       Only run the tests containing <s>.
+  --load <fixture>   Load the binary file <addr>:<path> in ram before the
+                     tests. Can be repeated.
+  --machine <m>      JSON machine description with the ROMs and devices to
+                     use, <file> is loaded after its ROMs.
+  --max-ticks <n>    Fail the tests running longer than this
+                     [default: 1000000].
+  --log-litterals    Print the LOG values of failed tests as `(char*)n`.
+  --coverage <file>  Write the number of words executed by the tests between
+                     each label to this file.
+  --lcov <file>      Write the lines and IF branches executed by the tests in
+                     the lcov format. Needs --debug-info.
+  --debug-info <f>   Line mapping file written by the assembler.
+  -h, --help         Show this message.
+  --version          Show the version of dcpu.
+";
+
+#[cfg(feature = "bins")]
+#[derive(Debug, RustcDecodable)]
+struct Args {
+    arg_file: String,
+    flag_symbols: Option<String>,
+    flag_prefix: String,
+    flag_setup: String,
+    flag_filter: Option<String>,
+    flag_load: Vec<String>,
+    flag_machine: Option<String>,
+    flag_max_ticks: u64,
+    flag_log_litterals: bool,
+    flag_coverage: Option<String>,
+    flag_lcov: Option<String>,
+    flag_debug_info: Option<String>,
+}
+
+#[cfg(feature = "bins")]
+fn main_ret() -> i32 {
+    simplelog::TermLogger::init(simplelog::LogLevelFilter::Info,
+                                Default::default()).unwrap();
+
+    let version = option_env!("CARGO_PKG_VERSION").map(|s| s.into());
+    let args: Args = Docopt::new(USAGE)
+                            .map(|d| d.version(version))
+                            .and_then(|d| d.decode())
+                            .unwrap_or_else(|e| e.exit());
+
+    let symbols_path = args.flag_symbols
+                           .clone()
+                           .unwrap_or(format!("{}.sym", args.arg_file));
+    let symbols: Globals = match utils::get_input(Some(symbols_path.clone())) {
+        Ok(i) => match serde_json::from_reader(i) {
+            Ok(symbols) => symbols,
+            Err(e) => die!(1, "Error while decoding the symbols: {}", e),
+        },
+        Err(e) => die!(1, "Error while reading \"{}\": {}", symbols_path, e),
+    };
+
+    let mut builder = ComputerBuilder::new();
+    if let Some(ref path) = args.flag_machine {
+        let machine: Machine = match utils::get_input(Some(path.clone())) {
+            Ok(i) => match serde_json::from_reader(i) {
+                Ok(m) => m,
+                Err(e) => die!(1, "Error while decoding the machine: {}", e),
+            },
+            Err(e) => die!(1, "Error while reading \"{}\": {}", path, e),
+        };
+        let base = Path::new(path).parent().unwrap_or(Path::new(""));
+        builder = match builder.machine(&machine, base) {
+            Ok(b) => b,
+            Err(e) => die!(1, "Error while building the machine: {}", e),
+        };
+    }
+    builder = match builder.rom_file(&args.arg_file, 0) {
+        Ok(b) => b,
+        Err(e) => die!(1, "Error while loading \"{}\": {}", args.arg_file, e),
+    };
+    // The coverage summary stops at the end of the program
+    let program_end = builder.image().len();
+    for fixture in &args.flag_load {
+        let mut parts = fixture.splitn(2, ':');
+        let addr = parts.next().and_then(utils::parse_number);
+        builder = match (addr, parts.next()) {
+            (Some(addr), Some(path)) => match builder.rom_file(path, addr) {
+                Ok(b) => b,
+                Err(e) => die!(1, "Error while loading \"{}\": {}", path, e),
+            },
+            _ => die!(1, "Invalid fixture \"{}\", expected <addr>:<path>", fixture),
+        };
+    }
+    let debug_info: DebugInfo = match (args.flag_lcov.as_ref(),
+                                       args.flag_debug_info.as_ref()) {
+        (Some(_), Some(path)) => match utils::get_debug_info(path) {
+            Ok(d) => d,
+            Err(e) => die!(1, "{}", e),
+        },
+        (Some(_), None) => die!(1, "--lcov needs the --debug-info of the program"),
+        (None, _) => DebugInfo::default(),
+    };
+
+    let image = builder.image();
+    let mut computer = builder.build();
+    if args.flag_coverage.is_some() || args.flag_lcov.is_some() {
+        computer.coverage(Some(Coverage::new()));
+    }
+
+    let tests: Vec<(String, u16)> =
+        tester::find_tests(&symbols, &args.flag_prefix)
+            .into_iter()
+            .filter(|&(ref name, _)| {
+                args.flag_filter.as_ref().map_or(true, |f| name.contains(f))
+            })
+            .collect();
+    let mut runner = Runner::new(image.clone());
+    runner.setup = symbols.get(&args.flag_setup).map(|infos| infos.addr);
+    runner.max_ticks = args.flag_max_ticks;
+    runner.log_litterals = args.flag_log_litterals;
+
+    let nb_run = tests.len();
+    println!("running {} tests", nb_run);
+    let mut failures = vec![];
+    for (name, addr) in tests {
+        let mut logs = vec![];
+        match runner.run(&mut computer, addr, &mut logs) {
+            Ok(()) => println!("test {} ... ok", name),
+            Err(e) => {
+                println!("test {} ... FAILED ({})", name, e);
+                failures.push((name, logs));
+            }
+        }
+    }
+
+    for &(ref name, ref logs) in &failures {
+        if !logs.is_empty() {
+            println!("\n---- {} logs ----", name);
+            for msg in logs {
+                println!("LOG {}", msg);
+            }
+        }
+    }
+    let nb_filtered = symbols.keys()
+                             .filter(|name| name.starts_with(&args.flag_prefix))
+                             .count() - nb_run;
+    println!("\ntest result: {}. {} passed; {} failed; {} filtered out",
+             if failures.is_empty() { "ok" } else { "FAILED" },
+             nb_run - failures.len(),
+             failures.len(),
+             nb_filtered);
+
+    if let Some(coverage) = computer.coverage(None) {
+        if let Some(ref path) = args.flag_coverage {
+            let res = utils::get_output(Some(path.clone())).and_then(|mut o| {
+                coverage.write_summary(&mut o, &symbols, &image[..program_end])
+            });
+            if let Err(e) = res {
+                die!(1, "Error while writing the coverage: {}", e);
+            }
+        }
+        if let Some(ref path) = args.flag_lcov {
+            let res = utils::get_output(Some(path.clone())).and_then(|mut o| {
+                coverage.write_lcov(&mut o, &debug_info, &symbols, &image)
+            });
+            if let Err(e) = res {
+                die!(1, "Error while writing the lcov file: {}", e);
+            }
+        }
+    }
+
+    if failures.is_empty() { 0 } else { 1 }
+}
+
+#[cfg(not(feature = "bins"))]
+fn main_ret() -> i32 {
+    use std::io::Write;
+    writeln!(std::io::stderr(),
+             "The feature \"bins\" must be activated to use this binary").unwrap();
+    1
+}
+
+fn main() {
+    std::process::exit(main_ret());
+}
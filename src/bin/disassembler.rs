@@ -5,6 +5,8 @@ extern crate log;
 #[cfg(feature = "bins")]
 extern crate rustc_serialize;
 #[cfg(feature = "bins")]
+extern crate serde_json;
+#[cfg(feature = "bins")]
 extern crate simplelog;
 
 #[macro_use]
@@ -72,7 +74,10 @@ fn main_ret() -> i32 {
 
 #[cfg(not(feature = "bins"))]
 fn main_ret() -> i32 {
-    "The feature \"bins\" must be activated to use this binary"
+    use std::io::Write;
+    writeln!(std::io::stderr(),
+             "The feature \"bins\" must be activated to use this binary").unwrap();
+    1
 }
 
 fn main() {
@@ -5,6 +5,8 @@ extern crate docopt;
 extern crate log;
 #[cfg(feature = "bins")]
 extern crate rustc_serialize;
+#[cfg(feature = "bins")]
+#[macro_use]
 extern crate serde_json;
 #[cfg(feature = "bins")]
 extern crate simplelog;
@@ -13,30 +15,112 @@ extern crate simplelog;
 mod utils;
 
 use std::{time, thread};
+use std::fs::File;
 use std::io::prelude::*;
+use std::io::BufWriter;
+use std::path::Path;
 use std::result;
 
 #[cfg(feature = "bins")]
 use docopt::Docopt;
 
-use dcpu::assembler::types::Globals;
+use dcpu::assembler::types::{DebugInfo, Globals};
 use dcpu::byteorder::{LittleEndian, ReadBytesExt};
-use dcpu::emulator::{Cpu, Computer, Debugger};
-use dcpu::emulator::device::*;
+use dcpu::emulator::{Computer, ComputerBuilder, Debugger, ExitConditions,
+                     ExitReason};
+use dcpu::emulator::debugger::{self, gdb};
+#[cfg(feature = "debugger-tui")]
+use dcpu::emulator::debugger::tui;
+use dcpu::emulator::bbfs;
+use dcpu::emulator::coverage::Coverage;
+use dcpu::emulator::device::bbos;
+use dcpu::emulator::golden::{self, Reference};
+use dcpu::emulator::machine::{DeviceDescription, Machine};
+use dcpu::emulator::profiler::{self, Profiler};
+use dcpu::emulator::trace::{self, Tracer};
 
 #[cfg(feature = "bins")]
 const USAGE: &'static str = "
 Usage:
-  emulator [options] [(-d <device>)...] [<file>]
+  emulator [options] [--ex <cmd>]... [(-d <device>)...] [--floppy <spec>]... [<file>]
+  emulator --headless [options] [--until <addr>]... [--until-log <n>]... [--dump <range>]... [--expect-screen <ref>]... [(-d <device>)...] [--floppy <spec>]... [<file>]
   emulator (--help | --version)
 
 Options:
-  <file>             The binary file to execute.
+  <file>             The binary file to execute, loaded at address 0.
+  --machine <m>      JSON machine description with the ROMs to load and the
+                     devices to plug before those given with -d.
   --tps              Print the number of ticks by second
   --limit            Try to limit the tick rate to 100_000/s
-  -d, --device       clock, keyscreen or m35fd(=(<floppy>|empty))?.
+  -d, --device       clock, spc2000, keyscreen, termscreen(=pixels,256)?,
+                     recorder=<file.gif|frame{}.png>,
+                     sped3(=<file.gif|frame{}.png>)?, hic(=<n>)?,
+                     m35fd(=(<floppy>(,<option>)*|empty))?,
+                     hmd2043(=(<media>(,wb)?|empty))?,
+                     m525hd(=<image>(,wb)?)? or bbos.
+  --floppy <spec>    Plug a m35fd after the -d devices, <spec> being
+                     <floppy>(,<option>)* with the options ro for a
+                     write-protected floppy, wb to save the written sectors
+                     to the image and create to create a blank image if
+                     needed. Same as -d m35fd=<spec>.
+  --boot             Load the boot sector of the first bootable m35fd at
+                     address 0 instead of reading <file>, like BBOS. The
+                     BBOS services are also available if the bbos device
+                     is plugged.
   --debugger         Launches the debugger.
-  --symbols <s>      Symbol map file (debugger only).
+  --tui              Launches the full-screen debugger.
+  --gdb <port>       Launches the debugger as a GDB remote stub listening on
+                     localhost:<port>.
+  --ex <cmd>         Execute this debugger command before the prompt. Can
+                     be repeated.
+  --batch            Exit after the --ex commands instead of showing the
+                     prompt. The exit code is 1 if a command failed.
+  --headless         Run without display until an exit condition, then print
+                     a JSON report with the registers, the dumped memory and
+                     the LOG values. The exit code tells what happened:
+                     0 for HLT with an empty interrupt queue, 2 for BRK, 3
+                     for the tick limit, 4 for --until and --until-log, 5
+                     for an error and 6 if a screen differs from its
+                     reference.
+  --max-ticks <n>    Stop after <n> ticks (headless only).
+  --until <addr>     Stop when PC reaches this address or label (headless
+                     only). Can be repeated.
+  --until-log <n>    Stop after a LOG of this value (headless only). Can be
+                     repeated.
+  --expect-screen <ref>  Compare the screen of the first LEM1802 with this
+                     reference when the emulation stops (headless only).
+                     PNG files are compared pixel by pixel and a diff
+                     image is written to <ref>.diff.png on mismatch, other
+                     files are compared to the text of the screen. Can be
+                     repeated.
+  --update-screens   Write the --expect-screen references instead of
+                     comparing them.
+  --screenshot <file>  Write the last frame of the first recorder screen to
+                     this PNG file when the emulation stops (headless
+                     only).
+  --dump <range>     Add the memory range <addr>:<length> to the report
+                     (headless only). Can be repeated.
+  --report <file>    Write the report to this file instead of stdout.
+  --trace <file>     Write each executed instruction with the registers and
+                     the memory it changed to this file.
+  --trace-format <f>  text or binary [default: text].
+  --profile <file>   Write the cycles spent in each function and instruction
+                     to this file when the emulation stops (headless
+                     only).
+  --flamegraph <file>  Write the cycles by call stack in the folded format
+                     of flamegraph tools when the emulation stops (headless
+                     only).
+  --sample <n>       Profile by sampling the call stack every <n> ticks
+                     instead of counting every instruction.
+  --coverage <file>  Write the number of executed words between each label
+                     to this file when the emulation stops (headless
+                     only).
+  --lcov <file>      Write the executed lines and IF branches in the lcov
+                     format when the emulation stops (headless only). Needs
+                     --debug-info.
+  --debug-info <f>   Line mapping file written by the assembler.
+  --symbols <s>      Symbol map file (debugger, headless, trace and profile
+                     only).
   --log-litterals    When a `LOG n` is triggered, print
                      `(char*)n`.
   --debug-history <file>   Use this file for the debugger history
@@ -49,13 +133,37 @@ Options:
 #[derive(Debug, RustcDecodable)]
 struct Args {
     arg_device: Option<Vec<String>>,
+    flag_floppy: Vec<String>,
+    flag_boot: bool,
     arg_file: Option<String>,
+    flag_machine: Option<String>,
     flag_log_litterals: bool,
     flag_debugger: bool,
+    flag_tui: bool,
+    flag_gdb: Option<u16>,
+    flag_ex: Vec<String>,
+    flag_batch: bool,
     flag_tps: bool,
     flag_limit: bool,
     flag_symbols: Option<String>,
     flag_debug_history: String,
+    flag_headless: bool,
+    flag_max_ticks: Option<u64>,
+    flag_until: Vec<String>,
+    flag_until_log: Vec<String>,
+    flag_expect_screen: Vec<String>,
+    flag_update_screens: bool,
+    flag_screenshot: Option<String>,
+    flag_dump: Vec<String>,
+    flag_report: Option<String>,
+    flag_trace: Option<String>,
+    flag_trace_format: String,
+    flag_profile: Option<String>,
+    flag_flamegraph: Option<String>,
+    flag_sample: Option<u64>,
+    flag_coverage: Option<String>,
+    flag_lcov: Option<String>,
+    flag_debug_info: Option<String>,
 }
 
 #[cfg(feature = "bins")]
@@ -69,79 +177,188 @@ fn main_ret() -> i32 {
                             .and_then(|d| d.decode())
                             .unwrap_or_else(|e| e.exit());
 
-    let rom = {
+    // Only a headless run has a defined end to write them
+    if !args.flag_headless &&
+       (args.flag_profile.is_some() || args.flag_flamegraph.is_some() ||
+        args.flag_coverage.is_some() || args.flag_lcov.is_some()) {
+        die!(1, "--profile, --flamegraph, --coverage and --lcov need --headless");
+    }
+
+    let machine = match args.flag_machine {
+        Some(ref path) => match get_machine(path) {
+            Ok(m) => Some(m),
+            Err(i) => return i,
+        },
+        None => None,
+    };
+    // Checked before the termscreen takes the terminal
+    let terminal_debugger = args.flag_debugger || args.flag_tui || args.flag_batch ||
+                            !args.flag_ex.is_empty();
+    let termscreen = machine.iter().flat_map(|m| &m.devices).any(is_termscreen) ||
+                     args.arg_device.iter().flat_map(|d| d).any(|d| {
+                         d.parse().map(|d| is_termscreen(&d)).unwrap_or(false)
+                     });
+    if terminal_debugger && termscreen {
+        die!(1, "The termscreen device can't share the terminal with the debugger");
+    }
+
+    let mut builder = ComputerBuilder::new();
+    if let Some(ref machine) = machine {
+        let path = Path::new(args.flag_machine.as_ref().unwrap());
+        let base = path.parent().unwrap_or(Path::new(""));
+        builder = match builder.machine(machine, base) {
+            Ok(b) => b,
+            Err(e) => die!(1, "Error while building the machine: {}", e),
+        };
+    }
+    // Without machine description or boot, the program is read from stdin
+    if (args.flag_machine.is_none() && !args.flag_boot) || args.arg_file.is_some() {
         let mut input = match utils::get_input(args.arg_file.clone()) {
             Ok(input) => input,
             Err(e) => die!(1, "Error while opening the input: {}", e),
         };
-        let mut rom = Vec::new();
-        rom.extend(input.iter_items::<u16, LittleEndian>());
-        rom
+        let rom: Vec<u16> = input.iter_items::<u16, LittleEndian>().collect();
+        builder = builder.rom(&rom, 0);
+    }
+
+    let symbols = if args.flag_trace.is_some() || args.flag_profile.is_some() ||
+                     args.flag_flamegraph.is_some() ||
+                     args.flag_coverage.is_some() || args.flag_lcov.is_some() {
+        match get_optional_symbols(&args) {
+            Ok(s) => s,
+            Err(i) => return i,
+        }
+    } else {
+        None
+    };
+    let tracer = match get_tracer(&args, symbols.as_ref()) {
+        Ok(t) => t,
+        Err(i) => return i,
+    };
+    let profiler = if args.flag_profile.is_some() ||
+                      args.flag_flamegraph.is_some() {
+        let mode = match args.flag_sample {
+            Some(n) => profiler::Mode::Sampling(n),
+            None => profiler::Mode::Exact,
+        };
+        let mut profiler = Profiler::new(mode);
+        if let Some(ref symbols) = symbols {
+            profiler.symbols(symbols);
+        }
+        Some(profiler)
+    } else {
+        None
     };
 
-    let mut cpu = Cpu::default();
-    cpu.load(&rom, 0);
+    let debug_info = match (args.flag_lcov.as_ref(), args.flag_debug_info.as_ref()) {
+        (Some(_), Some(path)) => match utils::get_debug_info(path) {
+            Ok(d) => Some(d),
+            Err(e) => die!(1, "{}", e),
+        },
+        (Some(_), None) => die!(1, "--lcov needs the --debug-info of the program"),
+        (None, _) => None,
+    };
+    let coverage = if args.flag_coverage.is_some() || args.flag_lcov.is_some() {
+        Some(Coverage::new())
+    } else {
+        None
+    };
+    let mut reports = Reports {
+        image: builder.image(),
+        symbols: symbols.unwrap_or_default(),
+        debug_info: debug_info.unwrap_or_default(),
+    };
 
-    let devices = {
-        let mut devices: Vec<Box<Device>> = vec![];
-        if let Some(devs) = args.arg_device {
+    let mut image = builder.image();
+    let booted = builder.is_booted() || args.flag_boot;
+    let (mut cpu, mut devices) = builder.into_parts();
+    let mut devices = {
+        if let Some(ref devs) = args.arg_device {
             for d in devs {
-                match d.as_ref() {
-                    "clock" => devices.push(Box::new(eeprom::Eeprom::new(clock::Clock::new(100_000)))),
-                    "keyscreen" => if let Err(e) = add_keyscreen(&mut devices) {
-                        return e;
-                    },
-                    "m35fd" => devices.push(Box::new(m35fd::M35fd::new(None))),
-                    _ => {
-                        let mut components = d.split("=");
-                        match (components.next(), components.next()) {
-                            (Some("m35fd"), Some("empty")) =>
-                                devices.push(Box::new(m35fd::M35fd::new(m35fd::Floppy::default()))),
-                            (Some("m35fd"), Some(path)) => {
-                                let floppy = match m35fd::Floppy::load(path) {
-                                    Ok(f) => f,
-                                    Err(e) =>
-                                        die!(1,
-                                             "Error while loading the floppy \"{}\": {}",
-                                             path,
-                                             e),
-                                };
-                                devices.push(Box::new(m35fd::M35fd::new(floppy)));
-                            }
-                            _ => die!(1, "Device \"{}\" unknown", d),
-                        }
-                   }
+                if let Err(e) = utils::add_device(&mut devices, d) {
+                    die!(1, "{}", e);
                 }
             }
         }
+        for spec in &args.flag_floppy {
+            if let Err(e) = utils::add_device(&mut devices,
+                                              &format!("m35fd={}", spec)) {
+                die!(1, "{}", e);
+            }
+        }
         devices
     };
+    if args.flag_boot {
+        if let Err(e) = bbos::boot(&mut cpu, &mut devices) {
+            die!(1, "Error while booting: {}", e);
+        }
+        image = cpu.ram.iter().take(bbfs::SECTOR_SIZE).cloned().collect();
+        reports.image = image.clone();
+    }
 
-    if args.flag_debugger {
+    if args.flag_debugger || args.flag_tui || args.flag_gdb.is_some() ||
+       args.flag_batch || !args.flag_ex.is_empty() {
         let mut debugger = Debugger::new(cpu, devices);
+        debugger.rom(image);
+        debugger.boot_on_reset(booted);
         debugger.log_litterals(args.flag_log_litterals);
-        if let Some(path) = args.flag_symbols {
+        debugger.set_event_handler(debugger::print_event);
+        debugger.trace(tracer);
+        if let Some(path) = symbols_path(&args) {
             println!("Loading symbols from {}", path);
-            let symbols = match get_symbols(path) {
-                Ok(s) => s,
-                Err(i) => {
-                    return i;
+            match get_symbols(path) {
+                Ok(symbols) => debugger.symbols(symbols),
+                Err(i) => return i,
+            }
+        }
+        if let Some(port) = args.flag_gdb {
+            if let Err(e) = gdb::serve(&mut debugger, ("127.0.0.1", port)) {
+                die!(1, "GDB stub error: {}", e);
+            }
+        } else {
+            let mut ok = true;
+            for cmd in &args.flag_ex {
+                if let Err(e) = debugger.exec_line(cmd, &mut debugger::print_output) {
+                    println!("{}", e);
+                    ok = false;
+                    break;
                 }
-            };
-            debugger.symbols(symbols);
-        } else if let Some(mut path) = args.arg_file {
-            path.push_str(".sym");
-            match get_symbols(path.clone()) {
-                Ok(symbols) => {
-                    println!("Loading symbols from {}", path);
-                    debugger.symbols(symbols);
+            }
+            if args.flag_batch {
+                return if ok { 0 } else { 1 };
+            }
+            if args.flag_tui {
+                if let Err(e) = run_tui(&mut debugger) {
+                    die!(1, "{}", e);
                 }
-                Err(_) => (),
+            } else {
+                debugger.run(args.flag_debug_history);
+            }
+        }
+    } else if args.flag_headless {
+        let mut computer = Computer::new(cpu, devices);
+        computer.boot_on_reset(booted);
+        computer.trace(tracer);
+        computer.profile(profiler);
+        computer.coverage(coverage);
+        let code = run_headless(&mut computer, &args);
+        if let Some(ref path) = args.flag_screenshot {
+            let res = match computer.recorder() {
+                Some(recorder) => recorder.screenshot(path).map_err(|e| e.to_string()),
+                None => Err("no recorder screen found".into()),
             };
+            if let Err(e) = res {
+                error!("Error while writing the screenshot {}: {}", path, e);
+            }
         }
-        debugger.run(args.flag_debug_history);
+        return match write_reports(&mut computer, &args, &reports) {
+            Ok(()) => code,
+            Err(i) => i,
+        };
     } else {
         let mut computer = Computer::new(cpu, devices);
+        computer.boot_on_reset(booted);
+        computer.trace(tracer);
         let mut timer_tps = time::Instant::now();
         let mut timer_limit = time::Instant::now();
         let normal_tickrate = 100_000;
@@ -153,9 +370,8 @@ fn main_ret() -> i32 {
         };
 
         loop {
-            match computer.tick() {
-                Ok(_) => (),
-                Err(e) => die!(1, "{}", e),
+            if let Err(e) = computer.tick() {
+                die!(1, "{}", e);
             }
 
             for msg in &computer.cpu.log_queue {
@@ -192,13 +408,318 @@ fn main_ret() -> i32 {
 
 #[cfg(not(feature = "bins"))]
 fn main_ret() -> i32 {
-    "The feature \"bins\" must be activated to use this binary"
+    use std::io::Write;
+    writeln!(std::io::stderr(),
+             "The feature \"bins\" must be activated to use this binary").unwrap();
+    1
 }
 
 fn main() {
     std::process::exit(main_ret());
 }
 
+#[cfg(feature = "bins")]
+fn run_headless(computer: &mut Computer, args: &Args) -> i32 {
+    // Symbols are only loaded if an address is given as a label
+    let mut symbols = None;
+    let mut addr = |s: &str| -> result::Result<u16, i32> {
+        if let Some(n) = utils::parse_number(s) {
+            return Ok(n);
+        }
+        if symbols.is_none() {
+            match try!(get_optional_symbols(args)) {
+                Some(s) => symbols = Some(s),
+                None => {
+                    println!("The address \"{}\" needs the symbols, see --symbols",
+                             s);
+                    return Err(1);
+                }
+            }
+        }
+        match symbols.as_ref().unwrap().get(s) {
+            Some(infos) => Ok(infos.addr),
+            None => {
+                println!("Unknown address \"{}\"", s);
+                Err(1)
+            }
+        }
+    };
+
+    let mut conditions = ExitConditions {
+        max_ticks: args.flag_max_ticks,
+        pcs: vec![],
+        logs: vec![],
+    };
+    for s in &args.flag_until {
+        match addr(s) {
+            Ok(a) => conditions.pcs.push(a),
+            Err(i) => return i,
+        }
+    }
+    for s in &args.flag_until_log {
+        match utils::parse_number(s) {
+            Some(n) => conditions.logs.push(n),
+            None => die!(1, "Invalid LOG value \"{}\"", s),
+        }
+    }
+    let mut ranges = vec![];
+    for s in &args.flag_dump {
+        let mut parts = s.splitn(2, ':');
+        let from = match addr(parts.next().unwrap()) {
+            Ok(a) => a,
+            Err(i) => return i,
+        };
+        match parts.next().and_then(utils::parse_number) {
+            Some(len) => ranges.push((from, len)),
+            None => die!(1, "Invalid memory range \"{}\"", s),
+        }
+    }
+
+    let mut logs = vec![];
+    let log_litterals = args.flag_log_litterals;
+    let reason = computer.run(&conditions, |cpu| {
+        let values: Vec<u16> = cpu.log_queue.drain(..).collect();
+        for msg in values {
+            let litteral = if log_litterals {
+                Some(cpu.get_str(msg))
+            } else {
+                None
+            };
+            match litteral {
+                Some(ref s) => info!("LOG 0x{:0>4x}: {}", msg, s),
+                None => info!("LOG 0x{:0>4x}", msg),
+            }
+            logs.push(debugger::LogMessage {
+                value: msg,
+                litteral: litteral,
+            });
+        }
+    });
+
+    let (outcome, mut code) = match reason {
+        ExitReason::Halted => (json!({"reason": "halted"}), 0),
+        ExitReason::Break(msg) => (json!({"reason": "break", "message": msg}), 2),
+        ExitReason::TickLimit => (json!({"reason": "tick_limit"}), 3),
+        ExitReason::Pc(pc) => (json!({"reason": "pc", "pc": pc}), 4),
+        ExitReason::Log(msg) => (json!({"reason": "log", "value": msg}), 4),
+        ExitReason::Error(ref e) => (json!({"reason": "error", "error": e}), 5),
+    };
+    let screens = check_screens(computer, args);
+    // An error is reported over a screen mismatch
+    if code != 5 && screens.iter().any(|s| !s.1.is_none()) {
+        code = 6;
+    }
+    let screens: Vec<_> = screens.iter().map(|&(ref path, ref error)| {
+        json!({"reference": path, "ok": error.is_none(), "error": error})
+    }).collect();
+    let regs = debugger::Registers::new(&computer.cpu, computer.current_tick);
+    let memory: Vec<_> = ranges.iter().map(|&(from, len)| {
+        let words: Vec<u16> = computer.cpu
+                                      .ram
+                                      .iter_wrap(from)
+                                      .take(len as usize)
+                                      .cloned()
+                                      .collect();
+        json!({"address": from, "words": words})
+    }).collect();
+    let logs: Vec<_> = logs.iter().map(|msg| {
+        json!({"value": msg.value, "litteral": msg.litteral})
+    }).collect();
+    let report = json!({
+        "outcome": outcome,
+        "ticks": computer.current_tick,
+        "registers": regs,
+        "memory": memory,
+        "logs": logs,
+        "screens": screens,
+    });
+
+    let mut output = match utils::get_output(args.flag_report.clone()) {
+        Ok(o) => o,
+        Err(e) => die!(1, "Error while opening the report file: {}", e),
+    };
+    if let Err(e) = serde_json::to_writer_pretty(&mut output, &report)
+                                .map_err(|e| e.to_string())
+                                .and_then(|_| writeln!(output, "")
+                                                  .map_err(|e| e.to_string())) {
+        die!(1, "Error while writing the report: {}", e);
+    }
+    code
+}
+
+/// Compare the screen with the `--expect-screen` references, or update them.
+/// Return the references with their error.
+#[cfg(feature = "bins")]
+fn check_screens(computer: &mut Computer,
+                 args: &Args) -> Vec<(String, Option<String>)> {
+    if args.flag_expect_screen.is_empty() {
+        return vec![];
+    }
+    let screen = computer.screen();
+    args.flag_expect_screen.iter().map(|path| {
+        let screen = match screen {
+            Some(ref screen) => screen,
+            None => return (path.clone(), Some("no LEM1802 found".into())),
+        };
+        if args.flag_update_screens {
+            let res = Reference::save(screen, path).map_err(|e| e.to_string());
+            return (path.clone(), res.err());
+        }
+        let reference = match Reference::load(path) {
+            Ok(r) => r,
+            Err(e) => return (path.clone(), Some(e.to_string())),
+        };
+        let error = reference.check(screen).err().map(|mismatch| {
+            if let golden::Mismatch::Pixels { ref diff, .. } = mismatch {
+                let diff_path = format!("{}.diff.png", path);
+                if let Err(e) = File::create(&diff_path)
+                                     .and_then(|f| diff.write_png(&mut BufWriter::new(f))) {
+                    error!("Error while writing {}: {}", diff_path, e);
+                }
+            }
+            mismatch.to_string()
+        });
+        if let Some(ref e) = error {
+            error!("Screen differs from {}: {}", path, e);
+        }
+        (path.clone(), error)
+    }).collect()
+}
+
+#[cfg(feature = "bins")]
+fn get_machine(path: &str) -> result::Result<Machine, i32> {
+    match utils::get_input(Some(path.into())) {
+        Ok(i) => serde_json::from_reader(i).map_err(|e| {
+            println!("Error while decoding the machine description: {}", e);
+            1
+        }),
+        Err(e) => {
+            println!("Error while reading the machine description: {}", e);
+            Err(1)
+        }
+    }
+}
+
+#[cfg(feature = "bins")]
+fn is_termscreen(device: &DeviceDescription) -> bool {
+    match *device {
+        DeviceDescription::Termscreen { .. } => true,
+        _ => false,
+    }
+}
+
+/// Path of the symbols given with --symbols, or next to the binary file if
+/// it exists.
+#[cfg(feature = "bins")]
+fn symbols_path(args: &Args) -> Option<String> {
+    if let Some(ref path) = args.flag_symbols {
+        return Some(path.clone());
+    }
+    args.arg_file
+        .as_ref()
+        .map(|file| format!("{}.sym", file))
+        .and_then(|path| if Path::new(&path).exists() { Some(path) } else { None })
+}
+
+#[cfg(feature = "bins")]
+fn get_optional_symbols(args: &Args) -> result::Result<Option<Globals>, i32> {
+    match symbols_path(args) {
+        Some(path) => get_symbols(path).map(Some),
+        None => Ok(None),
+    }
+}
+
+/// Open the --trace file. The PCs are shown with `symbols` if they are
+/// available.
+#[cfg(feature = "bins")]
+fn get_tracer(args: &Args,
+              symbols: Option<&Globals>) -> result::Result<Option<Tracer>, i32> {
+    let path = match args.flag_trace {
+        Some(ref path) => path,
+        None => return Ok(None),
+    };
+    let format: trace::Format = match args.flag_trace_format.parse() {
+        Ok(f) => f,
+        Err(e) => {
+            println!("Invalid --trace-format: {}", e);
+            return Err(1);
+        }
+    };
+    let output = match File::create(path) {
+        Ok(f) => BufWriter::new(f),
+        Err(e) => {
+            println!("Error while opening the trace file: {}", e);
+            return Err(1);
+        }
+    };
+    let mut tracer = Tracer::new(Box::new(output), format);
+    if let Some(symbols) = symbols {
+        tracer.symbols(symbols);
+    }
+    Ok(Some(tracer))
+}
+
+/// What the profile and coverage reports need besides the results.
+#[cfg(feature = "bins")]
+struct Reports {
+    image: Vec<u16>,
+    symbols: Globals,
+    debug_info: DebugInfo,
+}
+
+/// Write the --profile, --flamegraph, --coverage and --lcov files.
+#[cfg(feature = "bins")]
+fn write_reports(computer: &mut Computer,
+                 args: &Args,
+                 reports: &Reports) -> result::Result<(), i32> {
+    if let Some(coverage) = computer.coverage(None) {
+        if let Some(ref path) = args.flag_coverage {
+            let res = utils::get_output(Some(path.clone())).and_then(|mut o| {
+                coverage.write_summary(&mut o, &reports.symbols, &reports.image)
+            });
+            if let Err(e) = res {
+                println!("Error while writing the coverage: {}", e);
+                return Err(1);
+            }
+        }
+        if let Some(ref path) = args.flag_lcov {
+            let res = utils::get_output(Some(path.clone())).and_then(|mut o| {
+                coverage.write_lcov(&mut o,
+                                    &reports.debug_info,
+                                    &reports.symbols,
+                                    &reports.image)
+            });
+            if let Err(e) = res {
+                println!("Error while writing the lcov file: {}", e);
+                return Err(1);
+            }
+        }
+    }
+
+    let profiler = match computer.profile(None) {
+        Some(p) => p,
+        None => return Ok(()),
+    };
+    if let Some(ref path) = args.flag_profile {
+        let res = utils::get_output(Some(path.clone()))
+                      .and_then(|mut o| profiler.write_report(&mut o, 30));
+        if let Err(e) = res {
+            println!("Error while writing the profile: {}", e);
+            return Err(1);
+        }
+    }
+    if let Some(ref path) = args.flag_flamegraph {
+        let res = utils::get_output(Some(path.clone()))
+                      .and_then(|mut o| profiler.write_folded(&mut o));
+        if let Err(e) = res {
+            println!("Error while writing the folded stacks: {}", e);
+            return Err(1);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "bins")]
 fn get_symbols(path: String) -> result::Result<Globals, i32> {
     Ok(match utils::get_input(Some(path)) {
         Ok(i) => match serde_json::from_reader(i) {
@@ -215,16 +736,12 @@ fn get_symbols(path: String) -> result::Result<Globals, i32> {
     })
 }
 
-#[cfg(feature = "glium")]
-fn add_keyscreen(devices: &mut Vec<Box<Device>>) -> result::Result<(), i32> {
-    let (screen_backend, kb_backend) = glium_backend::start();
-    devices.push(Box::new(keyboard::Keyboard::new(kb_backend)));
-    devices.push(Box::new(lem1802::LEM1802::new(screen_backend)));
-    Ok(())
+#[cfg(feature = "debugger-tui")]
+fn run_tui(debugger: &mut Debugger) -> result::Result<(), String> {
+    tui::run(debugger).map_err(|e| e.to_string())
 }
 
-#[cfg(not(feature = "glium"))]
-fn add_keyscreen(_devices: &mut Vec<Box<Device>>) -> result::Result<(), i32> {
-    println!("Symbol map loading is disabled, activate the \"glium\" feature.");
-    Err(1)
+#[cfg(not(feature = "debugger-tui"))]
+fn run_tui(_debugger: &mut Debugger) -> result::Result<(), String> {
+    Err("The TUI is disabled, activate the \"debugger-tui\" feature.".into())
 }
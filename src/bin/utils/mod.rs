@@ -1,6 +1,11 @@
 use std::fs::{File, OpenOptions};
 use std::io::{self, Read, Write, BufReader, BufWriter};
 
+#[cfg(feature = "bins")]
+use dcpu::assembler::types::DebugInfo;
+use dcpu::emulator::device::Device;
+use dcpu::emulator::machine::DeviceDescription;
+
 #[allow(dead_code)]
 pub fn get_input(i: Option<String>) -> Result<Box<Read>, io::Error> {
     if let Some(path) = i {
@@ -29,6 +34,40 @@ pub fn get_output(o: Option<String>) -> Result<Box<Write>, io::Error> {
     }
 }
 
+/// Read the line mapping written by `assembler --debug-info`.
+#[cfg(feature = "bins")]
+#[allow(dead_code)]
+pub fn get_debug_info(path: &str) -> Result<DebugInfo, String> {
+    let input = try!(get_input(Some(path.into())).map_err(|e| {
+        format!("Error while reading \"{}\": {}", path, e)
+    }));
+    ::serde_json::from_reader(input).map_err(|e| {
+        format!("Error while decoding the debug info: {}", e)
+    })
+}
+
+/// Parse a decimal or `0x` prefixed hexadecimal number.
+#[allow(dead_code)]
+pub fn parse_number(s: &str) -> Option<u16> {
+    if s.starts_with("0x") {
+        u16::from_str_radix(&s[2..], 16).ok()
+    } else {
+        s.parse().ok()
+    }
+}
+
+/// Add the devices described by `d`, see `DeviceDescription::from_str`.
+#[allow(dead_code)]
+pub fn add_device(devices: &mut Vec<Box<Device>>, d: &str) -> Result<(), String> {
+    let description = try!(d.parse::<DeviceDescription>()
+                            .map_err(|e| e.to_string()));
+    let new_devices = try!(description.build("").map_err(|e| {
+        format!("Error while creating the device \"{}\": {}", d, e)
+    }));
+    devices.extend(new_devices);
+    Ok(())
+}
+
 macro_rules! die {
     ( $exit:expr, $($x:expr),* ) => (
         {
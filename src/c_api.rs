@@ -1,6 +1,5 @@
 use emulator::{Debugger};
 use emulator::cpu::{Cpu, OnDecodeError};
-use types::Register;
 
 #[repr(C)]
 pub struct CRegisters {
@@ -33,20 +32,20 @@ pub unsafe extern fn dcpu_debugger_ram(d: *mut Debugger) -> *mut u16 {
 
 #[no_mangle]
 pub unsafe extern fn dcpu_debugger_registers(d: *mut Debugger) -> CRegisters {
-    let d = &*d;
+    let regs = (*d).registers();
     CRegisters {
-        pc: d.cpu.pc.0,
-        ia: d.cpu.ia,
-        sp: d.cpu.sp.0,
-        ex: d.cpu.ex,
-        a: d.cpu.registers[Register::A],
-        b: d.cpu.registers[Register::B],
-        c: d.cpu.registers[Register::C],
-        i: d.cpu.registers[Register::I],
-        j: d.cpu.registers[Register::J],
-        x: d.cpu.registers[Register::X],
-        y: d.cpu.registers[Register::Y],
-        z: d.cpu.registers[Register::Z],
+        pc: regs.pc,
+        ia: regs.ia,
+        sp: regs.sp,
+        ex: regs.ex,
+        a: regs.a,
+        b: regs.b,
+        c: regs.c,
+        i: regs.i,
+        j: regs.j,
+        x: regs.x,
+        y: regs.y,
+        z: regs.z,
     }
 }
 
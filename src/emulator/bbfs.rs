@@ -0,0 +1,520 @@
+//! BBFS, the filesystem of the BBOS floppies, on m35fd images of 1440
+//! sectors of 512 little endian words.
+//!
+//! - Sector 0 holds the bootloader. The disk is bootable when the last word
+//!   of this sector is `BOOT_SIGNATURE`.
+//! - Sectors 1 to 3 hold the header: the version, 5 reserved words, a mask
+//!   of the free sectors (bit `n % 16` of word `n / 16` is set when sector
+//!   `n` is free) and the FAT. The FAT entry of a sector is the next sector
+//!   of the file, or `FAT_LAST | <words used>` for its last sector.
+//! - The root directory starts at sector 4.
+//!
+//! A directory is a file starting with the version and the number of
+//! entries. Each entry is 10 words: the type, the first sector and the name,
+//! up to 16 ASCII characters packed two by word, the first one in the high
+//! byte.
+
+use std::cmp;
+use std::fs::File;
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+use byteorder::{self, WriteBytesExt};
+use emulator::device::disk;
+
+/// Geometry of the m35fd floppies.
+pub use emulator::device::m35fd::{SECTORS, SECTOR_SIZE};
+
+pub const VERSION: u16 = 0xbf10;
+/// Last word of the boot sector of a bootable disk.
+pub const BOOT_SIGNATURE: u16 = 0x55aa;
+/// Largest bootloader, the last word of the sector being the signature.
+pub const MAX_BOOTLOADER_SIZE: usize = SECTOR_SIZE - 1;
+pub const MAX_NAME_SIZE: usize = 16;
+
+const HEADER_START: usize = SECTOR_SIZE;
+const FREE_MASK_START: usize = HEADER_START + 6;
+const FAT_START: usize = FREE_MASK_START + SECTORS / 16;
+const ROOT_SECTOR: u16 = 4;
+const FAT_LAST: u16 = 0x8000;
+const FAT_FREE: u16 = 0xffff;
+const DIRECTORY_HEADER_SIZE: usize = 2;
+const ENTRY_SIZE: usize = 10;
+
+error_chain!(
+    foreign_links {
+        Io(io::Error);
+    }
+
+    errors {
+        NotFormatted {
+            description("the image is not formatted with BBFS")
+        }
+        NotFound(path: String) {
+            description("no such file or directory")
+            display("no such file or directory: {}", path)
+        }
+        NotADirectory(path: String) {
+            description("not a directory")
+            display("not a directory: {}", path)
+        }
+        IsADirectory(path: String) {
+            description("is a directory")
+            display("is a directory: {}", path)
+        }
+        AlreadyExists(path: String) {
+            description("the file already exists")
+            display("the file already exists: {}", path)
+        }
+        InvalidName(name: String) {
+            description("invalid file name")
+            display("invalid file name \"{}\", it must be 1 to 16 ASCII \
+                     characters without '/'",
+                    name)
+        }
+        DiskFull {
+            description("no space left on the disk")
+        }
+        CorruptDirectory(sector: u16) {
+            description("corrupt directory")
+            display("the directory starting at sector {} is corrupt", sector)
+        }
+        BootloaderTooBig(size: usize) {
+            description("the bootloader doesn't fit in the boot sector")
+            display("the bootloader is {} words, the maximum is {}",
+                    size,
+                    MAX_BOOTLOADER_SIZE)
+        }
+    }
+);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+    Directory = 0,
+    File = 1,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+    pub name: String,
+    pub kind: EntryKind,
+    pub sector: u16,
+    /// Size in words.
+    pub size: usize,
+}
+
+/// A floppy image, kept in memory.
+pub struct Image {
+    words: Vec<u16>,
+}
+
+impl Image {
+    /// Blank formatted image, with an empty root directory and no
+    /// bootloader.
+    pub fn format() -> Image {
+        let mut image = Image { words: vec![0; SECTORS * SECTOR_SIZE] };
+        image.words[HEADER_START] = VERSION;
+        for sector in 0..SECTORS as u16 {
+            image.set_fat(sector, FAT_FREE);
+            image.set_free(sector, true);
+        }
+        // Boot sector and header
+        image.set_fat(0, FAT_LAST | SECTOR_SIZE as u16);
+        image.set_fat(1, 2);
+        image.set_fat(2, 3);
+        image.set_fat(3, FAT_LAST | SECTOR_SIZE as u16);
+        for sector in 0..ROOT_SECTOR + 1 {
+            image.set_free(sector, false);
+        }
+        image.set_fat(ROOT_SECTOR, FAT_LAST);
+        image.write_chain(ROOT_SECTOR, &[VERSION, 0]).unwrap();
+        image
+    }
+
+    /// Load an image, missing words being 0 and extra ones ignored.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Image> {
+        let mut words = vec![0; SECTORS * SECTOR_SIZE];
+        try!(disk::load_image(path, words.iter_mut()));
+        Ok(Image { words: words })
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let mut output = BufWriter::new(try!(File::create(path)));
+        try!(output.write_all_items::<u16, byteorder::LittleEndian>(&self.words));
+        try!(output.flush());
+        Ok(())
+    }
+
+    pub fn words(&self) -> &[u16] {
+        &self.words
+    }
+
+    pub fn is_formatted(&self) -> bool {
+        self.words[HEADER_START] == VERSION
+    }
+
+    pub fn is_bootable(&self) -> bool {
+        self.words[SECTOR_SIZE - 1] == BOOT_SIGNATURE
+    }
+
+    /// Write `code` to the boot sector and mark the disk bootable.
+    pub fn set_bootloader(&mut self, code: &[u16]) -> Result<()> {
+        if code.len() > MAX_BOOTLOADER_SIZE {
+            return Err(ErrorKind::BootloaderTooBig(code.len()).into());
+        }
+        for w in &mut self.words[..SECTOR_SIZE] {
+            *w = 0;
+        }
+        self.words[..code.len()].copy_from_slice(code);
+        self.words[SECTOR_SIZE - 1] = BOOT_SIGNATURE;
+        Ok(())
+    }
+
+    /// Number of free sectors.
+    pub fn free_sectors(&self) -> usize {
+        (0..SECTORS as u16).filter(|s| self.is_free(*s)).count()
+    }
+
+    /// Entries of the directory at `path`, `/` being the root.
+    pub fn list(&self, path: &str) -> Result<Vec<Entry>> {
+        let directory = try!(self.find(path));
+        if directory.kind != EntryKind::Directory {
+            return Err(ErrorKind::NotADirectory(path.into()).into());
+        }
+        self.entries(directory.sector)
+    }
+
+    /// Content of the file at `path`.
+    pub fn read(&self, path: &str) -> Result<Vec<u16>> {
+        let file = try!(self.find(path));
+        if file.kind != EntryKind::File {
+            return Err(ErrorKind::IsADirectory(path.into()).into());
+        }
+        Ok(self.read_chain(file.sector))
+    }
+
+    /// Create or replace the file at `path`.
+    pub fn write(&mut self, path: &str, data: &[u16]) -> Result<()> {
+        let (parent, name) = try!(self.find_parent(path));
+        let entries = try!(self.entries(parent.sector));
+        match entries.into_iter().find(|e| e.name == name) {
+            Some(Entry { kind: EntryKind::File, sector, .. }) => {
+                self.write_chain(sector, data)
+            }
+            Some(_) => Err(ErrorKind::IsADirectory(path.into()).into()),
+            None => self.create(&parent, name, EntryKind::File, data),
+        }
+    }
+
+    /// Create an empty directory at `path`.
+    pub fn mkdir(&mut self, path: &str) -> Result<()> {
+        let (parent, name) = try!(self.find_parent(path));
+        if try!(self.entries(parent.sector)).iter().any(|e| e.name == name) {
+            return Err(ErrorKind::AlreadyExists(path.into()).into());
+        }
+        self.create(&parent, name, EntryKind::Directory, &[VERSION, 0])
+    }
+
+    fn create(&mut self,
+              parent: &Entry,
+              name: &str,
+              kind: EntryKind,
+              data: &[u16])
+              -> Result<()> {
+        let mut directory = try!(self.read_directory(parent.sector));
+        let needed = sectors_for(data.len()) +
+                     sectors_for(directory.len() + ENTRY_SIZE) -
+                     sectors_for(directory.len());
+        if needed > self.free_sectors() {
+            return Err(ErrorKind::DiskFull.into());
+        }
+        let sector = self.allocate().unwrap();
+        self.set_fat(sector, FAT_LAST);
+        try!(self.write_chain(sector, data));
+
+        directory[1] += 1;
+        directory.push(kind as u16);
+        directory.push(sector);
+        directory.extend_from_slice(&pack_name(name));
+        self.write_chain(parent.sector, &directory)
+    }
+
+    /// Entry of `path`, the root being a directory entry named "/".
+    fn find(&self, path: &str) -> Result<Entry> {
+        if !self.is_formatted() {
+            return Err(ErrorKind::NotFormatted.into());
+        }
+        let mut current = Entry {
+            name: "/".into(),
+            kind: EntryKind::Directory,
+            sector: ROOT_SECTOR,
+            size: self.read_chain(ROOT_SECTOR).len(),
+        };
+        for name in path.split('/').filter(|n| !n.is_empty()) {
+            if current.kind != EntryKind::Directory {
+                return Err(ErrorKind::NotADirectory(current.name).into());
+            }
+            let entries = try!(self.entries(current.sector));
+            current = try!(entries.into_iter()
+                                  .find(|e| e.name == name)
+                                  .ok_or_else(|| ErrorKind::NotFound(path.into())));
+        }
+        Ok(current)
+    }
+
+    /// Directory containing `path` and the name of `path` in it.
+    fn find_parent<'a>(&self, path: &'a str) -> Result<(Entry, &'a str)> {
+        let path = path.trim_right_matches('/');
+        let (parent, name) = match path.rfind('/') {
+            Some(i) => (&path[..i], &path[i + 1..]),
+            None => ("", path),
+        };
+        if name.is_empty() || name.len() > MAX_NAME_SIZE || name.bytes().any(|c| c >= 0x80) {
+            return Err(ErrorKind::InvalidName(name.into()).into());
+        }
+        let parent_entry = try!(self.find(parent));
+        if parent_entry.kind != EntryKind::Directory {
+            return Err(ErrorKind::NotADirectory(parent.into()).into());
+        }
+        Ok((parent_entry, name))
+    }
+
+    /// Content of the directory at `sector`, checking that it holds the
+    /// entries announced by its header.
+    fn read_directory(&self, sector: u16) -> Result<Vec<u16>> {
+        let directory = self.read_chain(sector);
+        if directory.len() < DIRECTORY_HEADER_SIZE ||
+           directory.len() < DIRECTORY_HEADER_SIZE + directory[1] as usize * ENTRY_SIZE {
+            return Err(ErrorKind::CorruptDirectory(sector).into());
+        }
+        Ok(directory)
+    }
+
+    fn entries(&self, sector: u16) -> Result<Vec<Entry>> {
+        let directory = try!(self.read_directory(sector));
+        let count = directory[1] as usize;
+        directory[DIRECTORY_HEADER_SIZE..]
+            .chunks(ENTRY_SIZE)
+            .take(count)
+            .map(|e| {
+                if e[1] as usize >= SECTORS {
+                    return Err(ErrorKind::CorruptDirectory(sector).into());
+                }
+                Ok(Entry {
+                    name: unpack_name(&e[2..]),
+                    kind: if e[0] == EntryKind::Directory as u16 {
+                        EntryKind::Directory
+                    } else {
+                        EntryKind::File
+                    },
+                    sector: e[1],
+                    size: self.read_chain(e[1]).len(),
+                })
+            })
+            .collect()
+    }
+
+    fn read_chain(&self, first: u16) -> Vec<u16> {
+        let mut data = vec![];
+        let mut sector = first;
+        // Bounded in case of a loop in the FAT
+        for _ in 0..SECTORS {
+            if sector as usize >= SECTORS {
+                break;
+            }
+            let words = self.sector(sector);
+            let next = self.fat(sector);
+            if next & FAT_LAST != 0 {
+                let used = cmp::min((next & !FAT_LAST) as usize, SECTOR_SIZE);
+                data.extend_from_slice(&words[..used]);
+                break;
+            }
+            data.extend_from_slice(words);
+            sector = next;
+        }
+        data
+    }
+
+    /// Write `data` to the file starting at `first`, reusing its sectors and
+    /// allocating or freeing the difference.
+    fn write_chain(&mut self, first: u16, data: &[u16]) -> Result<()> {
+        let mut old = vec![];
+        let mut sector = first;
+        loop {
+            old.push(sector);
+            let next = self.fat(sector);
+            if next & FAT_LAST != 0 || old.len() == SECTORS {
+                break;
+            }
+            sector = next;
+        }
+        let count = sectors_for(data.len());
+        if count > old.len() + self.free_sectors() {
+            return Err(ErrorKind::DiskFull.into());
+        }
+
+        let mut sectors = old.clone();
+        sectors.truncate(count);
+        while sectors.len() < count {
+            let sector = self.allocate().unwrap();
+            sectors.push(sector);
+        }
+        for sector in old.iter().skip(count) {
+            self.set_fat(*sector, FAT_FREE);
+            self.set_free(*sector, true);
+        }
+        for (i, sector) in sectors.iter().enumerate() {
+            let chunk = data.chunks(SECTOR_SIZE).nth(i).unwrap_or(&[]);
+            let start = *sector as usize * SECTOR_SIZE;
+            for w in &mut self.words[start..start + SECTOR_SIZE] {
+                *w = 0;
+            }
+            self.words[start..start + chunk.len()].copy_from_slice(chunk);
+            let link = match sectors.get(i + 1) {
+                Some(next) => *next,
+                None => FAT_LAST | chunk.len() as u16,
+            };
+            self.set_fat(*sector, link);
+        }
+        Ok(())
+    }
+
+    /// Mark the first free sector as used and return it.
+    fn allocate(&mut self) -> Option<u16> {
+        let sector = (0..SECTORS as u16).find(|s| self.is_free(*s));
+        if let Some(sector) = sector {
+            self.set_free(sector, false);
+        }
+        sector
+    }
+
+    fn sector(&self, sector: u16) -> &[u16] {
+        let start = sector as usize * SECTOR_SIZE;
+        &self.words[start..start + SECTOR_SIZE]
+    }
+
+    fn fat(&self, sector: u16) -> u16 {
+        self.words[FAT_START + sector as usize]
+    }
+
+    fn set_fat(&mut self, sector: u16, value: u16) {
+        self.words[FAT_START + sector as usize] = value;
+    }
+
+    fn is_free(&self, sector: u16) -> bool {
+        self.words[FREE_MASK_START + sector as usize / 16] & (1 << (sector % 16)) != 0
+    }
+
+    fn set_free(&mut self, sector: u16, free: bool) {
+        let word = &mut self.words[FREE_MASK_START + sector as usize / 16];
+        if free {
+            *word |= 1 << (sector % 16);
+        } else {
+            *word &= !(1 << (sector % 16));
+        }
+    }
+}
+
+/// Sectors used by a file of `size` words, at least one.
+fn sectors_for(size: usize) -> usize {
+    if size == 0 {
+        1
+    } else {
+        (size + SECTOR_SIZE - 1) / SECTOR_SIZE
+    }
+}
+
+fn pack_name(name: &str) -> [u16; MAX_NAME_SIZE / 2] {
+    let mut packed = [0; MAX_NAME_SIZE / 2];
+    for (i, c) in name.bytes().enumerate() {
+        packed[i / 2] |= (c as u16) << if i % 2 == 0 { 8 } else { 0 };
+    }
+    packed
+}
+
+fn unpack_name(packed: &[u16]) -> String {
+    packed.iter()
+          .flat_map(|w| vec![(w >> 8) as u8, *w as u8])
+          .take_while(|c| *c != 0)
+          .map(|c| c as char)
+          .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_files() {
+        let mut image = Image::format();
+        assert!(image.list("/").unwrap().is_empty());
+        let free = image.free_sectors();
+
+        let big: Vec<u16> = (0..1300).collect();
+        image.mkdir("/bin").unwrap();
+        image.write("/bin/hello", &[1, 2, 3]).unwrap();
+        image.write("big", &big).unwrap();
+        assert_eq!(image.free_sectors(), free - 1 - 1 - 3);
+        assert_eq!(image.read("/bin/hello").unwrap(), [1, 2, 3]);
+        assert_eq!(image.read("/big").unwrap(), big);
+
+        let names: Vec<(String, EntryKind, usize)> = image.list("/")
+                                                          .unwrap()
+                                                          .into_iter()
+                                                          .map(|e| (e.name, e.kind, e.size))
+                                                          .collect();
+        assert_eq!(names,
+                   [("bin".into(), EntryKind::Directory, 12),
+                    ("big".into(), EntryKind::File, 1300)]);
+
+        image.write("/big", &[4]).unwrap();
+        assert_eq!(image.free_sectors(), free - 1 - 1 - 1);
+        assert_eq!(image.read("/big").unwrap(), [4]);
+
+        assert!(image.write("/nope/file", &[]).is_err());
+        assert!(image.write("/a_much_too_long_name", &[]).is_err());
+        assert!(image.mkdir("/bin").is_err());
+        assert!(image.read("/bin").is_err());
+    }
+
+    #[test]
+    fn test_bootloader() {
+        let mut image = Image::format();
+        assert!(!image.is_bootable());
+        image.set_bootloader(&[0x7f81, 0]).unwrap();
+        assert!(image.is_bootable());
+        assert_eq!(&image.words()[..2], &[0x7f81, 0]);
+        assert!(image.set_bootloader(&[0; SECTOR_SIZE]).is_err());
+        assert!(image.list("/").unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_corrupt_directory() {
+        let corrupt = |image: &mut Image| {
+            match image.list("/") {
+                Err(Error(ErrorKind::CorruptDirectory(ROOT_SECTOR), _)) => (),
+                r => panic!("{:?}", r),
+            }
+            assert!(image.write("/file", &[1]).is_err());
+            assert!(image.mkdir("/dir").is_err());
+            assert!(image.read("/dir/file").is_err());
+        };
+        let root = ROOT_SECTOR as usize * SECTOR_SIZE;
+
+        // Shorter than the header
+        let mut image = Image::format();
+        image.set_fat(ROOT_SECTOR, FAT_LAST | 1);
+        corrupt(&mut image);
+
+        // More entries than words
+        let mut image = Image::format();
+        image.words[root + 1] = 1;
+        corrupt(&mut image);
+
+        // Entry outside of the floppy
+        let mut image = Image::format();
+        image.write("/file", &[1]).unwrap();
+        image.words[root + DIRECTORY_HEADER_SIZE + 1] = SECTORS as u16;
+        corrupt(&mut image);
+    }
+}
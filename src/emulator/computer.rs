@@ -1,11 +1,42 @@
 use emulator::cpu;
-use emulator::device::{Device, TickResult};
+use emulator::coverage::Coverage;
+use emulator::device::{self, bbos, lem1802, Device, TickResult};
+use emulator::device::record::{self, RecordBackend};
+use emulator::profiler::{self, Profiler};
+use emulator::trace::Tracer;
+
+/// When `Computer::run` should stop, in addition to `HLT` and `BRK`.
+#[derive(Debug, Clone, Default)]
+pub struct ExitConditions {
+    pub max_ticks: Option<u64>,
+    /// Stop before executing an instruction at one of these addresses.
+    pub pcs: Vec<u16>,
+    /// Stop after a `LOG` of one of these values.
+    pub logs: Vec<u16>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExitReason {
+    /// `HLT` with an empty interrupt queue.
+    Halted,
+    /// `BRK` with its message.
+    Break(u16),
+    TickLimit,
+    Pc(u16),
+    Log(u16),
+    Error(String),
+}
 
 #[derive(Default)]
 pub struct Computer {
     pub cpu: cpu::Cpu,
     devices: Vec<Box<Device>>,
     pub current_tick: u64,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    /// Boot from the floppies again after resets.
+    boot: bool,
 }
 
 impl Computer {
@@ -14,20 +45,274 @@ impl Computer {
             cpu: cpu,
             devices: devices,
             current_tick: 0,
+            tracer: None,
+            profiler: None,
+            coverage: None,
+            boot: false,
+        }
+    }
+
+    /// Run `bbos::boot` again after each reset and power cycle, for a
+    /// computer started from a floppy.
+    pub fn boot_on_reset(&mut self, enabled: bool) {
+        self.boot = enabled;
+    }
+
+    /// Record each executed instruction with `tracer`, or stop tracing.
+    /// Return the previous tracer.
+    pub fn trace(&mut self, tracer: Option<Tracer>) -> Option<Tracer> {
+        let old = ::std::mem::replace(&mut self.tracer, tracer);
+        self.update_cpu_trace();
+        old
+    }
+
+    /// Profile the execution with `profiler`, or stop profiling. Return the
+    /// previous profiler.
+    pub fn profile(&mut self, profiler: Option<Profiler>) -> Option<Profiler> {
+        let old = ::std::mem::replace(&mut self.profiler, profiler);
+        self.update_cpu_trace();
+        old
+    }
+
+    /// Record the code coverage in `coverage`, or stop recording it. Return
+    /// the previous coverage.
+    pub fn coverage(&mut self, coverage: Option<Coverage>) -> Option<Coverage> {
+        let old = ::std::mem::replace(&mut self.coverage, coverage);
+        self.update_cpu_trace();
+        old
+    }
+
+    /// Only record the instructions if someone uses them.
+    fn update_cpu_trace(&mut self) {
+        self.cpu.trace = self.tracer.is_some() || self.coverage.is_some() ||
+                         self.profiler.as_ref().map_or(false, |p| {
+                             p.mode() == profiler::Mode::Exact
+                         });
+    }
+
+    /// Screen of the first LEM1802, if it uses a backend of this crate.
+    pub fn screen(&mut self) -> Option<Box<lem1802::RawScreen>> {
+        let cpu = &self.cpu;
+        self.devices
+            .iter_mut()
+            .filter_map(|d| lem1802::screen_of(&mut **d, cpu))
+            .next()
+    }
+
+    /// Backend of the first recording screen, to take screenshots.
+    pub fn recorder(&mut self) -> Option<RecordBackend> {
+        self.devices
+            .iter_mut()
+            .filter_map(|d| record::recorder_of(&mut **d))
+            .next()
+    }
+
+    /// Reset the cpu and the devices, keeping the ram.
+    pub fn reset(&mut self) -> Result<(), device::Error> {
+        self.cpu.reset();
+        try!(self.reset_devices());
+        self.current_tick = 0;
+        Ok(())
+    }
+
+    /// Reset the cpu and the devices, and reload the ram with `rom`.
+    pub fn power_cycle(&mut self, rom: &[u16]) -> Result<(), device::Error> {
+        self.cpu.power_cycle(rom);
+        try!(self.reset_devices());
+        self.current_tick = 0;
+        Ok(())
+    }
+
+    fn reset_devices(&mut self) -> Result<(), device::Error> {
+        for device in &mut self.devices {
+            try!(device.reset());
+        }
+        if self.boot {
+            try!(bbos::boot(&mut self.cpu, &mut self.devices));
+        }
+        Ok(())
+    }
+
+    /// Tick until one of the `conditions` is met, calling `after_tick` after
+    /// each tick.
+    pub fn run<F>(&mut self,
+                  conditions: &ExitConditions,
+                  mut after_tick: F) -> ExitReason
+        where F: FnMut(&mut cpu::Cpu)
+    {
+        loop {
+            if self.cpu.wait == 0 && conditions.pcs.contains(&self.cpu.pc.0) {
+                return ExitReason::Pc(self.cpu.pc.0);
+            }
+            if conditions.max_ticks.map_or(false, |max| self.current_tick >= max) {
+                return ExitReason::TickLimit;
+            }
+            let res = self.tick();
+            let log = self.cpu
+                          .log_queue
+                          .iter()
+                          .find(|msg| conditions.logs.contains(msg))
+                          .cloned();
+            after_tick(&mut self.cpu);
+            match res {
+                Ok(()) => (),
+                Err(e) => return match *e.kind() {
+                    cpu::ErrorKind::Break(msg) => ExitReason::Break(msg),
+                    _ => ExitReason::Error(e.to_string()),
+                },
+            }
+            if let Some(msg) = log {
+                return ExitReason::Log(msg);
+            }
+            if self.cpu.halted && self.cpu.interrupts_queue.is_empty() {
+                return ExitReason::Halted;
+            }
         }
     }
 
     pub fn tick(&mut self) -> Result<(), cpu::Error> {
-        try!(self.cpu.tick(&mut self.devices));
+        let res = self.cpu.tick(&mut self.devices);
+        self.record_step();
+        try!(res);
 
+        let mut skipped = 0u64;
         for device in &mut self.devices {
             match try!(device.tick(&mut self.cpu, self.current_tick)) {
                 TickResult::Nothing => (),
                 TickResult::Interrupt(msg) => self.cpu.hardware_interrupt(msg),
+                TickResult::SkipTime(ticks) => skipped = skipped.saturating_add(ticks),
             }
         }
 
         self.current_tick += 1;
+        if skipped != 0 {
+            self.skip_time(skipped);
+        }
         Ok(())
     }
+
+    /// Jump `ticks` ticks forward without executing instructions, like
+    /// during a SPC2000 sleep. The tick number stops at `device::MAX_TICK`.
+    pub fn skip_time(&mut self, ticks: u64) {
+        let ticks = device::clamp_skip(self.current_tick, ticks);
+        if ticks == 0 {
+            return;
+        }
+        for device in &mut self.devices {
+            device.skip_time(ticks);
+        }
+        self.current_tick += ticks;
+    }
+
+    fn record_step(&mut self) {
+        let mut steps = self.cpu.take_steps();
+        if let Some(ref mut profiler) = self.profiler {
+            profiler.tick(&self.cpu, &steps);
+        }
+        if let Some(ref mut coverage) = self.coverage {
+            for step in &steps {
+                coverage.record(&self.cpu, step);
+            }
+        }
+        let mut failed = None;
+        if let Some(ref mut tracer) = self.tracer {
+            for entry in &mut steps {
+                entry.tick = self.current_tick;
+                if let Err(e) = tracer.record(entry) {
+                    failed = Some(e);
+                    break;
+                }
+            }
+        }
+        if let Some(e) = failed {
+            error!("Error while writing the trace, tracing stopped: {}", e);
+            self.trace(None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::cpu::Cpu;
+    use emulator::device::spc2000::Spc2000;
+    use types::{BasicOp, Instruction, Register, SpecialOp, Value};
+
+    fn computer(ops: &[Instruction<u16>]) -> Computer {
+        let mut cpu = Cpu::default();
+        cpu.load_ops(ops, 0);
+        Computer::new(cpu, vec![])
+    }
+
+    #[test]
+    fn test_run() {
+        let set = Instruction::BasicOp(BasicOp::SET,
+                                       Value::Reg(Register::A),
+                                       Value::Litteral(1));
+        let conditions = ExitConditions::default();
+
+        let mut c = computer(&[set, Instruction::SpecialOp(SpecialOp::BRK,
+                                                           Value::Litteral(7))]);
+        assert_eq!(c.run(&conditions, |_| ()), ExitReason::Break(7));
+
+        let mut c = computer(&[set, Instruction::SpecialOp(SpecialOp::HLT,
+                                                           Value::Litteral(0))]);
+        assert_eq!(c.run(&conditions, |_| ()), ExitReason::Halted);
+
+        let mut c = computer(&[set, set, set]);
+        let conditions = ExitConditions {
+            max_ticks: Some(100),
+            pcs: vec![2],
+            logs: vec![],
+        };
+        assert_eq!(c.run(&conditions, |_| ()), ExitReason::Pc(2));
+        assert_eq!(c.run(&ExitConditions { pcs: vec![], ..conditions },
+                         |_| ()),
+                   ExitReason::TickLimit);
+    }
+
+    #[test]
+    fn test_skip_time() {
+        let set = |r, v| Instruction::BasicOp(BasicOp::SET,
+                                              Value::Reg(r),
+                                              Value::Litteral(v));
+        let hwi = Instruction::SpecialOp(SpecialOp::HWI, Value::Litteral(0));
+        let mut cpu = Cpu::default();
+        // Sleep for 2 minutes
+        cpu.load_ops(&[set(Register::A, 3), set(Register::B, 1), hwi,
+                       set(Register::A, 1), set(Register::B, 0x100), hwi,
+                       set(Register::A, 2), hwi],
+                     0);
+        cpu.ram.copy([0, 0, 0, 2].iter(), 0x100);
+        let devices: Vec<Box<Device>> = vec![Box::new(Spc2000::new(100_000))];
+        let mut c = Computer::new(cpu, devices);
+        let conditions = ExitConditions {
+            max_ticks: Some(1000),
+            ..ExitConditions::default()
+        };
+        assert_eq!(c.run(&conditions, |_| ()), ExitReason::TickLimit);
+        assert!(c.current_tick > 2 * 60 * 100_000);
+        assert!(c.current_tick < 2 * 60 * 100_000 + 100);
+    }
+
+    #[test]
+    fn test_skip_time_overflow() {
+        let set = |r, v| Instruction::BasicOp(BasicOp::SET,
+                                              Value::Reg(r),
+                                              Value::Litteral(v));
+        let hwi = Instruction::SpecialOp(SpecialOp::HWI, Value::Litteral(0));
+        let hlt = Instruction::SpecialOp(SpecialOp::HLT, Value::Litteral(0));
+        let mut cpu = Cpu::default();
+        // Sleep twice for the longest time
+        cpu.load_ops(&[set(Register::A, 3), set(Register::B, 3), hwi,
+                       set(Register::A, 1), set(Register::B, 0x100), hwi,
+                       set(Register::A, 2), hwi, hwi, hlt],
+                     0);
+        cpu.ram.copy([0xffff; 4].iter(), 0x100);
+        let devices: Vec<Box<Device>> = vec![Box::new(Spc2000::new(100_000))];
+        let mut c = Computer::new(cpu, devices);
+        assert_eq!(c.run(&ExitConditions::default(), |_| ()), ExitReason::Halted);
+        assert!(c.current_tick > device::MAX_TICK);
+        assert!(c.current_tick < device::MAX_TICK + 100);
+    }
 }
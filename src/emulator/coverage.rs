@@ -0,0 +1,257 @@
+//! Code coverage: how many times each word was executed and how many times
+//! each `IF*` instruction let the next instruction run (taken) or skipped it.
+//!
+//! The results can be written in the lcov format, using the line mapping of
+//! `assembler --debug-info`, or as a summary by label.
+
+use std::collections::HashMap;
+use std::io::{self, Write};
+
+use assembler::types::{DebugInfo, Globals};
+use emulator::cpu::Cpu;
+use emulator::trace::{Entry, Event};
+use types::Instruction;
+
+pub struct Coverage {
+    /// Number of executions of the instruction containing each word.
+    words: Vec<u64>,
+    /// Taken and skipped counts of the `IF*` instructions, by address.
+    branches: HashMap<u16, (u64, u64)>,
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    pub fn new() -> Coverage {
+        Coverage {
+            words: vec![0; 0x10000],
+            branches: HashMap::new(),
+        }
+    }
+
+    /// Record an executed instruction. `cpu` is the state after it. The
+    /// other trace entries are ignored.
+    pub fn record(&mut self, cpu: &Cpu, step: &Entry) {
+        let instruction = match step.event {
+            Event::Instruction(ref instruction) => instruction,
+            _ => return,
+        };
+        let len = instruction_len(instruction);
+        for i in 0..len {
+            self.words[step.pc.wrapping_add(i) as usize] += 1;
+        }
+        if instruction.is_if() {
+            let branch = self.branches.entry(step.pc).or_insert((0, 0));
+            if cpu.pc.0 == step.pc.wrapping_add(len) {
+                branch.0 += 1;
+            } else {
+                branch.1 += 1;
+            }
+        }
+    }
+
+    /// Number of executions of the word at `addr`.
+    pub fn hits(&self, addr: u16) -> u64 {
+        self.words[addr as usize]
+    }
+
+    /// Taken and skipped counts of the `IF*` at `addr`, if it was executed.
+    pub fn branch(&self, addr: u16) -> Option<(u64, u64)> {
+        self.branches.get(&addr).cloned()
+    }
+
+    /// Write an lcov tracefile for the lines of `debug_info`. `memory`
+    /// contains the program, to find the `IF*` never executed.
+    pub fn write_lcov<W: Write>(&self,
+                                output: &mut W,
+                                debug_info: &DebugInfo,
+                                globals: &Globals,
+                                memory: &[u16]) -> io::Result<()> {
+        try!(writeln!(output, "TN:"));
+        try!(writeln!(output,
+                      "SF:{}",
+                      debug_info.file.as_ref().map_or("", |f| f.as_str())));
+
+        // Labels of data are not functions
+        let mut functions: Vec<(usize, &str, u64)> =
+            globals.iter()
+                   .filter_map(|(name, infos)| {
+                       debug_info.lines
+                                 .iter()
+                                 .find(|l| l.code && l.addr == infos.addr)
+                                 .map(|l| {
+                                     (l.line, name.as_str(), self.hits(l.addr))
+                                 })
+                   })
+                   .collect();
+        functions.sort();
+        for &(line, name, _) in &functions {
+            try!(writeln!(output, "FN:{},{}", line, name));
+        }
+        for &(_, name, hits) in &functions {
+            try!(writeln!(output, "FNDA:{},{}", hits, name));
+        }
+        try!(writeln!(output, "FNF:{}", functions.len()));
+        try!(writeln!(output,
+                      "FNH:{}",
+                      functions.iter().filter(|f| f.2 > 0).count()));
+
+        let code: Vec<_> = debug_info.lines.iter().filter(|l| l.code).collect();
+        let mut branches = 0;
+        let mut branches_hit = 0;
+        for line in &code {
+            let is_if = decode(memory, line.addr).map_or(false, |i| i.is_if());
+            if !is_if {
+                continue;
+            }
+            branches += 2;
+            match self.branch(line.addr) {
+                Some((taken, skipped)) => {
+                    try!(writeln!(output, "BRDA:{},0,0,{}", line.line, taken));
+                    try!(writeln!(output, "BRDA:{},0,1,{}", line.line, skipped));
+                    branches_hit += (taken > 0) as usize + (skipped > 0) as usize;
+                }
+                None => {
+                    try!(writeln!(output, "BRDA:{},0,0,-", line.line));
+                    try!(writeln!(output, "BRDA:{},0,1,-", line.line));
+                }
+            }
+        }
+        try!(writeln!(output, "BRF:{}", branches));
+        try!(writeln!(output, "BRH:{}", branches_hit));
+
+        for line in &code {
+            try!(writeln!(output, "DA:{},{}", line.line, self.hits(line.addr)));
+        }
+        try!(writeln!(output, "LF:{}", code.len()));
+        try!(writeln!(output,
+                      "LH:{}",
+                      code.iter().filter(|l| self.hits(l.addr) > 0).count()));
+        writeln!(output, "end_of_record")
+    }
+
+    /// Write the number of executed words between each label and the next
+    /// one, `memory` being the program.
+    pub fn write_summary<W: Write>(&self,
+                                   output: &mut W,
+                                   globals: &Globals,
+                                   memory: &[u16]) -> io::Result<()> {
+        let end = memory.len();
+        let mut labels: Vec<(usize, &str)> =
+            globals.iter()
+                   .map(|(name, infos)| (infos.addr as usize, name.as_str()))
+                   .filter(|&(addr, _)| addr < end)
+                   .collect();
+        labels.sort();
+        // Labels sharing an address cover the same range
+        labels.dedup_by_key(|l| l.0);
+
+        let executed = |from: usize, to: usize| {
+            self.words[from..to].iter().filter(|&&n| n > 0).count()
+        };
+        let total = executed(0, end);
+        try!(writeln!(output,
+                      "Executed {}/{} words ({:.2}%)",
+                      total,
+                      end,
+                      percent(total, end)));
+        try!(writeln!(output, ""));
+        for (i, &(addr, name)) in labels.iter().enumerate() {
+            let to = labels.get(i + 1).map_or(end, |l| l.0);
+            let n = executed(addr, to);
+            try!(writeln!(output,
+                          "{:>13} {:>6.2}%  {:04x} {}",
+                          format!("{}/{}", n, to - addr),
+                          percent(n, to - addr),
+                          addr,
+                          name));
+        }
+        Ok(())
+    }
+}
+
+fn percent(n: usize, total: usize) -> f64 {
+    if total == 0 {
+        0.
+    } else {
+        n as f64 * 100. / total as f64
+    }
+}
+
+fn instruction_len(instruction: &Instruction<u16>) -> u16 {
+    instruction.encode(&mut [0; 3])
+}
+
+fn decode(memory: &[u16], addr: u16) -> Option<Instruction<u16>> {
+    let word = |i: u16| {
+        memory.get(addr.wrapping_add(i) as usize).cloned().unwrap_or(0)
+    };
+    Instruction::decode(&[word(0), word(1), word(2)]).ok().map(|(_, i)| i)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::types::{DebugInfo, Globals, LabelInfos, LineInfo};
+    use emulator::{Computer, ExitConditions, ExitReason};
+    use types::{BasicOp, Register, SpecialOp, Value};
+
+    #[test]
+    fn test_lcov() {
+        // main: IFE A, 0; SET A, 1; IFN A, 1; SET A, 2; BRK 0
+        let ops = [Instruction::BasicOp(BasicOp::IFE,
+                                        Value::Reg(Register::A),
+                                        Value::Litteral(0)),
+                   Instruction::BasicOp(BasicOp::SET,
+                                        Value::Reg(Register::A),
+                                        Value::Litteral(1)),
+                   Instruction::BasicOp(BasicOp::IFN,
+                                        Value::Reg(Register::A),
+                                        Value::Litteral(1)),
+                   Instruction::BasicOp(BasicOp::SET,
+                                        Value::Reg(Register::A),
+                                        Value::Litteral(2)),
+                   Instruction::SpecialOp(SpecialOp::BRK, Value::Litteral(0))];
+        let mut cpu = Cpu::default();
+        cpu.load_ops(&ops, 0);
+        cpu.registers[Register::A] = 0;
+        let memory: Vec<u16> = cpu.ram.iter().take(5).cloned().collect();
+        let mut computer = Computer::new(cpu, vec![]);
+        computer.coverage(Some(Coverage::new()));
+        assert_eq!(computer.run(&ExitConditions::default(), |_| ()),
+                   ExitReason::Break(0));
+        let coverage = computer.coverage(None).unwrap();
+
+        let mut debug_info = DebugInfo {
+            file: Some("main.asm".into()),
+            lines: (0..5).map(|i| LineInfo {
+                line: i as usize + 2,
+                addr: i,
+                size: 1,
+                code: true,
+            }).collect(),
+        };
+        debug_info.lines.push(LineInfo {
+            line: 8,
+            addr: 5,
+            size: 1,
+            code: false,
+        });
+        let mut globals = Globals::new();
+        globals.insert("main".into(), LabelInfos::default());
+
+        let mut output = vec![];
+        coverage.write_lcov(&mut output, &debug_info, &globals, &memory)
+                .unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   "TN:\nSF:main.asm\nFN:2,main\nFNDA:1,main\nFNF:1\nFNH:1\n\
+                    BRDA:2,0,0,1\nBRDA:2,0,1,0\nBRDA:4,0,0,0\nBRDA:4,0,1,1\n\
+                    BRF:4\nBRH:2\n\
+                    DA:2,1\nDA:3,1\nDA:4,1\nDA:5,0\nDA:6,1\nLF:5\nLH:4\n\
+                    end_of_record\n");
+    }
+}
@@ -1,11 +1,13 @@
 use std::cmp::max;
 use std::collections::VecDeque;
 use std::default::Default;
+use std::mem;
 use std::num::Wrapping;
 
-use emulator::device::{self, Device};
+use emulator::device::{self, Device, OtherDevices};
 use emulator::Ram;
 use emulator::Registers;
+use emulator::trace;
 use types::*;
 use types::Value::*;
 use types::BasicOp::*;
@@ -62,6 +64,12 @@ pub struct Cpu {
     pub interrupts_queue: VecDeque<u16>,
     pub log_queue: VecDeque<u16>,
     pub halted: bool,
+    /// Record the effects of each executed instruction, see `take_steps`.
+    pub trace: bool,
+    /// Memory written since the beginning of the tick, or by the devices
+    /// since the last tick.
+    writes: Vec<(u16, u16)>,
+    steps: Vec<trace::Entry>,
 }
 
 impl Default for Cpu {
@@ -79,6 +87,9 @@ impl Default for Cpu {
             interrupts_queue: VecDeque::new(),
             log_queue: VecDeque::new(),
             halted: false,
+            trace: false,
+            writes: vec![],
+            steps: vec![],
         }
     }
 }
@@ -90,6 +101,29 @@ impl Cpu {
         cpu
     }
 
+    /// Reset the registers, interrupt state and queues. The ram is kept.
+    pub fn reset(&mut self) {
+        self.registers = Registers::default();
+        self.pc = Wrapping(0);
+        self.sp = Wrapping(0xffff);
+        self.ex = 0;
+        self.ia = 0;
+        self.wait = 0;
+        self.is_queue_enabled = false;
+        self.interrupts_queue.clear();
+        self.log_queue.clear();
+        self.halted = false;
+        self.writes.clear();
+        self.steps.clear();
+    }
+
+    /// Reset the cpu and replace the whole ram content with `rom`.
+    pub fn power_cycle(&mut self, rom: &[u16]) {
+        self.reset();
+        self.ram = Ram::default();
+        self.load(rom, 0);
+    }
+
     pub fn load(&mut self, data: &[u16], offset: u16) {
         self.ram.copy(data.iter(), offset);
     }
@@ -143,25 +177,86 @@ impl Cpu {
     fn set(&mut self, i: Value<u16>, val: u16) {
         match i {
             Reg(r) => self.registers[r] = val,
-            AtReg(r) => self.ram[self.registers[r]] = val,
+            AtReg(r) => {
+                let i = self.registers[r];
+                self.write(i, val);
+            }
             AtRegPlus(r, off) => {
                 let i = off.wrapping_add(self.get(Reg(r)));
-                self.ram[i] = val;
+                self.write(i, val);
             }
             Push => {
                 self.sp -= Wrapping(1);
-                self.ram[self.sp] = val;
+                let sp = self.sp.0;
+                self.write(sp, val);
             },
-            Peek => self.ram[self.sp] = val,
-            Pick(n) => self.ram[self.sp + Wrapping(n)] = val,
+            Peek => {
+                let sp = self.sp.0;
+                self.write(sp, val);
+            }
+            Pick(n) => {
+                let i = (self.sp + Wrapping(n)).0;
+                self.write(i, val);
+            }
             SP => self.sp = Wrapping(val),
             PC => self.pc = Wrapping(val),
             EX => self.ex = val,
-            AtAddr(off) => self.ram[off] = val,
+            AtAddr(off) => self.write(off, val),
             Litteral(_) => ()
         }
     }
 
+    /// Write `val` at `addr`, recording it in the trace. Devices writing the
+    /// ram should use this or `copy`.
+    pub fn write(&mut self, addr: u16, val: u16) {
+        self.ram[addr] = val;
+        if self.trace {
+            self.writes.push((addr, val));
+        }
+    }
+
+    /// Copy `items` to the ram starting at `addr`, wrapping around, and
+    /// record them in the trace.
+    pub fn copy<'a, T: Iterator<Item=&'a u16>>(&mut self, items: T, addr: u16) {
+        if self.trace {
+            for (i, &val) in items.take(self.ram.len()).enumerate() {
+                self.write(addr.wrapping_add(i as u16), val);
+            }
+        } else {
+            self.ram.copy(items, addr);
+        }
+    }
+
+    fn trace_registers(&self) -> [u16; 11] {
+        let r = &self.registers;
+        [r[Register::A], r[Register::B], r[Register::C],
+         r[Register::X], r[Register::Y], r[Register::Z],
+         r[Register::I], r[Register::J],
+         self.sp.0, self.ex, self.ia]
+    }
+
+    /// Effects of the last tick, if `trace` is set: the memory written by
+    /// the devices before it, the interrupt dispatched and the instruction
+    /// executed, each of them being optional. The tick numbers are left to 0.
+    pub fn take_steps(&mut self) -> Vec<trace::Entry> {
+        mem::replace(&mut self.steps, vec![])
+    }
+
+    fn push_step(&mut self, event: trace::Event, pc: u16, before: &[u16; 11]) {
+        let after = self.trace_registers();
+        self.steps.push(trace::Entry {
+            tick: 0,
+            pc: pc,
+            event: event,
+            registers: trace::REGISTERS.iter()
+                                       .zip(before.iter().zip(after.iter()))
+                                       .filter(|&(_, (b, a))| b != a)
+                                       .map(|(r, (_, a))| (*r, *a))
+                                       .collect(),
+            memory: mem::replace(&mut self.writes, vec![]),
+        });
+    }
+
     pub fn tick(&mut self, devices: &mut [Box<Device>]) -> Result<CpuState> {
         if self.wait != 0 {
             self.wait -= 1;
@@ -169,10 +264,27 @@ impl Cpu {
             return Ok(CpuState::Waiting);
         }
 
+        let mut before = None;
+        if self.trace {
+            let registers = self.trace_registers();
+            if !self.writes.is_empty() {
+                let pc = self.pc.0;
+                self.push_step(trace::Event::Devices, pc, &registers);
+            }
+            before = Some(registers);
+        }
+
         if !self.is_queue_enabled {
             if let Some(interrupt) = self.interrupts_queue.pop_front() {
+                let pc = self.pc.0;
                 self.exec_interrupt(interrupt);
                 self.halted = false;
+                if let Some(ref mut before) = before {
+                    if self.ia != 0 {
+                        self.push_step(trace::Event::Interrupt(interrupt), pc, before);
+                        *before = self.trace_registers();
+                    }
+                }
             }
         }
 
@@ -200,7 +312,11 @@ impl Cpu {
         debug!("Executing {}", instruction);
         // BRK and HLT have a 0 delay
         self.wait = max(instruction.delay(), 1) - 1;
-        try!(self.op(instruction, devices));
+        let res = self.op(instruction, devices);
+        if let Some(ref before) = before {
+            self.push_step(trace::Event::Instruction(instruction), pc.0, before);
+        }
+        try!(res);
 
         if self.halted {
             Ok(CpuState::Halted)
@@ -620,8 +736,8 @@ impl Cpu {
     fn op_hwi(&mut self, a: Value<u16>, devices: &mut [Box<Device>]) -> Result<()> {
         let val_a = self.get(a) as usize;
 
-        if let Some(device) = devices.get_mut(val_a) {
-            self.wait += try!(device.interrupt(self));
+        if let Some((device, mut others)) = OtherDevices::split(devices, val_a) {
+            self.wait += try!(device.interrupt_with_devices(self, &mut others));
             Ok(())
         } else {
             Err(ErrorKind::InvalidHardwareId(val_a as u16).into())
@@ -645,3 +761,55 @@ impl Cpu {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::device::m35fd::{Floppy, M35fd};
+    use emulator::trace::{Event, RegisterId};
+
+    #[test]
+    fn test_trace_events() {
+        let set = |b, a| Instruction::BasicOp(SET, b, Litteral(a));
+        let hwi = Instruction::SpecialOp(HWI, Litteral(0));
+        let int = Instruction::SpecialOp(INT, Litteral(5));
+        let mut cpu = Cpu::default();
+        // Read sector 0 to 0x1000, without seeking, and interrupt itself
+        cpu.load_ops(&[Instruction::SpecialOp(IAS, Litteral(0x20)),
+                       set(Reg(Register::A), 2),
+                       set(Reg(Register::X), 0),
+                       set(Reg(Register::Y), 0x1000),
+                       hwi,
+                       int,
+                       set(PC, 8)],
+                     0);
+        cpu.load_ops(&[set(PC, 0x20)], 0x20);
+        cpu.trace = true;
+        let mut devices: Vec<Box<Device>> = vec![Box::new(M35fd::new(Floppy::default()))];
+
+        let mut steps = vec![];
+        for tick in 0..20 {
+            cpu.tick(&mut devices).unwrap();
+            steps.extend(cpu.take_steps());
+            devices[0].tick(&mut cpu, tick).unwrap();
+        }
+        let position = |event| steps.iter().position(|s| s.event == event).unwrap();
+
+        let i = position(Event::Interrupt(5));
+        assert_eq!(steps[i].pc, 8);
+        assert_eq!(steps[i].registers, [(RegisterId::A, 5), (RegisterId::SP, 0xfffd)]);
+        assert_eq!(steps[i].memory, [(0xfffe, 8), (0xfffd, 2)]);
+        assert_eq!(steps[i + 1].event, Event::Instruction(set(PC, 0x20)));
+        assert_eq!(steps[i + 1].pc, 0x20);
+        assert!(steps[i + 1].memory.is_empty());
+
+        // The sector is read after HWI, before the next instruction
+        assert!(steps[position(Event::Instruction(hwi))].memory.is_empty());
+        let i = position(Event::Devices);
+        assert_eq!(steps[i + 1].event, Event::Instruction(int));
+        assert_eq!(steps[i].pc, 7);
+        assert!(steps[i].registers.is_empty());
+        assert_eq!(steps[i].memory.len(), 512);
+        assert_eq!(steps[i].memory[0], (0x1000, 0));
+    }
+}
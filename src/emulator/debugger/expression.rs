@@ -0,0 +1,468 @@
+//! Debugger expressions.
+//!
+//! Unlike the assembler expressions, they can read the registers (`A`, `PC`,
+//! `SP`, `EX`, `IA`...) and the memory (`[A + 2]`). `(signed)`, `(unsigned)`
+//! and `(char)` casts change how the value is interpreted and printed.
+
+use std::char;
+use std::str::FromStr;
+
+use nom::{multispace, IResult};
+
+use assembler::parser::nom_parser::{pos_number, raw_label, raw_local_label};
+use assembler::types::Globals;
+use emulator::cpu::Cpu;
+use types::Register;
+
+error_chain! {
+    errors {
+        Parse(e: String) {
+            description("invalid expression")
+            display("invalid expression: \"{}\"", e)
+        }
+        UnknownFormat(f: String) {
+            description("unknown format")
+            display("unknown format: \"{}\"", f)
+        }
+        UnknownLabel(l: String) {
+            description("unknown label")
+            display("unknown label: \"{}\"", l)
+        }
+        UnknownLocalLabel(l: String) {
+            description("unknown local label")
+            display("unknown local label: \"{}\"", l)
+        }
+        DivisionByZero {
+            description("division by zero")
+            display("division by zero")
+        }
+    }
+}
+
+/// Maximum number of characters printed with `/s`.
+const MAX_STR_LEN: usize = 256;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Expr {
+    Num(u16),
+    Register(Register),
+    PC,
+    SP,
+    EX,
+    IA,
+    Label(String),
+    LocalLabel(String),
+    /// `[e]`
+    Deref(Box<Expr>),
+    Cast(Kind, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Kind {
+    Unsigned,
+    Signed,
+    Char,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UnaryOp {
+    Neg,
+    Not,
+    BitNot,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinaryOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Shl,
+    Shr,
+    And,
+    Or,
+    Xor,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Equal,
+    NotEqual,
+}
+
+/// How to print a value.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// Depends on the value kind.
+    Natural,
+    Hex,
+    Decimal,
+    /// Null-terminated string at this address.
+    Str,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypedValue {
+    pub value: u16,
+    pub kind: Kind,
+}
+
+pub fn parse(s: &str) -> Result<Expr> {
+    match expression(s.trim().as_bytes()) {
+        IResult::Done(i, e) if i.is_empty() => Ok(e),
+        _ => try!(Err(ErrorKind::Parse(s.into()))),
+    }
+}
+
+impl FromStr for Format {
+    type Err = Error;
+
+    /// Parse `x`, `d` or `s`.
+    fn from_str(s: &str) -> Result<Format> {
+        match s {
+            "x" => Ok(Format::Hex),
+            "d" => Ok(Format::Decimal),
+            "s" => Ok(Format::Str),
+            _ => try!(Err(ErrorKind::UnknownFormat(s.into()))),
+        }
+    }
+}
+
+impl Expr {
+    pub fn eval(&self,
+                cpu: &Cpu,
+                globals: &Globals,
+                last_global: &Option<String>) -> Result<TypedValue> {
+        let value = match *self {
+            Expr::Num(n) => n,
+            Expr::Register(r) => cpu.registers[r],
+            Expr::PC => cpu.pc.0,
+            Expr::SP => cpu.sp.0,
+            Expr::EX => cpu.ex,
+            Expr::IA => cpu.ia,
+            Expr::Label(ref l) => match globals.get(l) {
+                Some(infos) => infos.addr,
+                None => try!(Err(ErrorKind::UnknownLabel(l.clone()))),
+            },
+            Expr::LocalLabel(ref l) => {
+                let addr = last_global.as_ref()
+                                      .and_then(|g| globals.get(g))
+                                      .and_then(|infos| infos.locals.get(l));
+                match addr {
+                    Some(addr) => *addr,
+                    None => try!(Err(ErrorKind::UnknownLocalLabel(l.clone()))),
+                }
+            }
+            Expr::Deref(ref e) => {
+                cpu.ram[try!(e.eval(cpu, globals, last_global)).value]
+            }
+            Expr::Cast(kind, ref e) => {
+                let v = try!(e.eval(cpu, globals, last_global));
+                return Ok(TypedValue {
+                    value: v.value,
+                    kind: kind,
+                });
+            }
+            Expr::Unary(op, ref e) => {
+                let v = try!(e.eval(cpu, globals, last_global));
+                return Ok(match op {
+                    UnaryOp::Neg => TypedValue {
+                        value: 0u16.wrapping_sub(v.value),
+                        kind: v.kind,
+                    },
+                    UnaryOp::Not => TypedValue::unsigned((v.value == 0) as u16),
+                    UnaryOp::BitNot => TypedValue {
+                        value: !v.value,
+                        kind: v.kind,
+                    },
+                });
+            }
+            Expr::Binary(op, ref a, ref b) => {
+                let a = try!(a.eval(cpu, globals, last_global));
+                let b = try!(b.eval(cpu, globals, last_global));
+                return eval_binary(op, a, b);
+            }
+        };
+        Ok(TypedValue::unsigned(value))
+    }
+}
+
+fn eval_binary(op: BinaryOp, a: TypedValue, b: TypedValue) -> Result<TypedValue> {
+    let signed = a.kind == Kind::Signed || b.kind == Kind::Signed;
+    let (x, y) = (a.value, b.value);
+    let (sx, sy) = (x as i16, y as i16);
+    let value = match op {
+        BinaryOp::Add => x.wrapping_add(y),
+        BinaryOp::Sub => x.wrapping_sub(y),
+        BinaryOp::Mul => x.wrapping_mul(y),
+        BinaryOp::Div | BinaryOp::Mod if y == 0 => {
+            try!(Err(ErrorKind::DivisionByZero))
+        }
+        BinaryOp::Div if signed => sx.wrapping_div(sy) as u16,
+        BinaryOp::Div => x / y,
+        BinaryOp::Mod if signed => sx.wrapping_rem(sy) as u16,
+        BinaryOp::Mod => x % y,
+        BinaryOp::Shl => x.checked_shl(y as u32).unwrap_or(0),
+        BinaryOp::Shr if signed => {
+            sx.checked_shr(y as u32).unwrap_or(if sx < 0 { -1 } else { 0 }) as u16
+        }
+        BinaryOp::Shr => x.checked_shr(y as u32).unwrap_or(0),
+        BinaryOp::And => x & y,
+        BinaryOp::Or => x | y,
+        BinaryOp::Xor => x ^ y,
+        BinaryOp::Less => return Ok(bool_value(if signed { sx < sy } else { x < y })),
+        BinaryOp::LessEqual =>
+            return Ok(bool_value(if signed { sx <= sy } else { x <= y })),
+        BinaryOp::Greater =>
+            return Ok(bool_value(if signed { sx > sy } else { x > y })),
+        BinaryOp::GreaterEqual =>
+            return Ok(bool_value(if signed { sx >= sy } else { x >= y })),
+        BinaryOp::Equal => return Ok(bool_value(x == y)),
+        BinaryOp::NotEqual => return Ok(bool_value(x != y)),
+    };
+    Ok(TypedValue {
+        value: value,
+        kind: if signed { Kind::Signed } else { Kind::Unsigned },
+    })
+}
+
+fn bool_value(b: bool) -> TypedValue {
+    TypedValue::unsigned(b as u16)
+}
+
+impl TypedValue {
+    pub fn unsigned(value: u16) -> TypedValue {
+        TypedValue {
+            value: value,
+            kind: Kind::Unsigned,
+        }
+    }
+
+    pub fn format(&self, format: Format, cpu: &Cpu) -> String {
+        match (format, self.kind) {
+            (Format::Hex, _) => format!("0x{:0>4x}", self.value),
+            (Format::Decimal, Kind::Signed) => format!("{}", self.value as i16),
+            (Format::Decimal, _) => format!("{}", self.value),
+            (Format::Str, _) => {
+                let s = cpu.get_str(self.value)
+                           .chars()
+                           .take(MAX_STR_LEN)
+                           .collect::<String>();
+                format!("0x{:0>4x} {:?}", self.value, s)
+            }
+            (Format::Natural, Kind::Unsigned) => {
+                format!("{} (0x{:0>4x})", self.value, self.value)
+            }
+            (Format::Natural, Kind::Signed) => {
+                format!("{} (0x{:0>4x})", self.value as i16, self.value)
+            }
+            (Format::Natural, Kind::Char) => {
+                let c = char::from_u32(self.value as u32).unwrap_or('?');
+                format!("{:?} (0x{:0>4x})", c, self.value)
+            }
+        }
+    }
+}
+
+fn identifier(name: String) -> Expr {
+    match &*name.to_uppercase() {
+        "PC" => return Expr::PC,
+        "SP" => return Expr::SP,
+        "EX" => return Expr::EX,
+        "IA" => return Expr::IA,
+        _ => (),
+    }
+    match Register::from_str(&name) {
+        Ok(r) => Expr::Register(r),
+        Err(_) => Expr::Label(name),
+    }
+}
+
+fn fold_binary(first: Expr, rest: Vec<(BinaryOp, Expr)>) -> Expr {
+    rest.into_iter().fold(first, |acc, (op, e)| {
+        Expr::Binary(op, Box::new(acc), Box::new(e))
+    })
+}
+
+named!(space<Option<&[u8]> >, opt!(complete!(multispace)));
+
+named!(kind<Kind>,
+    alt_complete!(
+        map!(tag!("signed"), |_| Kind::Signed) |
+        map!(tag!("unsigned"), |_| Kind::Unsigned) |
+        map!(tag!("char"), |_| Kind::Char)
+    )
+);
+
+named!(atom<Expr>,
+    alt_complete!(
+        map!(pos_number, Expr::Num) |
+        do_parse!(
+               char!('[')
+            >> space
+            >> e: expression
+            >> space
+            >> char!(']')
+            >> (Expr::Deref(Box::new(e)))
+        ) |
+        do_parse!(
+               char!('(')
+            >> space
+            >> e: expression
+            >> space
+            >> char!(')')
+            >> (e)
+        ) |
+        map!(raw_local_label, Expr::LocalLabel) |
+        map!(raw_label, identifier)
+    )
+);
+
+named!(unary<Expr>,
+    alt_complete!(
+        do_parse!(
+               char!('-')
+            >> space
+            >> e: unary
+            >> (Expr::Unary(UnaryOp::Neg, Box::new(e)))
+        ) |
+        do_parse!(
+               char!('!')
+            >> space
+            >> e: unary
+            >> (Expr::Unary(UnaryOp::Not, Box::new(e)))
+        ) |
+        do_parse!(
+               char!('~')
+            >> space
+            >> e: unary
+            >> (Expr::Unary(UnaryOp::BitNot, Box::new(e)))
+        ) |
+        do_parse!(
+               char!('(')
+            >> space
+            >> k: kind
+            >> space
+            >> char!(')')
+            >> space
+            >> e: unary
+            >> (Expr::Cast(k, Box::new(e)))
+        ) |
+        atom
+    )
+);
+
+named!(product<Expr>,
+    do_parse!(
+           first: unary
+        >> rest: many0!(complete!(do_parse!(
+                  space
+               >> op: alt_complete!(
+                      map!(char!('*'), |_| BinaryOp::Mul) |
+                      map!(char!('/'), |_| BinaryOp::Div) |
+                      map!(char!('%'), |_| BinaryOp::Mod)
+                  )
+               >> space
+               >> e: unary
+               >> ((op, e))
+           )))
+        >> (fold_binary(first, rest))
+    )
+);
+
+named!(sum<Expr>,
+    do_parse!(
+           first: product
+        >> rest: many0!(complete!(do_parse!(
+                  space
+               >> op: alt_complete!(
+                      map!(char!('+'), |_| BinaryOp::Add) |
+                      map!(char!('-'), |_| BinaryOp::Sub)
+                  )
+               >> space
+               >> e: product
+               >> ((op, e))
+           )))
+        >> (fold_binary(first, rest))
+    )
+);
+
+named!(shift<Expr>,
+    do_parse!(
+           first: sum
+        >> rest: many0!(complete!(do_parse!(
+                  space
+               >> op: alt_complete!(
+                      map!(tag!("<<"), |_| BinaryOp::Shl) |
+                      map!(tag!(">>"), |_| BinaryOp::Shr)
+                  )
+               >> space
+               >> e: sum
+               >> ((op, e))
+           )))
+        >> (fold_binary(first, rest))
+    )
+);
+
+named!(comparison<Expr>,
+    do_parse!(
+           first: shift
+        >> rest: many0!(complete!(do_parse!(
+                  space
+               >> op: alt_complete!(
+                      map!(tag!("=="), |_| BinaryOp::Equal) |
+                      map!(tag!("!="), |_| BinaryOp::NotEqual) |
+                      map!(tag!("<="), |_| BinaryOp::LessEqual) |
+                      map!(tag!(">="), |_| BinaryOp::GreaterEqual) |
+                      map!(char!('<'), |_| BinaryOp::Less) |
+                      map!(char!('>'), |_| BinaryOp::Greater)
+                  )
+               >> space
+               >> e: shift
+               >> ((op, e))
+           )))
+        >> (fold_binary(first, rest))
+    )
+);
+
+named!(pub expression<Expr>,
+    do_parse!(
+           first: comparison
+        >> rest: many0!(complete!(do_parse!(
+                  space
+               >> op: alt_complete!(
+                      map!(char!('&'), |_| BinaryOp::And) |
+                      map!(char!('|'), |_| BinaryOp::Or) |
+                      map!(char!('^'), |_| BinaryOp::Xor)
+                  )
+               >> space
+               >> e: comparison
+               >> ((op, e))
+           )))
+        >> (fold_binary(first, rest))
+    )
+);
+
+#[cfg(test)]
+#[test]
+fn test_eval() {
+    use std::num::Wrapping;
+
+    let mut cpu = Cpu::default();
+    cpu.registers[Register::A] = 0x10;
+    cpu.sp = Wrapping(0xfffe);
+    cpu.ram[0x12] = 0xfffd;
+    let globals = Globals::default();
+    let eval = |s: &str| parse(s).unwrap().eval(&cpu, &globals, &None).unwrap();
+
+    assert_eq!(eval("1 + 2 * 3").value, 7);
+    assert_eq!(eval("10 - 2 - 3").value, 5);
+    assert_eq!(eval("[A + 2]").value, 0xfffd);
+    assert_eq!(eval("(signed)[a+2] / 3"),
+               TypedValue { value: 0xffff, kind: Kind::Signed });
+    assert_eq!(eval("SP == 0xfffe & !0").value, 1);
+    assert!(parse("A +").is_err());
+}
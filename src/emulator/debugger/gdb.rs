@@ -0,0 +1,452 @@
+//! GDB remote serial protocol stub.
+//!
+//! The DCPU is word-addressed, so every address and length in the memory and
+//! breakpoint packets is expressed in 16 bits words. Words and registers are
+//! encoded as 4 hexadecimal digits in little-endian order, like the binary
+//! files.
+//!
+//! Registers are numbered as follows: A, B, C, X, Y, Z, I, J, PC, SP, EX, IA.
+//!
+//! Supported packets: `?`, `g`, `G`, `p`, `P`, `m`, `M`, `c`, `s`, `Z0`/`z0`
+//! and `Z1`/`z1` (breakpoints), `Z2`/`z2` (write watchpoints on each word of
+//! the range), `D`, `k` and the usual `q` queries. Sending `0x03` while the
+//! CPU runs interrupts it. The `m` replies are truncated to fit in a packet.
+
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream, ToSocketAddrs};
+use std::num::Wrapping;
+use std::str;
+
+use enum_primitive::FromPrimitive;
+
+use assembler::types::{Expression, Num};
+use emulator::cpu;
+use emulator::debugger::{self, Debugger};
+use types::Register;
+
+error_chain! {
+    foreign_links {
+        Io(io::Error);
+    }
+}
+
+const NB_REGISTERS: u16 = 12;
+/// Largest packet accepted and sent, including the `$` and the checksum.
+const PACKET_SIZE: usize = 0x1000;
+/// Words of the longest `m` reply.
+const MAX_READ_WORDS: usize = (PACKET_SIZE - 4) / 4;
+/// Number of instructions executed between two checks for an interruption.
+const POLL_INTERVAL: u32 = 4096;
+
+/// Wait for one client on `addr`, then serve it until it detaches.
+pub fn serve<A: ToSocketAddrs>(debugger: &mut Debugger, addr: A) -> Result<()> {
+    let listener = try!(TcpListener::bind(addr));
+    info!("Waiting for a GDB connection on {}",
+          try!(listener.local_addr()));
+    let (stream, client) = try!(listener.accept());
+    info!("GDB client connected from {}", client);
+    let mut stub = try!(GdbStub::new(debugger, stream));
+    stub.run()
+}
+
+enum Reply {
+    Packet(String),
+    /// `D`: reply `OK` and close the connection.
+    Detach,
+    /// `k`: close the connection without a reply.
+    Kill,
+}
+
+pub struct GdbStub<'a> {
+    debugger: &'a mut Debugger,
+    reader: BufReader<TcpStream>,
+    ack: bool,
+}
+
+impl<'a> GdbStub<'a> {
+    pub fn new(debugger: &'a mut Debugger,
+               stream: TcpStream) -> Result<GdbStub<'a>> {
+        try!(stream.set_nodelay(true));
+        Ok(GdbStub {
+            debugger: debugger,
+            reader: BufReader::new(stream),
+            ack: true,
+        })
+    }
+
+    pub fn run(&mut self) -> Result<()> {
+        while let Some(packet) = try!(self.read_packet()) {
+            match try!(self.handle(&packet)) {
+                Reply::Packet(reply) => try!(self.send(&reply)),
+                Reply::Detach => {
+                    try!(self.send("OK"));
+                    break;
+                }
+                Reply::Kill => break,
+            }
+            if packet == "QStartNoAckMode" {
+                self.ack = false;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return the next valid packet, `None` when the connection is closed.
+    fn read_packet(&mut self) -> Result<Option<String>> {
+        loop {
+            let mut garbage = vec![];
+            if try!(self.reader.read_until(b'$', &mut garbage)) == 0 {
+                return Ok(None);
+            }
+            let mut data = vec![];
+            try!(self.reader.read_until(b'#', &mut data));
+            let mut checksum = [0; 2];
+            try!(io::Read::read_exact(&mut self.reader, &mut checksum));
+            data.pop();
+
+            let expected = str::from_utf8(&checksum)
+                               .ok()
+                               .and_then(|c| u8::from_str_radix(c, 16).ok());
+            if expected == Some(checksum_of(&data)) {
+                if self.ack {
+                    try!(self.reader.get_mut().write_all(b"+"));
+                }
+                match String::from_utf8(data) {
+                    Ok(packet) => return Ok(Some(packet)),
+                    Err(_) => try!(self.send("E01")),
+                }
+            } else if self.ack {
+                try!(self.reader.get_mut().write_all(b"-"));
+            }
+        }
+    }
+
+    fn send(&mut self, data: &str) -> Result<()> {
+        let packet = format!("${}#{:0>2x}", data, checksum_of(data.as_bytes()));
+        try!(self.reader.get_mut().write_all(packet.as_bytes()));
+        if self.ack {
+            let mut ack = [0];
+            try!(io::Read::read_exact(&mut self.reader, &mut ack));
+            if ack[0] == b'-' {
+                return self.send(data);
+            }
+        }
+        Ok(())
+    }
+
+    fn handle(&mut self, packet: &str) -> Result<Reply> {
+        let (kind, args) = packet.split_at(if packet.is_empty() { 0 } else { 1 });
+        Ok(Reply::Packet(match kind {
+            "?" => "S05".into(),
+            "g" => {
+                (0..NB_REGISTERS).map(|r| encode_word(self.register(r)))
+                                 .collect()
+            }
+            "G" => match decode_words(args) {
+                Some(ref values) if values.len() == NB_REGISTERS as usize => {
+                    for (r, v) in values.iter().enumerate() {
+                        self.set_register(r as u16, *v);
+                    }
+                    "OK".into()
+                }
+                _ => "E01".into(),
+            },
+            "p" => match u16::from_str_radix(args, 16) {
+                Ok(r) if r < NB_REGISTERS => encode_word(self.register(r)),
+                _ => "E01".into(),
+            },
+            "P" => {
+                let mut parts = args.splitn(2, '=');
+                let reg = parts.next()
+                               .and_then(|r| u16::from_str_radix(r, 16).ok());
+                let value = parts.next()
+                                 .and_then(decode_words)
+                                 .and_then(|v| v.first().cloned());
+                match (reg, value) {
+                    (Some(r), Some(v)) if r < NB_REGISTERS => {
+                        self.set_register(r, v);
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "m" => match parse_addr_len(args) {
+                Some((addr, len)) => {
+                    let mut reply = String::new();
+                    for word in self.debugger
+                                    .cpu
+                                    .ram
+                                    .iter_wrap(addr)
+                                    .take(::std::cmp::min(len as usize,
+                                                          MAX_READ_WORDS)) {
+                        reply.push_str(&encode_word(*word));
+                    }
+                    reply
+                }
+                None => "E01".into(),
+            },
+            "M" => {
+                let mut parts = args.splitn(2, ':');
+                match (parts.next().and_then(parse_addr_len),
+                       parts.next().and_then(decode_words)) {
+                    (Some((addr, len)), Some(ref words))
+                        if words.len() == len as usize => {
+                        self.debugger.cpu.ram.copy(words.iter(), addr);
+                        self.debugger.refresh_watchpoints();
+                        "OK".into()
+                    }
+                    _ => "E01".into(),
+                }
+            }
+            "c" => {
+                self.set_pc_from(args);
+                try!(self.continue_exec())
+            }
+            "s" => {
+                self.set_pc_from(args);
+                match self.debugger.step() {
+                    Ok(()) => "S05".into(),
+                    Err(e) => stop_reply(&e),
+                }
+            }
+            "Z" | "z" => self.handle_point(kind == "Z", args),
+            "q" => self.handle_query(args),
+            "Q" if args == "StartNoAckMode" => "OK".into(),
+            "H" | "T" => "OK".into(),
+            "D" => return Ok(Reply::Detach),
+            "k" => return Ok(Reply::Kill),
+            _ => String::new(),
+        }))
+    }
+
+    fn handle_query(&self, query: &str) -> String {
+        if query.starts_with("Supported") {
+            format!("PacketSize={:x};QStartNoAckMode+", PACKET_SIZE)
+        } else {
+            match query {
+                "Attached" => "1".into(),
+                "C" => "QC1".into(),
+                "fThreadInfo" => "m1".into(),
+                "sThreadInfo" => "l".into(),
+                _ => String::new(),
+            }
+        }
+    }
+
+    fn handle_point(&mut self, insert: bool, args: &str) -> String {
+        let mut parts = args.splitn(2, ',');
+        let kind = parts.next();
+        let range = parts.next().and_then(parse_addr_len);
+        match (kind, range) {
+            // The length of a breakpoint is the kind of instruction
+            (Some("0"), Some((addr, _))) | (Some("1"), Some((addr, _))) => {
+                if insert {
+                    let expr = Expression::Num(Num::U(addr));
+                    self.debugger.add_breakpoint(addr, expr);
+                    "OK".into()
+                } else if self.debugger.delete_breakpoint_at(addr) {
+                    "OK".into()
+                } else {
+                    "E01".into()
+                }
+            }
+            (Some("2"), Some((addr, len))) if len > 0 => {
+                let words = (0..len).map(|i| addr.wrapping_add(i));
+                if insert {
+                    for addr in words {
+                        self.debugger.add_watchpoint(addr);
+                    }
+                    "OK".into()
+                } else if words.fold(false, |found, addr| {
+                    self.debugger.delete_watchpoint_at(addr) || found
+                }) {
+                    "OK".into()
+                } else {
+                    "E01".into()
+                }
+            }
+            (Some("0"), _) | (Some("1"), _) | (Some("2"), _) |
+            (Some("3"), None) | (Some("4"), None) => "E01".into(),
+            // Read and access watchpoints are not supported
+            _ => String::new(),
+        }
+    }
+
+    /// Run until a stop condition, or until the client sends `0x03`.
+    fn continue_exec(&mut self) -> Result<String> {
+        loop {
+            for _ in 0..POLL_INTERVAL {
+                if let Err(e) = self.debugger.step_checked() {
+                    return Ok(stop_reply(&e));
+                }
+            }
+            if try!(self.interrupted()) {
+                return Ok("S02".into());
+            }
+        }
+    }
+
+    fn interrupted(&mut self) -> Result<bool> {
+        try!(self.reader.get_ref().set_nonblocking(true));
+        let res = match self.reader.fill_buf() {
+            Ok(buf) => Ok(buf.first() == Some(&0x03)),
+            Err(ref e) if e.kind() == io::ErrorKind::WouldBlock => Ok(false),
+            Err(e) => Err(e),
+        };
+        try!(self.reader.get_ref().set_nonblocking(false));
+        let interrupted = try!(res);
+        if interrupted {
+            self.reader.consume(1);
+        }
+        Ok(interrupted)
+    }
+
+    fn set_pc_from(&mut self, args: &str) {
+        if let Ok(addr) = u16::from_str_radix(args, 16) {
+            self.debugger.cpu.pc = Wrapping(addr);
+        }
+    }
+
+    fn register(&self, r: u16) -> u16 {
+        let cpu = &self.debugger.cpu;
+        match r {
+            8 => cpu.pc.0,
+            9 => cpu.sp.0,
+            10 => cpu.ex,
+            11 => cpu.ia,
+            r => cpu.registers[Register::from_u16(r).unwrap()],
+        }
+    }
+
+    fn set_register(&mut self, r: u16, value: u16) {
+        let cpu = &mut self.debugger.cpu;
+        match r {
+            8 => cpu.pc = Wrapping(value),
+            9 => cpu.sp = Wrapping(value),
+            10 => cpu.ex = value,
+            11 => cpu.ia = value,
+            r => cpu.registers[Register::from_u16(r).unwrap()] = value,
+        }
+    }
+}
+
+fn stop_reply(e: &debugger::Error) -> String {
+    match *e.kind() {
+        debugger::ErrorKind::Breakpoint(..) |
+        debugger::ErrorKind::Cpu(cpu::ErrorKind::Break(_)) => "S05".into(),
+        debugger::ErrorKind::Watchpoint(_, addr, _, _) =>
+            format!("T05watch:{:x};", addr),
+        _ => {
+            info!("Execution stopped: {}", e);
+            "S04".into()
+        }
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn encode_word(w: u16) -> String {
+    format!("{:0>2x}{:0>2x}", w & 0xff, w >> 8)
+}
+
+fn decode_words(hex: &str) -> Option<Vec<u16>> {
+    if hex.len() % 4 != 0 {
+        return None;
+    }
+    let mut words = vec![];
+    for i in 0..hex.len() / 4 {
+        let low = u16::from_str_radix(&hex[i * 4..i * 4 + 2], 16);
+        let high = u16::from_str_radix(&hex[i * 4 + 2..i * 4 + 4], 16);
+        match (low, high) {
+            (Ok(l), Ok(h)) => words.push(h << 8 | l),
+            _ => return None,
+        }
+    }
+    Some(words)
+}
+
+fn parse_addr_len(args: &str) -> Option<(u16, u16)> {
+    let mut parts = args.split(',');
+    match (parts.next().and_then(|a| u16::from_str_radix(a, 16).ok()),
+           parts.next().and_then(|l| u16::from_str_radix(l, 16).ok())) {
+        (Some(addr), Some(len)) => Some((addr, len)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_words() {
+    assert_eq!(encode_word(0x1234), "3412");
+    assert_eq!(decode_words("3412cdab"), Some(vec![0x1234, 0xabcd]));
+    assert_eq!(decode_words("341"), None);
+}
+
+#[cfg(test)]
+#[test]
+fn test_checksum() {
+    assert_eq!(checksum_of(b"OK"), 0x9a);
+}
+
+#[cfg(test)]
+#[test]
+fn test_parse_addr_len() {
+    assert_eq!(parse_addr_len("8000,10"), Some((0x8000, 0x10)));
+    assert_eq!(parse_addr_len("8000"), None);
+    assert_eq!(parse_addr_len("8000,x"), None);
+}
+
+#[cfg(test)]
+fn reply(stub: &mut GdbStub, packet: &str) -> String {
+    match stub.handle(packet).unwrap() {
+        Reply::Packet(reply) => reply,
+        Reply::Detach | Reply::Kill => panic!("{}: stopped", packet),
+    }
+}
+
+#[cfg(test)]
+#[test]
+fn test_memory_and_watchpoints() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let _client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+    let (stream, _) = listener.accept().unwrap();
+    let mut debugger = Debugger::new(cpu::Cpu::default(), vec![]);
+    let mut stub = GdbStub::new(&mut debugger, stream).unwrap();
+
+    assert_eq!(reply(&mut stub, "Z2,100,2"), "OK");
+    assert_eq!(reply(&mut stub, "M100,2:cdab3412"), "OK");
+    assert_eq!(stub.debugger
+                   .watchpoints()
+                   .iter()
+                   .map(|w| (w.addr, w.value))
+                   .collect::<Vec<_>>(),
+               [(0x100, 0xabcd), (0x101, 0x1234)]);
+    assert_eq!(reply(&mut stub, "m100,2"), "cdab3412");
+    assert_eq!(reply(&mut stub, "m0,ffff").len(), MAX_READ_WORDS * 4);
+
+    assert_eq!(reply(&mut stub, "Z2,100,0"), "E01");
+    assert_eq!(reply(&mut stub, "Z2,100"), "E01");
+    assert_eq!(reply(&mut stub, "Z3,100,1"), "");
+    assert_eq!(reply(&mut stub, "z2,100,2"), "OK");
+    assert_eq!(reply(&mut stub, "z2,100,2"), "E01");
+}
+
+#[cfg(test)]
+#[test]
+fn test_detach_and_kill() {
+    // The client acknowledges the reply in advance
+    for &(packet, expected) in &[("$D#44+", "+$OK#9a"), ("$k#6b", "+")] {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap()).unwrap();
+        let (stream, _) = listener.accept().unwrap();
+        client.write_all(packet.as_bytes()).unwrap();
+
+        let mut debugger = Debugger::new(cpu::Cpu::default(), vec![]);
+        GdbStub::new(&mut debugger, stream).unwrap().run().unwrap();
+        let mut received = String::new();
+        io::Read::read_to_string(&mut client, &mut received).unwrap();
+        assert_eq!(received, expected);
+    }
+}
@@ -1,14 +1,22 @@
 #[cfg(feature = "debugger-cli")]
 mod completion;
+pub mod expression;
+pub mod gdb;
+#[cfg(feature = "debugger-tui")]
+pub mod tui;
 #[cfg(feature = "debugger-cli")]
 mod parser;
 
 use std::collections::HashMap;
+use std::fmt;
 use std::iter::Iterator;
+use std::mem;
 #[cfg(feature = "debugger-cli")]
 use std::io;
 use std::num::Wrapping;
 #[cfg(feature = "debugger-cli")]
+use std::fs;
+#[cfg(feature = "debugger-cli")]
 use std::path::Path;
 
 #[cfg(feature = "debugger-cli")]
@@ -17,18 +25,57 @@ use colored::Colorize;
 use assembler;
 use assembler::types::Expression;
 use iterators;
-use emulator::{cpu, device};
+use emulator::{cpu, device, trace};
+use emulator::device::bbos;
+#[cfg(feature = "debugger-cli")]
+use emulator::device::record;
+#[cfg(feature = "debugger-cli")]
+use emulator::device::m35fd;
+#[cfg(feature = "debugger-cli")]
 use emulator::device::Device;
 #[cfg(feature = "debugger-cli")]
 use emulator::debugger::parser::Command;
-use types::Register;
+use types::{Instruction, Register, SpecialOp};
 
 error_chain! {
     links {
         Cpu(cpu::Error, cpu::ErrorKind);
         Device(device::Error, device::ErrorKind);
+        Expr(expression::Error, expression::ErrorKind);
     }
     errors {
+        InvalidCommand(e: String) {
+            description("invalid command")
+            display("{}", e)
+        }
+        InvalidExpression(e: String) {
+            description("invalid expression")
+            display("invalid expression: {}", e)
+        }
+        InvalidDisplay(i: u16) {
+            description("invalid display id")
+            display("invalid display id: {}", i)
+        }
+        InvalidDevice(i: u16) {
+            description("invalid device id")
+            display("invalid device id: {}", i)
+        }
+        WrongDevice(i: u16, expected: String) {
+            description("unexpected device type")
+            display("device {} is not a {}", i, expected)
+        }
+        EmptyDevice(i: u16) {
+            description("empty device")
+            display("device {} is empty", i)
+        }
+        HookOfHook {
+            description("hooks can't be hooked")
+            display("you can't hook hooks")
+        }
+        TooManyNestedScripts {
+            description("too many nested scripts")
+            display("too many nested scripts")
+        }
         Breakpoint(i: usize, addr: u16, expr: Expression) {
             description("breakpoint triggered")
             display("breakpoint {} triggered at 0x{:0>4x} ({})",
@@ -36,43 +83,229 @@ error_chain! {
                     addr,
                     expr)
         }
+        Watchpoint(i: usize, addr: u16, old: u16, new: u16) {
+            description("watchpoint triggered")
+            display("watchpoint {} triggered at 0x{:0>4x}: 0x{:0>4x} -> 0x{:0>4x}",
+                    i,
+                    addr,
+                    old,
+                    new)
+        }
     }
 }
 
-struct Breakpoint {
-    addr: u16,
-    expression: Expression,
+/// Maximum nesting of scripts and user-defined commands.
+#[cfg(feature = "debugger-cli")]
+const MAX_SCRIPT_DEPTH: u16 = 32;
+
+#[derive(Debug, Clone)]
+pub struct Breakpoint {
+    pub addr: u16,
+    pub expression: Expression,
+}
+
+pub struct Watchpoint {
+    pub addr: u16,
+    /// Value of the word when last checked.
+    pub value: u16,
+}
+
+/// Snapshot of the cpu registers.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct Registers {
+    pub a: u16,
+    pub b: u16,
+    pub c: u16,
+    pub i: u16,
+    pub j: u16,
+    pub x: u16,
+    pub y: u16,
+    pub z: u16,
+    pub pc: u16,
+    pub sp: u16,
+    pub ex: u16,
+    pub ia: u16,
+    pub tick_number: u64,
+}
+
+impl Registers {
+    pub fn new(cpu: &cpu::Cpu, tick_number: u64) -> Registers {
+        let regs = &cpu.registers;
+        Registers {
+            a: regs[Register::A],
+            b: regs[Register::B],
+            c: regs[Register::C],
+            i: regs[Register::I],
+            j: regs[Register::J],
+            x: regs[Register::X],
+            y: regs[Register::Y],
+            z: regs[Register::Z],
+            pc: cpu.pc.0,
+            sp: cpu.sp.0,
+            ex: cpu.ex,
+            ia: cpu.ia,
+            tick_number: tick_number,
+        }
+    }
+}
+
+/// A message sent with the `LOG` instruction.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct LogMessage {
+    pub value: u16,
+    /// String starting at `value`, only filled if litterals are logged.
+    pub litteral: Option<String>,
+}
+
+impl fmt::Display for LogMessage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.litteral {
+            Some(ref s) => write!(f, "0x{:0>4x}: {}", self.value, s),
+            None => write!(f, "0x{:0>4x}", self.value),
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StopReason {
+    Breakpoint { id: usize, addr: u16 },
+    Watchpoint { id: usize, addr: u16, old: u16, new: u16 },
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            StopReason::Breakpoint { id, addr } =>
+                write!(f, "breakpoint {} triggered at 0x{:0>4x}", id, addr),
+            StopReason::Watchpoint { id, addr, old, new } =>
+                write!(f,
+                       "watchpoint {} triggered at 0x{:0>4x}: 0x{:0>4x} -> 0x{:0>4x}",
+                       id,
+                       addr,
+                       old,
+                       new),
+        }
+    }
+}
+
+/// Something that happened while the debugger was executing code.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Event {
+    Stopped(StopReason),
+    Log(LogMessage),
+    HardwareInterrupt { device: usize, message: u16 },
+    /// An error which didn't stop the execution, like a failure to write the
+    /// trace. The other errors are returned by the methods.
+    Error(String),
+}
+
+pub type EventHandler = Box<FnMut(&Event) + Send>;
+
+/// When `Debugger::run_until` stops, besides breakpoints, watchpoints and
+/// errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Until {
+    /// After one instruction.
+    Step,
+    /// When PC reaches `pc` with SP at least `sp`, a subroutine call having
+    /// returned.
+    Return { pc: u16, sp: u16 },
+    /// When SP goes above this value, the current subroutine having returned.
+    StackAbove(u16),
+    Never,
+}
+
+/// Result of a debugger command, rendered by the frontends.
+#[derive(Debug, Clone)]
+pub enum Output {
+    Nothing,
+    Registers(Registers),
+    /// Decoded instructions with their address.
+    Disassembly(Vec<(u16, Instruction<u16>)>),
+    Memory { from: u16, words: Vec<u16> },
+    Breakpoints(Vec<Breakpoint>),
+    Devices(Vec<device::DeviceInfo>),
+    Logs(Vec<LogMessage>),
+    Symbols(Vec<String>),
+    Listing(Vec<ListingLine>),
+    /// Instruction written at this address by `asm`.
+    Assembled(u16, Instruction<u16>),
+    /// Value of a `print` expression.
+    Value(String),
+    /// Id and value, or error message, of the displayed expressions.
+    Displays(Vec<(usize, String)>),
+    /// Why `continue` stopped, and the displayed expressions.
+    Stopped(StopReason, Vec<(usize, String)>),
+}
+
+/// An instruction shown by `list`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ListingLine {
+    pub addr: u16,
+    /// Symbols pointing to `addr`.
+    pub labels: Vec<String>,
+    /// Instruction with the known addresses replaced by symbols.
+    pub instruction: String,
 }
 
 pub struct Debugger {
     pub cpu: cpu::Cpu,
     devices: Box<[Box<device::Device>]>,
     breakpoints: Vec<Breakpoint>,
+    watchpoints: Vec<Watchpoint>,
     tick_number: u64,
     #[cfg(feature = "debugger-cli")]
     hooks: Vec<Command>,
     #[cfg(feature = "debugger-cli")]
     last_command: Option<Command>,
+    /// User-defined commands.
+    #[cfg(feature = "debugger-cli")]
+    user_commands: HashMap<String, Vec<String>>,
+    /// Name and body of the command being defined.
+    #[cfg(feature = "debugger-cli")]
+    defining: Option<(String, Vec<String>)>,
+    /// Number of nested scripts and user-defined commands.
+    #[cfg(feature = "debugger-cli")]
+    script_depth: u16,
+    /// Expressions shown at each stop.
+    #[cfg(feature = "debugger-cli")]
+    displays: Vec<(expression::Format, expression::Expr)>,
     log_litterals: bool,
+    /// `LOG` messages already sent to the event handler, kept for
+    /// `take_logs`.
+    logs: Vec<LogMessage>,
     symbols: assembler::types::Globals,
-    show_hwi: Box<[bool]>,
+    event_handler: Option<EventHandler>,
+    /// Loaded at address 0 on power cycles.
+    rom: Vec<u16>,
+    /// Boot from the floppies again after resets.
+    boot: bool,
+    tracer: Option<trace::Tracer>,
 }
 
 impl Debugger {
     #[cfg(feature = "debugger-cli")]
     pub fn new(mut cpu: cpu::Cpu, devices: Vec<Box<device::Device>>) -> Debugger {
         cpu.on_decode_error = cpu::OnDecodeError::Fail;
-        let nb_devices = devices.len();
         Debugger {
             cpu: cpu,
             devices: devices.into_boxed_slice(),
             breakpoints: vec![],
+            watchpoints: vec![],
             tick_number: 0,
             hooks: vec![],
             last_command: None,
+            user_commands: HashMap::new(),
+            defining: None,
+            script_depth: 0,
+            displays: vec![],
             log_litterals: false,
+            logs: vec![],
             symbols: HashMap::new(),
-            show_hwi: vec![false; nb_devices].into_boxed_slice(),
+            event_handler: None,
+            rom: vec![],
+            boot: false,
+            tracer: None,
         }
     }
 
@@ -81,11 +314,17 @@ impl Debugger {
         cpu.on_decode_error = cpu::OnDecodeError::Fail;
         Debugger {
             cpu: cpu,
-            devices: devices,
+            devices: devices.into_boxed_slice(),
             breakpoints: vec![],
+            watchpoints: vec![],
             tick_number: 0,
             log_litterals: false,
+            logs: vec![],
             symbols: HashMap::new(),
+            event_handler: None,
+            rom: vec![],
+            boot: false,
+            tracer: None,
         }
     }
 
@@ -97,6 +336,90 @@ impl Debugger {
         self.symbols = symbols;
     }
 
+    pub fn rom(&mut self, rom: Vec<u16>) {
+        self.rom = rom;
+    }
+
+    /// Run `bbos::boot` again after each reset and power cycle.
+    pub fn boot_on_reset(&mut self, enabled: bool) {
+        self.boot = enabled;
+    }
+
+    /// Record each executed instruction with `tracer`, or stop tracing.
+    /// Return the previous tracer.
+    pub fn trace(&mut self, tracer: Option<trace::Tracer>) -> Option<trace::Tracer> {
+        self.cpu.trace = tracer.is_some();
+        mem::replace(&mut self.tracer, tracer)
+    }
+
+    /// Call `handler` for each event. While a handler is set, `LOG` messages
+    /// are sent to it instead of staying in the cpu log queue.
+    pub fn set_event_handler<F>(&mut self, handler: F)
+        where F: FnMut(&Event) + Send + 'static
+    {
+        self.event_handler = Some(Box::new(handler));
+    }
+
+    pub fn clear_event_handler(&mut self) {
+        self.event_handler = None;
+    }
+
+    pub fn registers(&self) -> Registers {
+        Registers::new(&self.cpu, self.tick_number)
+    }
+
+    /// Read `size` words starting at `from`, stopping at the end of the ram.
+    pub fn examine(&self, from: u16, size: u16) -> Vec<u16> {
+        let to = from.checked_add(size).unwrap_or(0xffff);
+        self.cpu.ram[from..to].to_vec()
+    }
+
+    /// Decode `count` instructions starting at `from`, with their address.
+    pub fn disassemble(&self, from: u16, count: u16) -> Vec<(u16, Instruction<u16>)> {
+        let it = iterators::U16ToInstructionOffset::chain(
+            self.cpu.ram.iter_wrap(from).cloned()
+        );
+        let mut addr = Wrapping(from);
+        let mut instructions = vec![];
+        for (used, instr) in it.take(count as usize) {
+            instructions.push((addr.0, instr));
+            addr += Wrapping(used);
+        }
+        instructions
+    }
+
+    pub fn breakpoints(&self) -> &[Breakpoint] {
+        &self.breakpoints
+    }
+
+    pub fn watchpoints(&self) -> &[Watchpoint] {
+        &self.watchpoints
+    }
+
+    pub fn devices(&self) -> &[Box<device::Device>] {
+        &self.devices
+    }
+
+    /// Remove the `LOG` messages received since the last call, including
+    /// those already sent to the event handler.
+    pub fn take_logs(&mut self) -> Vec<LogMessage> {
+        let values: Vec<u16> = self.cpu.log_queue.drain(..).collect();
+        let mut logs: Vec<LogMessage> = self.logs.drain(..).collect();
+        logs.extend(values.into_iter().map(|v| self.log_message(v)));
+        logs
+    }
+
+    fn log_message(&self, value: u16) -> LogMessage {
+        LogMessage {
+            value: value,
+            litteral: if self.log_litterals {
+                Some(self.cpu.get_str(value))
+            } else {
+                None
+            },
+        }
+    }
+
     #[cfg(feature = "debugger-cli")]
     pub fn run<P: AsRef<Path>>(&mut self, history_path: P) {
         use rustyline::error::ReadlineError;
@@ -114,31 +437,29 @@ impl Debugger {
         }
 
         loop {
-            match rl.readline(&format!("{} ", ">>".yellow())) {
+            let prompt = if self.defining.is_some() { ">" } else { ">>" };
+            match rl.readline(&format!("{} ", prompt.yellow())) {
                 Ok(line) => {
-                    let maybe_cmd = if line == "" {
-                        self.last_command.clone()
+                    let res = if line == "" && self.defining.is_none() {
+                        match self.last_command.clone() {
+                            Some(cmd) => self.exec(&cmd).map(|o| print_output(&o)),
+                            None => continue,
+                        }
                     } else {
                         rl.add_history_entry(&line);
-                        match parser::parse_command(&line) {
-                            Ok(cmd) => {
-                                Some(cmd.clone())
-                            }
-                            Err(e) => {
-                                println!("{}", e);
-                                continue
-                            }
-                        }
+                        self.exec_line(&line, &mut print_output)
                     };
 
-                    if let Some(cmd) = maybe_cmd {
-                        self.exec(&cmd);
-                        for cmd in self.hooks.clone() {
-                            self.exec(&cmd);
-                        }
-                        self.last_command = Some(cmd);
-                    } else {
-                        println!("Unknown command: {}", line);
+                    match res {
+                        Ok(()) => if self.defining.is_none() {
+                            for cmd in self.hooks.clone() {
+                                match self.exec(&cmd) {
+                                    Ok(output) => print_output(&output),
+                                    Err(e) => println!("{}", e),
+                                }
+                            }
+                        },
+                        Err(e) => println!("{}", e),
                     }
                 }
                 Err(ReadlineError::Interrupted) => (),
@@ -152,154 +473,413 @@ impl Debugger {
         }
     }
 
+    /// Execute one line of debugger commands, or add it to the command being
+    /// defined. The results of the commands are given to `output`, scripts
+    /// and user-defined commands giving several ones.
+    #[cfg(feature = "debugger-cli")]
+    pub fn exec_line(&mut self,
+                     line: &str,
+                     output: &mut FnMut(&Output)) -> Result<()> {
+        let line = line.trim();
+
+        if let Some((name, mut body)) = self.defining.take() {
+            if line == "end" {
+                self.user_commands.insert(name, body);
+            } else {
+                body.push(line.into());
+                self.defining = Some((name, body));
+            }
+            return Ok(());
+        }
+
+        if line.is_empty() || line.starts_with('#') {
+            return Ok(());
+        }
+
+        let user_command = line.split_whitespace()
+                               .next()
+                               .and_then(|name| self.user_commands.get(name))
+                               .cloned();
+        if let Some(body) = user_command {
+            return self.exec_script(body.iter().map(|l| &**l), output);
+        }
+
+        let cmd = try!(parser::parse_command(line).map_err(|e| {
+            ErrorKind::InvalidCommand(e.to_string())
+        }));
+        if let Command::Source(ref path) = cmd {
+            return self.source(path, output);
+        }
+        let res = self.exec(&cmd);
+        self.last_command = Some(cmd);
+        output(&try!(res));
+        Ok(())
+    }
+
+    /// Execute the debugger commands in a file, stopping at the first
+    /// failure.
+    #[cfg(feature = "debugger-cli")]
+    pub fn source<P: AsRef<Path>>(&mut self,
+                                  path: P,
+                                  output: &mut FnMut(&Output)) -> Result<()> {
+        use std::io::Read;
+
+        let mut script = String::new();
+        try!(fs::File::open(path.as_ref())
+                      .and_then(|mut f| f.read_to_string(&mut script))
+                      .chain_err(|| {
+                          format!("Error while reading \"{}\"",
+                                  path.as_ref().display())
+                      }));
+        self.exec_script(script.lines(), output)
+    }
+
+    #[cfg(feature = "debugger-cli")]
+    fn exec_script<'a, I>(&mut self,
+                          lines: I,
+                          output: &mut FnMut(&Output)) -> Result<()>
+        where I: Iterator<Item = &'a str>
+    {
+        if self.script_depth >= MAX_SCRIPT_DEPTH {
+            try!(Err(ErrorKind::TooManyNestedScripts));
+        }
+        self.script_depth += 1;
+        let mut res = Ok(());
+        for line in lines {
+            res = self.exec_line(line, output);
+            if res.is_err() {
+                break;
+            }
+        }
+        self.script_depth -= 1;
+        res
+    }
+
+    /// Execute a command. `source` commands must go through `exec_line`.
     #[cfg(feature = "debugger-cli")]
-    fn exec(&mut self, cmd: &Command) {
-        match *cmd {
+    fn exec(&mut self, cmd: &Command) -> Result<Output> {
+        Ok(match *cmd {
             Command::Step(n) => {
                 for _ in 0..n {
-                    if let Err(e) = self.step() {
-                        println!("{}", e);
-                    }
+                    try!(self.step());
+                }
+                self.displays()
+            }
+            Command::PrintRegisters => Output::Registers(self.registers()),
+            Command::Disassemble {ref from, size} => {
+                let from = try!(self.solve(from));
+                Output::Disassembly(self.disassemble(from, size))
+            }
+            Command::Examine {ref from, size} => {
+                let from = try!(self.solve(from));
+                Output::Memory {
+                    from: from,
+                    words: self.examine(from, size),
                 }
             }
-            Command::PrintRegisters => self.print_registers(),
-            Command::Disassemble {ref from, size} =>
-                self.disassemble(from, size),
-            Command::Examine {ref from, size} => self.examine_expr(from, size),
             Command::Breakpoint(ref b) => {
-                match b.solve(&self.symbols, &self.get_last_global()) {
-                    Ok(addr) => self.breakpoints.push(Breakpoint {
-                        addr: addr,
-                        expression: b.clone(),
-                    }),
-                    Err(e) => println!("Invalid expression: {}", e),
+                let addr = try!(self.solve(b));
+                self.add_breakpoint(addr, b.clone());
+                Output::Nothing
+            }
+            Command::ShowBreakpoints => Output::Breakpoints(self.breakpoints.clone()),
+            Command::DeleteBreakpoint(b) => {
+                self.delete_breakpoint(b as usize);
+                Output::Nothing
+            }
+            Command::Continue => {
+                // The execution only stops on errors
+                let e = self.continue_exec().unwrap_err();
+                match stop_reason(e.kind()) {
+                    Some(reason) => Output::Stopped(reason, self.display_values()),
+                    None => return Err(e),
                 }
             }
-            Command::ShowBreakpoints => self.show_breakpoints(),
-            Command::DeleteBreakpoint(b) =>
-                self.delete_breakpoint(b as usize),
-            Command::Continue => if let Err(e) = self.continue_exec() {
-                println!("{}", e);
-            },
-            Command::ShowDevices => self.show_devices(),
+            Command::ShowDevices => {
+                Output::Devices(self.devices.iter().map(|d| d.inspect()).collect())
+            }
             Command::Hook(ref cmd) => if let Command::Hook(_) = **cmd {
-                println!("You can't hook hooks!");
+                try!(Err(ErrorKind::HookOfHook))
             } else {
                 self.hooks.push(*cmd.clone());
+                Output::Nothing
             },
-            Command::Logs => self.show_logs(),
+            Command::Logs => Output::Logs(self.take_logs()),
             Command::M35fd(device_id, ref cmd) => {
-                use emulator::device::m35fd::*;
-
-                if let Some(m35fd) =
-                    self.downcast_device::<M35fd>(device_id) {
-                    match *cmd {
-                        parser::M35fdCmd::Eject => {
-                            if m35fd.eject().is_none() {
-                                println!("This device is already empty");
-                            }
-                        }
-                        parser::M35fdCmd::Load(ref path) => {
-                            match Floppy::load(path) {
-                                Ok(floppy) => m35fd.load(floppy),
-                                Err(e) => println!("{}", e),
-                            }
+                let m35fd = try!(self.downcast_device::<m35fd::M35fd>(device_id,
+                                                                      "m35fd"));
+                match *cmd {
+                    parser::M35fdCmd::Eject => {
+                        if m35fd.eject().is_none() {
+                            try!(Err(ErrorKind::EmptyDevice(device_id)));
                         }
                     }
+                    parser::M35fdCmd::Load(ref path) => {
+                        let floppy = try!(m35fd::Floppy::load(path).chain_err(|| {
+                            format!("Error while loading \"{}\"", path)
+                        }));
+                        m35fd.load(floppy);
+                    }
+                    parser::M35fdCmd::Save(ref path) => {
+                        let floppy = try!(m35fd.floppy().ok_or(
+                            ErrorKind::EmptyDevice(device_id)
+                        ));
+                        try!(floppy.save(path).chain_err(|| {
+                            format!("Error while saving \"{}\"", path)
+                        }));
+                    }
+                }
+                Output::Nothing
+            }
+            Command::Screenshot(device_id, ref path) => {
+                let recorder = match self.devices.get_mut(device_id as usize) {
+                    Some(device) => try!(record::recorder_of(&mut **device).ok_or(
+                        ErrorKind::WrongDevice(device_id, "recorder".into())
+                    )),
+                    None => try!(Err(ErrorKind::InvalidDevice(device_id))),
+                };
+                try!(recorder.screenshot(path).chain_err(|| {
+                    format!("Error while writing \"{}\"", path)
+                }));
+                Output::Nothing
+            }
+            Command::Stack(count) => {
+                let sp = self.cpu.sp.0;
+                Output::Memory {
+                    from: sp,
+                    words: self.examine(sp, count),
+                }
+            }
+            Command::Symbols => {
+                let mut symbols: Vec<String> = self.symbols.keys().cloned().collect();
+                symbols.sort();
+                Output::Symbols(symbols)
+            }
+            Command::List(n) => Output::Listing(self.list(n)),
+            Command::Source(_) => try!(Err(ErrorKind::InvalidCommand(
+                "source can only be used through exec_line".into()
+            ))),
+            Command::Define(ref name) => {
+                self.defining = Some((name.clone(), vec![]));
+                Output::Nothing
+            }
+            Command::Set(ref target, ref value) => {
+                try!(self.set(target, value));
+                Output::Nothing
+            }
+            Command::Fill {ref from, size, ref value} => {
+                try!(self.fill(from, size, value));
+                Output::Nothing
+            }
+            Command::Asm(ref from, ref instruction) => {
+                let (addr, solved) = try!(self.assemble(from, instruction));
+                Output::Assembled(addr, solved)
+            }
+            Command::Print(format, ref expr) => {
+                Output::Value(try!(self.eval(format, expr)))
+            }
+            Command::Display(format, ref expr) => {
+                let value = try!(self.eval(format, expr));
+                self.displays.push((format, expr.clone()));
+                Output::Displays(vec![(self.displays.len() - 1, value)])
+            }
+            Command::ShowDisplays => self.displays(),
+            Command::Undisplay(i) => if (i as usize) < self.displays.len() {
+                self.displays.remove(i as usize);
+                Output::Nothing
+            } else {
+                try!(Err(ErrorKind::InvalidDisplay(i)))
+            },
+            Command::Reset(reload_rom) => {
+                if reload_rom {
+                    try!(self.power_cycle());
+                } else {
+                    try!(self.reset());
                 }
+                Output::Nothing
             }
-            Command::Stack(count) => self.examine(self.cpu.sp.0, count),
-            Command::Symbols => self.show_symbols(),
-            Command::List(n) => self.list(n),
+        })
+    }
+
+    /// Reset the cpu and the devices, keeping the ram.
+    pub fn reset(&mut self) -> Result<()> {
+        self.cpu.reset();
+        self.after_reset()
+    }
+
+    /// Reset the cpu and the devices, and reload the ram with the ROM.
+    pub fn power_cycle(&mut self) -> Result<()> {
+        self.cpu.power_cycle(&self.rom);
+        self.after_reset()
+    }
+
+    fn after_reset(&mut self) -> Result<()> {
+        self.tick_number = 0;
+        for device in self.devices.iter_mut() {
+            try!(device.reset());
+        }
+        if self.boot {
+            try!(bbos::boot(&mut self.cpu, &mut self.devices));
         }
+        self.refresh_watchpoints();
+        Ok(())
     }
 
-    #[allow(dead_code)]
+    /// Execute one instruction.
     pub fn step(&mut self) -> Result<()> {
+        let res = self.tick();
+        if self.event_handler.is_some() {
+            while let Some(value) = self.cpu.log_queue.pop_front() {
+                let msg = self.log_message(value);
+                emit(&mut self.event_handler, Event::Log(msg.clone()));
+                self.logs.push(msg);
+            }
+        }
+        res
+    }
+
+    fn tick(&mut self) -> Result<()> {
         self.tick_number += 1;
+        let mut skipped = 0u64;
         for (i, device) in self.devices.iter_mut().enumerate() {
             match try!(device.tick(&mut self.cpu, self.tick_number)) {
                 device::TickResult::Nothing => (),
                 device::TickResult::Interrupt(msg) => {
-                    if self.show_hwi[i] {
-                        println!("Hardware interrupt from device {} with message {}",
-                                 i,
-                                 msg);
-                    }
+                    emit(&mut self.event_handler,
+                         Event::HardwareInterrupt {
+                             device: i,
+                             message: msg,
+                         });
                     self.cpu.hardware_interrupt(msg);
                 }
+                device::TickResult::SkipTime(ticks) => {
+                    skipped = skipped.saturating_add(ticks)
+                }
             }
         }
-        match self.cpu.tick(&mut self.devices) {
+        let skipped = device::clamp_skip(self.tick_number, skipped);
+        if skipped != 0 {
+            for device in self.devices.iter_mut() {
+                device.skip_time(skipped);
+            }
+            self.tick_number += skipped;
+        }
+        let res = self.cpu.tick(&mut self.devices);
+        self.record_step();
+        match res {
             Ok(cpu::CpuState::Executing)
             | Ok(cpu::CpuState::Halted)=> Ok(()),
-            Ok(cpu::CpuState::Waiting) => self.step(),
+            Ok(cpu::CpuState::Waiting) => self.tick(),
             Err(e) => try!(Err(e)),
         }
     }
 
-    #[allow(dead_code)]
-    fn print_registers(&self) {
-        let regs = &self.cpu.registers;
-        println!(" A {:>4x} |  B {:>4x} |  C {:>4x}",
-                 regs[Register::A], regs[Register::B], regs[Register::C]);
-        println!(" I {:>4x} |  J {:>4x}",
-                 regs[Register::I], regs[Register::J]);
-        println!(" X {:>4x} |  Y {:>4x} |  Z {:>4x}",
-                 regs[Register::X], regs[Register::Y], regs[Register::Z]);
-
-        println!("PC {:>4x} | SP {:>4x} | EX {:>4x} | IA {:>4x}",
-                 self.cpu.pc, self.cpu.sp, self.cpu.ex, self.cpu.ia);
-        println!("Tick number: {}", self.tick_number);
+    fn record_step(&mut self) {
+        let mut failed = None;
+        if let Some(ref mut tracer) = self.tracer {
+            for mut entry in self.cpu.take_steps() {
+                entry.tick = self.tick_number;
+                if let Err(e) = tracer.record(&entry) {
+                    failed = Some(e);
+                    break;
+                }
+            }
+        }
+        if let Some(e) = failed {
+            emit(&mut self.event_handler,
+                 Event::Error(format!("Error while writing the trace, \
+                                       tracing stopped: {}",
+                                      e)));
+            self.trace(None);
+        }
     }
 
-    #[allow(dead_code)]
-    fn disassemble(&self, from: &Expression, size: u16) {
-        let from = match from.solve(&self.symbols, &self.get_last_global()) {
-            Ok(addr) => addr as usize,
-            Err(e) => {
-                println!("Invalid expression: {}", e);
-                return;
+    #[cfg(feature = "debugger-cli")]
+    fn set(&mut self, target: &parser::SetTarget, value: &Expression) -> Result<()> {
+        use self::parser::SetTarget;
+
+        let value = try!(self.solve(value));
+        match *target {
+            SetTarget::Register(r) => self.cpu.registers[r] = value,
+            SetTarget::PC => self.cpu.pc = Wrapping(value),
+            SetTarget::SP => self.cpu.sp = Wrapping(value),
+            SetTarget::EX => self.cpu.ex = value,
+            SetTarget::IA => self.cpu.ia = value,
+            SetTarget::Memory(ref addr) => {
+                let addr = try!(self.solve(addr));
+                self.cpu.ram[addr] = value;
+                self.refresh_watchpoints();
             }
-        };
-        for i in iterators::U16ToInstruction::chain(self.cpu
-                                                        .ram
-                                                        .iter()
-                                                        .cloned()
-                                                        .skip(from))
-                                             .take(size as usize) {
-            println!("{}", i);
         }
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    fn examine_expr(&self, from: &Expression, size: u16) {
-        let from = match from.solve(&self.symbols, &self.get_last_global()) {
-            Ok(addr) => addr,
-            Err(e) => {
-                println!("Invalid expression: {}", e);
-                return;
-            }
-        };
-        self.examine(from, size);
+    #[cfg(feature = "debugger-cli")]
+    fn fill(&mut self, from: &Expression, size: u16, value: &Expression) -> Result<()> {
+        let from = try!(self.solve(from));
+        let value = try!(self.solve(value));
+        let values = vec![value; size as usize];
+        self.cpu.ram.copy(values.iter(), from);
+        self.refresh_watchpoints();
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    fn examine(&self, from: u16, size: u16) {
-        let to = from.checked_add(size).unwrap_or(0xffff);
-        print!("0x{:0>4x}: ", from);
-        for x in &self.cpu.ram[from..to] {
-            print!("{:0>4x} ", x);
-        }
-        println!("");
+    #[cfg(feature = "debugger-cli")]
+    fn assemble(&mut self,
+                from: &Expression,
+                instruction: &Instruction<Expression>)
+                -> Result<(u16, Instruction<u16>)> {
+        let from = try!(self.solve(from));
+        let solved = try!(instruction.solve(&self.symbols, &self.get_last_global())
+                                     .map_err(|e| {
+                                         ErrorKind::InvalidExpression(e.to_string())
+                                     }));
+        let mut buffer = [0; 3];
+        let used = solved.encode(&mut buffer);
+        self.cpu.ram.copy(buffer[..used as usize].iter(), from);
+        self.refresh_watchpoints();
+        Ok((from, solved))
     }
 
-    #[allow(dead_code)]
-    fn show_breakpoints(&self) {
-        println!("Num    Address    Expression");
-        for (i, b) in self.breakpoints.iter().enumerate() {
-            println!("{:<4}   0x{:0>4x}     {}", i, b.addr, b.expression);
-        }
+    /// Evaluate `expr` and format its value.
+    #[cfg(feature = "debugger-cli")]
+    fn eval(&self,
+            format: expression::Format,
+            expr: &expression::Expr) -> Result<String> {
+        let value = try!(expr.eval(&self.cpu, &self.symbols, &self.get_last_global()));
+        Ok(value.format(format, &self.cpu))
+    }
+
+    #[cfg(feature = "debugger-cli")]
+    fn displays(&self) -> Output {
+        Output::Displays(self.display_values())
+    }
+
+    #[cfg(feature = "debugger-cli")]
+    fn display_values(&self) -> Vec<(usize, String)> {
+        self.displays
+            .iter()
+            .enumerate()
+            .map(|(i, &(format, ref expr))| {
+                match self.eval(format, expr) {
+                    Ok(value) => (i, value),
+                    Err(e) => (i, e.to_string()),
+                }
+            })
+            .collect()
+    }
+
+    #[cfg(feature = "debugger-cli")]
+    fn solve(&self, expr: &Expression) -> Result<u16> {
+        expr.solve(&self.symbols, &self.get_last_global())
+            .map_err(|e| ErrorKind::InvalidExpression(e.to_string()).into())
+    }
+
+    pub fn add_breakpoint(&mut self, addr: u16, expression: Expression) {
+        self.breakpoints.push(Breakpoint {
+            addr: addr,
+            expression: expression,
+        });
     }
 
     pub fn delete_breakpoint(&mut self, b: usize) {
@@ -308,83 +888,173 @@ impl Debugger {
         }
     }
 
-    pub fn continue_exec(&mut self) -> Result<()> {
-        loop {
-            try!(self.step());
+    /// Delete every breakpoint set on `addr`, return false if there was none.
+    pub fn delete_breakpoint_at(&mut self, addr: u16) -> bool {
+        let len = self.breakpoints.len();
+        self.breakpoints.retain(|b| b.addr != addr);
+        len != self.breakpoints.len()
+    }
+
+    /// Stop the execution when the word at `addr` is modified.
+    pub fn add_watchpoint(&mut self, addr: u16) {
+        let value = self.cpu.ram[addr];
+        self.watchpoints.push(Watchpoint {
+            addr: addr,
+            value: value,
+        });
+    }
+
+    /// Take the current values of the watched words as the old ones, after
+    /// writing the memory outside of the execution.
+    pub fn refresh_watchpoints(&mut self) {
+        for w in &mut self.watchpoints {
+            w.value = self.cpu.ram[w.addr];
+        }
+    }
+
+    /// Delete every watchpoint set on `addr`, return false if there was none.
+    pub fn delete_watchpoint_at(&mut self, addr: u16) -> bool {
+        let len = self.watchpoints.len();
+        self.watchpoints.retain(|w| w.addr != addr);
+        len != self.watchpoints.len()
+    }
 
-            if let Some((i, b)) = self.breakpoints
-                                      .iter()
-                                      .enumerate()
-                                      .find(|&(_, x)| x.addr == self.cpu.pc.0) {
-                try!(Err(ErrorKind::Breakpoint(i,
-                                               b.addr,
-                                               b.expression.clone())));
+    /// Execute one instruction, then fail if a breakpoint or a watchpoint is
+    /// triggered.
+    pub fn step_checked(&mut self) -> Result<()> {
+        try!(self.step());
+
+        for (i, w) in self.watchpoints.iter_mut().enumerate() {
+            let new = self.cpu.ram[w.addr];
+            if new != w.value {
+                let old = w.value;
+                w.value = new;
+                emit(&mut self.event_handler,
+                     Event::Stopped(StopReason::Watchpoint {
+                         id: i,
+                         addr: w.addr,
+                         old: old,
+                         new: new,
+                     }));
+                try!(Err(ErrorKind::Watchpoint(i, w.addr, old, new)));
             }
         }
+
+        if let Some((i, b)) = self.breakpoints
+                                  .iter()
+                                  .enumerate()
+                                  .find(|&(_, x)| x.addr == self.cpu.pc.0) {
+            emit(&mut self.event_handler,
+                 Event::Stopped(StopReason::Breakpoint {
+                     id: i,
+                     addr: b.addr,
+                 }));
+            try!(Err(ErrorKind::Breakpoint(i,
+                                           b.addr,
+                                           b.expression.clone())));
+        }
+        Ok(())
     }
 
-    #[allow(dead_code)]
-    fn show_devices(&self) {
-        for (i, dev) in self.devices.iter().enumerate() {
-            print!("Device {}: ", i);
-            dev.inspect();
-            println!("");
+    /// Execute one instruction, running subroutine calls until they return.
+    pub fn step_over(&mut self) -> Result<()> {
+        let until = self.step_over_until();
+        while !try!(self.run_until(until, u32::max_value())) {}
+        Ok(())
+    }
+
+    /// Run until the current subroutine returns.
+    pub fn step_out(&mut self) -> Result<()> {
+        let until = self.step_out_until();
+        while !try!(self.run_until(until, u32::max_value())) {}
+        Ok(())
+    }
+
+    /// Where `step_over` stops, to run it with `run_until`.
+    pub fn step_over_until(&self) -> Until {
+        let pc = self.cpu.pc;
+        let next = iterators::U16ToInstructionOffset::chain(
+            self.cpu.ram.iter_wrap(pc.0).cloned()
+        ).next();
+        match next {
+            Some((used, Instruction::SpecialOp(SpecialOp::JSR, _))) => {
+                Until::Return {
+                    pc: (pc + Wrapping(used)).0,
+                    sp: self.cpu.sp.0,
+                }
+            }
+            _ => Until::Step,
         }
     }
 
-    #[allow(dead_code)]
-    fn show_logs(&mut self) {
-        for msg in &self.cpu.log_queue {
-            if self.log_litterals {
-                info!("LOG 0x{:0>4x}: {}", msg, self.cpu.get_str(*msg));
-            } else {
-                info!("LOG 0x{:0>4x}", msg);
+    /// Where `step_out` stops, to run it with `run_until`.
+    pub fn step_out_until(&self) -> Until {
+        Until::StackAbove(self.cpu.sp.0)
+    }
+
+    /// Execute at most `max` instructions, stopping when `until` is reached.
+    /// Returns whether it was reached, so that frontends can run long steps
+    /// in slices and stay responsive.
+    pub fn run_until(&mut self, until: Until, max: u32) -> Result<bool> {
+        for _ in 0..max {
+            try!(self.step_checked());
+            let reached = match until {
+                Until::Step => true,
+                Until::Return { pc, sp } => {
+                    self.cpu.pc.0 == pc && self.cpu.sp.0 >= sp
+                }
+                // The return address has been popped
+                Until::StackAbove(sp) => self.cpu.sp.0 > sp,
+                Until::Never => false,
+            };
+            if reached {
+                return Ok(true);
             }
         }
-        self.cpu.log_queue.clear();
+        Ok(false)
     }
 
-    #[allow(dead_code)]
-    fn show_symbols(&self) {
-        for symbol in self.symbols.keys() {
-            println!("{}", symbol);
+    pub fn continue_exec(&mut self) -> Result<()> {
+        loop {
+            try!(self.step_checked());
         }
     }
 
-    #[allow(dead_code)]
-    fn list(&self, n: u16) {
+    #[cfg(feature = "debugger-cli")]
+    fn list(&self, n: u16) -> Vec<ListingLine> {
         let it = iterators::U16ToInstructionOffset::chain(
             self.cpu.ram.iter_wrap(self.cpu.pc.0).cloned()
         );
         let mut addr = self.cpu.pc;
+        let mut lines = vec![];
         for (used, instr) in it.take(n as usize) {
-            for (sym, infos) in &self.symbols {
-                if infos.addr == addr.0 {
-                    println!("        {}:", sym.magenta());
-                }
-            }
-            println!("{}:     {}",
-                     format!("0x{:0>4}", addr).red(),
-                     instr.retrosolve(&self.symbols));
+            let mut labels: Vec<String> =
+                self.symbols
+                    .iter()
+                    .filter(|&(_, infos)| infos.addr == addr.0)
+                    .map(|(sym, _)| sym.clone())
+                    .collect();
+            labels.sort();
+            lines.push(ListingLine {
+                addr: addr.0,
+                labels: labels,
+                instruction: instr.retrosolve(&self.symbols),
+            });
             addr += Wrapping(used);
         }
+        lines
     }
 
-    #[allow(dead_code)]
+    #[cfg(feature = "debugger-cli")]
     fn downcast_device<D: Device>(&mut self,
-                                  device_id: u16) -> Option<&mut D> {
+                                  device_id: u16,
+                                  name: &str) -> Result<&mut D> {
         match self.devices.get_mut(device_id as usize) {
-            Some(box_dev) => {
-                let dev = box_dev.as_any().downcast_mut::<D>();
-                if dev.is_none() {
-                    println!("Device {} is not what you want", device_id);
-                }
-                dev
-            }
-            None => {
-                println!("Invalid device id: {}", device_id);
-                None
-            }
+            Some(box_dev) => match box_dev.as_any().downcast_mut::<D>() {
+                Some(dev) => Ok(dev),
+                None => Err(ErrorKind::WrongDevice(device_id, name.into()).into()),
+            },
+            None => Err(ErrorKind::InvalidDevice(device_id).into()),
         }
     }
 
@@ -401,3 +1071,254 @@ impl Debugger {
         last_global
     }
 }
+
+/// Breakpoint or watchpoint triggered by `step_checked`.
+#[cfg(feature = "debugger-cli")]
+fn stop_reason(e: &ErrorKind) -> Option<StopReason> {
+    match *e {
+        ErrorKind::Breakpoint(id, addr, _) => {
+            Some(StopReason::Breakpoint {
+                id: id,
+                addr: addr,
+            })
+        }
+        ErrorKind::Watchpoint(id, addr, old, new) => {
+            Some(StopReason::Watchpoint {
+                id: id,
+                addr: addr,
+                old: old,
+                new: new,
+            })
+        }
+        _ => None,
+    }
+}
+
+fn emit(handler: &mut Option<EventHandler>, event: Event) {
+    if let Some(ref mut handler) = *handler {
+        handler(&event);
+    }
+}
+
+/// Render the output of a command in the command line debugger.
+#[cfg(feature = "debugger-cli")]
+pub fn print_output(output: &Output) {
+    match *output {
+        Output::Nothing => (),
+        Output::Registers(ref regs) => {
+            println!(" A {:>4x} |  B {:>4x} |  C {:>4x}", regs.a, regs.b, regs.c);
+            println!(" I {:>4x} |  J {:>4x}", regs.i, regs.j);
+            println!(" X {:>4x} |  Y {:>4x} |  Z {:>4x}", regs.x, regs.y, regs.z);
+            println!("PC {:>4x} | SP {:>4x} | EX {:>4x} | IA {:>4x}",
+                     regs.pc, regs.sp, regs.ex, regs.ia);
+            println!("Tick number: {}", regs.tick_number);
+        }
+        Output::Disassembly(ref instructions) => for &(addr, ref i) in instructions {
+            println!("0x{:0>4x}: {}", addr, i);
+        },
+        Output::Memory { from, ref words } => {
+            print!("0x{:0>4x}: ", from);
+            for x in words {
+                print!("{:0>4x} ", x);
+            }
+            println!("");
+        }
+        Output::Breakpoints(ref breakpoints) => {
+            println!("Num    Address    Expression");
+            for (i, b) in breakpoints.iter().enumerate() {
+                println!("{:<4}   0x{:0>4x}     {}", i, b.addr, b.expression);
+            }
+        }
+        Output::Devices(ref devices) => for (i, info) in devices.iter().enumerate() {
+            println!("Device {}: {}", i, info);
+        },
+        Output::Logs(ref logs) => for msg in logs {
+            print_event(&Event::Log(msg.clone()));
+        },
+        Output::Symbols(ref symbols) => for symbol in symbols {
+            println!("{}", symbol);
+        },
+        Output::Listing(ref lines) => for line in lines {
+            for label in &line.labels {
+                println!("        {}:", label.magenta());
+            }
+            println!("{}:     {}",
+                     format!("0x{:0>4x}", line.addr).red(),
+                     line.instruction);
+        },
+        Output::Assembled(addr, ref instruction) =>
+            println!("0x{:0>4x}: {}", addr, instruction),
+        Output::Value(ref value) => println!("{}", value),
+        Output::Displays(ref displays) => for &(i, ref value) in displays {
+            println!("{}: {}", i, value);
+        },
+        Output::Stopped(ref reason, ref displays) => {
+            println!("{}", reason);
+            print_output(&Output::Displays(displays.clone()));
+        }
+    }
+}
+
+/// Event handler used by the command line debugger.
+#[cfg(feature = "debugger-cli")]
+pub fn print_event(event: &Event) {
+    match *event {
+        // Shown with the output of `continue`
+        Event::Stopped(_) => (),
+        Event::Log(ref msg) => info!("LOG {}", msg),
+        Event::HardwareInterrupt { device, message } =>
+            debug!("Hardware interrupt from device {} with message {}",
+                   device,
+                   message),
+        Event::Error(ref e) => println!("{}", e),
+    }
+}
+
+#[cfg(all(test, feature = "debugger-cli"))]
+mod tests {
+    use std::fs;
+    use std::io::Write;
+
+    use super::*;
+    use emulator::test_utils::temp_path;
+
+    fn debugger() -> Debugger {
+        Debugger::new(cpu::Cpu::default(), vec![])
+    }
+
+    /// Execute `line`, returning the outputs of the commands.
+    fn exec(d: &mut Debugger, line: &str) -> Result<Vec<Output>> {
+        let mut outputs = vec![];
+        try!(d.exec_line(line, &mut |o| outputs.push(o.clone())));
+        Ok(outputs)
+    }
+
+    #[test]
+    fn test_exec_line() {
+        let mut d = debugger();
+        exec(&mut d, "set A = 0x1234").unwrap();
+        exec(&mut d, "set [0x100] = 7").unwrap();
+        exec(&mut d, "fill 0x200 4 0xffff").unwrap();
+        exec(&mut d, "asm 0 ADD A, 1").unwrap();
+        exec(&mut d, "set PC = 0").unwrap();
+        exec(&mut d, "step").unwrap();
+        assert_eq!(d.cpu.registers[Register::A], 0x1235);
+        assert_eq!(d.cpu.ram[0x100], 7);
+        match exec(&mut d, "examine 0x200 4").unwrap()[0] {
+            Output::Memory { from: 0x200, ref words } => {
+                assert_eq!(*words, [0xffff; 4])
+            }
+            ref o => panic!("unexpected output: {:?}", o),
+        }
+        match exec(&mut d, "registers").unwrap()[0] {
+            Output::Registers(regs) => assert_eq!(regs.a, 0x1235),
+            ref o => panic!("unexpected output: {:?}", o),
+        }
+        match exec(&mut d, "print A + 1").unwrap()[0] {
+            Output::Value(ref v) => assert_eq!(v, "4662 (0x1236)"),
+            ref o => panic!("unexpected output: {:?}", o),
+        }
+
+        assert!(exec(&mut d, "frobnicate").is_err());
+        assert!(exec(&mut d, "set A").is_err());
+        assert!(exec(&mut d, "break unknown_label").is_err());
+        assert!(exec(&mut d, "undisplay 0").is_err());
+        assert!(exec(&mut d, "m35fd 0 eject").is_err());
+        assert!(exec(&mut d, "hook hook step").is_err());
+
+        // A breakpoint stops the execution without failing
+        exec(&mut d, "asm 0 SET PC, 0").unwrap();
+        exec(&mut d, "set PC = 0").unwrap();
+        exec(&mut d, "break 0").unwrap();
+        match exec(&mut d, "continue").unwrap()[0] {
+            Output::Stopped(StopReason::Breakpoint { id: 0, addr: 0 }, _) => (),
+            ref o => panic!("unexpected output: {:?}", o),
+        }
+        exec(&mut d, "delete 0").unwrap();
+        // 0 is not a valid instruction
+        exec(&mut d, "set [0] = 0").unwrap();
+        assert!(exec(&mut d, "continue").is_err());
+        assert!(exec(&mut d, "step").is_err());
+    }
+
+    #[test]
+    fn test_logs() {
+        let mut d = debugger();
+        // Like the command line debugger
+        d.set_event_handler(|_: &Event| ());
+        exec(&mut d, "asm 0 LOG 5").unwrap();
+        exec(&mut d, "set PC = 0").unwrap();
+        exec(&mut d, "step").unwrap();
+        match exec(&mut d, "logs").unwrap()[0] {
+            Output::Logs(ref logs) => {
+                assert_eq!(logs.iter().map(|l| l.value).collect::<Vec<_>>(), [5])
+            }
+            ref o => panic!("unexpected output: {:?}", o),
+        }
+        match exec(&mut d, "logs").unwrap()[0] {
+            Output::Logs(ref logs) => assert!(logs.is_empty()),
+            ref o => panic!("unexpected output: {:?}", o),
+        }
+    }
+
+    #[test]
+    fn test_watchpoints() {
+        let mut d = debugger();
+        d.add_watchpoint(0x100);
+        d.add_watchpoint(0x201);
+        // The second word of the instruction
+        d.add_watchpoint(1);
+        exec(&mut d, "set [0x100] = 7").unwrap();
+        exec(&mut d, "fill 0x200 2 7").unwrap();
+        exec(&mut d, "asm 0 SET [0x100], 8").unwrap();
+        exec(&mut d, "set PC = 0").unwrap();
+        // Only the write of the program is reported
+        match d.step_checked() {
+            Err(Error(ErrorKind::Watchpoint(0, 0x100, 7, 8), _)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+
+    #[test]
+    fn test_define_and_source() {
+        let mut d = debugger();
+        exec(&mut d, "set B = 0").unwrap();
+        for line in &["define incr", "set PC = 0", "asm 0 ADD B, 1", "step",
+                      "end"] {
+            exec(&mut d, line).unwrap();
+        }
+        assert_eq!(d.cpu.registers[Register::B], 0);
+        exec(&mut d, "incr").unwrap();
+        exec(&mut d, "incr").unwrap();
+        assert_eq!(d.cpu.registers[Register::B], 2);
+
+        let path = temp_path("script.dbg");
+        fs::File::create(&path)
+            .and_then(|mut f| f.write_all(b"# comment\nset C = 3\n\nincr\nprint C\n"))
+            .unwrap();
+        let outputs = exec(&mut d, &format!("source {}", path.display())).unwrap();
+        assert_eq!(d.cpu.registers[Register::C], 3);
+        assert_eq!(d.cpu.registers[Register::B], 3);
+        // set, asm, step and print
+        assert_eq!(outputs.len(), 5);
+
+        // The script stops at the first failure
+        fs::File::create(&path)
+            .and_then(|mut f| f.write_all(b"set X = 1\nbad\nset Y = 1\n"))
+            .unwrap();
+        exec(&mut d, "set Y = 0").unwrap();
+        assert!(d.source(&path, &mut |_| ()).is_err());
+        assert_eq!(d.cpu.registers[Register::X], 1);
+        assert_eq!(d.cpu.registers[Register::Y], 0);
+        fs::remove_file(&path).unwrap();
+        assert!(d.source(&path, &mut |_| ()).is_err());
+
+        exec(&mut d, "define loop").unwrap();
+        exec(&mut d, "loop").unwrap();
+        exec(&mut d, "end").unwrap();
+        match exec(&mut d, "loop") {
+            Err(Error(ErrorKind::TooManyNestedScripts, _)) => (),
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
+}
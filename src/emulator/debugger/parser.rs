@@ -1,12 +1,19 @@
 use std::fmt::{Debug, Display};
+use std::str::FromStr;
 
 use clap;
 use nom;
 
 pub use assembler::types::Expression;
-use assembler::parser::nom_parser::{expression, pos_number};
+use assembler::parser::nom_parser::{expression, instruction, pos_number};
+use emulator::debugger::expression::{self as expr, Expr, Format};
+use types::{Instruction, Register};
 
 error_chain! {
+    links {
+        Expression(expr::Error, expr::ErrorKind);
+    }
+
     foreign_links {
         Clap(clap::Error);
     }
@@ -39,15 +46,43 @@ pub enum Command {
     Hook(Box<Command>),
     Logs,
     M35fd(u16, M35fdCmd),
+    /// Write the last frame recorded by a device to a PNG file.
+    Screenshot(u16, String),
     Stack(u16),
     Symbols,
     List(u16),
+    Source(String),
+    Define(String),
+    Set(SetTarget, Expression),
+    Fill {
+        from: Expression,
+        size: u16,
+        value: Expression,
+    },
+    Asm(Expression, Instruction<Expression>),
+    Print(Format, Expr),
+    Display(Format, Expr),
+    ShowDisplays,
+    Undisplay(u16),
+    /// Reset the cpu and the devices, reloading the ROM if `true`.
+    Reset(bool),
+}
+
+#[derive(Debug, Clone)]
+pub enum SetTarget {
+    Register(Register),
+    PC,
+    SP,
+    EX,
+    IA,
+    Memory(Expression),
 }
 
 #[derive(Debug, Clone)]
 pub enum M35fdCmd {
     Eject,
     Load(String),
+    Save(String),
 }
 
 fn clap_parser<'a, 'b>() -> clap::App<'a, 'b> {
@@ -116,8 +151,18 @@ fn clap_parser<'a, 'b>() -> clap::App<'a, 'b> {
                 .help("Eject the floppy."))
             .subcommand(clap::SubCommand::with_name("load")
                 .help("Load a new floppy.")
+                .arg(clap::Arg::with_name("file")
+                    .required(true)))
+            .subcommand(clap::SubCommand::with_name("save")
+                .help("Write the floppy to an image file.")
                 .arg(clap::Arg::with_name("file")
                     .required(true))))
+        .subcommand(clap::SubCommand::with_name("screenshot")
+            .help("Write the last frame recorded by a recorder screen to a PNG file.")
+            .arg(clap::Arg::with_name("id")
+                .required(true))
+            .arg(clap::Arg::with_name("file")
+                .required(true)))
         .subcommand(clap::SubCommand::with_name("stack")
             .help("Show <count> bytes from the stack.")
             .arg(clap::Arg::with_name("count")
@@ -129,10 +174,68 @@ fn clap_parser<'a, 'b>() -> clap::App<'a, 'b> {
             .help("Show <count> instructions around PC.")
             .arg(clap::Arg::with_name("count")
                 .default_value("10")))
+        .subcommand(clap::SubCommand::with_name("source")
+            .help("Execute the debugger commands in a file.")
+            .arg(clap::Arg::with_name("file")
+                .required(true)))
+        .subcommand(clap::SubCommand::with_name("define")
+            .help("Define a new command from the following lines, up to \
+                   \"end\".")
+            .arg(clap::Arg::with_name("name")
+                .required(true)))
+        .subcommand(clap::SubCommand::with_name("set")
+            .help("Set a register or a memory word: set (<reg>|[<addr>]) = \
+                   <value>.")
+            .arg(clap::Arg::with_name("assignment")
+                .multiple(true)
+                .required(true)))
+        .subcommand(clap::SubCommand::with_name("fill")
+            .help("Fill a memory slice with a value.")
+            .arg(clap::Arg::with_name("base")
+                .required(true))
+            .arg(clap::Arg::with_name("length")
+                .required(true))
+            .arg(clap::Arg::with_name("value")
+                .required(true)))
+        .subcommand(clap::SubCommand::with_name("asm")
+            .help("Assemble an instruction in memory.")
+            .arg(clap::Arg::with_name("base")
+                .required(true))
+            .arg(clap::Arg::with_name("instruction")
+                .multiple(true)
+                .required(true)))
+        .subcommand(clap::SubCommand::with_name("print")
+            .visible_alias("p")
+            .help("Evaluate an expression: print[/x|/d|/s] <expr>.")
+            .arg(clap::Arg::with_name("expression")
+                .multiple(true)
+                .required(true)))
+        .subcommand(clap::SubCommand::with_name("display")
+            .help("Evaluate an expression at each stop: \
+                   display[/x|/d|/s] <expr>. Without expression, show the \
+                   current ones.")
+            .arg(clap::Arg::with_name("expression")
+                .multiple(true)))
+        .subcommand(clap::SubCommand::with_name("undisplay")
+            .help("Remove an expression from the display list.")
+            .arg(clap::Arg::with_name("id")
+                .required(true)))
+        .subcommand(clap::SubCommand::with_name("reset")
+            .help("Reset the cpu and the devices, keeping the ram.")
+            .arg(clap::Arg::with_name("rom")
+                .long("rom")
+                .help("Also clear the ram and reload the ROM.")))
 }
 
 pub fn parse_command(cmd: &str) -> Result<Command> {
-    let raw = try!(clap_parser().get_matches_from_safe(cmd.split(" ")));
+    // `print/x A` is split as `print /x A`
+    let mut words = cmd.split(" ").collect::<Vec<_>>();
+    if let Some(i) = words[0].find('/') {
+        let first = words.remove(0);
+        words.insert(0, &first[i..]);
+        words.insert(0, &first[..i]);
+    }
+    let raw = try!(clap_parser().get_matches_from_safe(words));
     Command::try_from(&raw)
 }
 
@@ -199,9 +302,19 @@ impl Command {
                         let file = args.value_of("file").unwrap();
                         Ok(Command::M35fd(id, M35fdCmd::Load(file.into())))
                     }
+                    ("save", Some(args)) => {
+                        let file = args.value_of("file").unwrap();
+                        Ok(Command::M35fd(id, M35fdCmd::Save(file.into())))
+                    }
                     _ => unreachable!(),
                 }
             }
+            ("screenshot", Some(args)) => {
+                let str_id = args.value_of("id").unwrap();
+                let id = try!(conv_iresult(pos_number(str_id.as_bytes())));
+                let file = args.value_of("file").unwrap();
+                Ok(Command::Screenshot(id, file.into()))
+            }
             ("stack", Some(args)) => {
                 let str_count = args.value_of("count").unwrap();
                 let count = try!(conv_iresult(pos_number(str_count.as_bytes())));
@@ -213,6 +326,69 @@ impl Command {
                 let count = try!(conv_iresult(pos_number(str_count.as_bytes())));
                 Ok(Command::List(count))
             }
+            ("source", Some(args)) => {
+                let file = args.value_of("file").unwrap();
+                Ok(Command::Source(file.into()))
+            }
+            ("define", Some(args)) => {
+                let name = args.value_of("name").unwrap();
+                Ok(Command::Define(name.into()))
+            }
+            ("set", Some(args)) => {
+                let assignment = args.values_of("assignment")
+                                     .unwrap()
+                                     .collect::<Vec<_>>()
+                                     .join(" ");
+                let mut parts = assignment.splitn(2, '=');
+                let target = try!(parse_target(parts.next().unwrap().trim()));
+                let str_value = try!(parts.next().ok_or("missing \"=\""));
+                let value = try!(conv_iresult(expression(str_value.trim()
+                                                                  .as_bytes())));
+                Ok(Command::Set(target, value))
+            }
+            ("fill", Some(args)) => {
+                let str_from = args.value_of("base").unwrap();
+                let from = try!(conv_iresult(expression(str_from.as_bytes())));
+                let str_len = args.value_of("length").unwrap();
+                let len = try!(conv_iresult(pos_number(str_len.as_bytes())));
+                let str_value = args.value_of("value").unwrap();
+                let value = try!(conv_iresult(expression(str_value.as_bytes())));
+                Ok(Command::Fill {
+                    from: from,
+                    size: len,
+                    value: value,
+                })
+            }
+            ("asm", Some(args)) => {
+                let str_from = args.value_of("base").unwrap();
+                let from = try!(conv_iresult(expression(str_from.as_bytes())));
+                let str_instr = args.values_of("instruction")
+                                    .unwrap()
+                                    .collect::<Vec<_>>()
+                                    .join(" ");
+                match instruction(str_instr.as_bytes()) {
+                    nom::IResult::Done(i, instr) if i.len() == 0 =>
+                        Ok(Command::Asm(from, instr)),
+                    _ => try!(Err(format!("invalid instruction \"{}\"",
+                                          str_instr))),
+                }
+            }
+            ("print", Some(args)) => {
+                let (format, expr) = try!(parse_formatted(args));
+                Ok(Command::Print(format, expr))
+            }
+            ("display", Some(args)) => if args.is_present("expression") {
+                let (format, expr) = try!(parse_formatted(args));
+                Ok(Command::Display(format, expr))
+            } else {
+                Ok(Command::ShowDisplays)
+            },
+            ("undisplay", Some(args)) => {
+                let str_id = args.value_of("id").unwrap();
+                let id = try!(conv_iresult(pos_number(str_id.as_bytes())));
+                Ok(Command::Undisplay(id))
+            }
+            ("reset", Some(args)) => Ok(Command::Reset(args.is_present("rom"))),
             (cmd, args) => {
                 try!(Err(format!("unknown command \"{}\" ({:?})", cmd, args)))
             }
@@ -220,6 +396,37 @@ impl Command {
     }
 }
 
+/// Parse `[/<format>] <expression>`.
+fn parse_formatted(args: &clap::ArgMatches) -> Result<(Format, Expr)> {
+    let mut words = args.values_of("expression").unwrap().collect::<Vec<_>>();
+    let format = if words[0].starts_with('/') {
+        try!(words.remove(0)[1..].parse())
+    } else {
+        Format::Natural
+    };
+    Ok((format, try!(expr::parse(&words.join(" ")))))
+}
+
+/// Parse a register, case insensitively, or a memory word `[<addr>]`.
+fn parse_target(target: &str) -> Result<SetTarget> {
+    let name = target.to_uppercase();
+    match &*name {
+        "PC" => Ok(SetTarget::PC),
+        "SP" => Ok(SetTarget::SP),
+        "EX" => Ok(SetTarget::EX),
+        "IA" => Ok(SetTarget::IA),
+        _ => if target.starts_with('[') && target.ends_with(']') {
+            let addr = &target[1..target.len() - 1];
+            let expr = try!(conv_iresult(expression(addr.trim().as_bytes())));
+            Ok(SetTarget::Memory(expr))
+        } else if let Ok(reg) = Register::from_str(&name) {
+            Ok(SetTarget::Register(reg))
+        } else {
+            try!(Err(format!("invalid target \"{}\"", target)))
+        },
+    }
+}
+
 fn conv_iresult<O: Display + Debug>(ires: nom::IResult<&[u8], O>) -> Result<O> {
     use nom::IResult;
 
@@ -235,3 +442,71 @@ fn conv_iresult<O: Display + Debug>(ires: nom::IResult<&[u8], O>) -> Result<O> {
         IResult::Incomplete(_) => unreachable!(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use assembler::types::Num;
+    use types::{BasicOp, Value};
+
+    use super::*;
+
+    fn num(n: u16) -> Expression {
+        Expression::Num(Num::U(n))
+    }
+
+    #[test]
+    fn test_set() {
+        for &(cmd, reg) in &[("set a = 1", Register::A),
+                             ("set J=1", Register::J),
+                             ("set  z =  1", Register::Z)] {
+            match parse_command(cmd) {
+                Ok(Command::Set(SetTarget::Register(r), ref e))
+                    if r == reg && *e == num(1) => (),
+                r => panic!("{}: {:?}", cmd, r),
+            }
+        }
+        match parse_command("set pc = 0x10") {
+            Ok(Command::Set(SetTarget::PC, ref e)) if *e == num(0x10) => (),
+            r => panic!("{:?}", r),
+        }
+        match parse_command("set ia = 2") {
+            Ok(Command::Set(SetTarget::IA, _)) => (),
+            r => panic!("{:?}", r),
+        }
+        match parse_command("set [ 0x8000 ] = 3") {
+            Ok(Command::Set(SetTarget::Memory(ref a), ref e))
+                if *a == num(0x8000) && *e == num(3) => (),
+            r => panic!("{:?}", r),
+        }
+        for cmd in &["set w = 1", "set a 1", "set [0x10 = 1", "set a = 1 +"] {
+            assert!(parse_command(cmd).is_err(), "{}", cmd);
+        }
+    }
+
+    #[test]
+    fn test_fill() {
+        match parse_command("fill 0x8000 0x180 0x20") {
+            Ok(Command::Fill { ref from, size: 0x180, ref value })
+                if *from == num(0x8000) && *value == num(0x20) => (),
+            r => panic!("{:?}", r),
+        }
+        for cmd in &["fill 0x8000 0x180", "fill 0 a 1", "fill 0 1 2 3"] {
+            assert!(parse_command(cmd).is_err(), "{}", cmd);
+        }
+    }
+
+    #[test]
+    fn test_asm() {
+        match parse_command("asm 0x10 set a, 1") {
+            Ok(Command::Asm(ref from,
+                            Instruction::BasicOp(BasicOp::SET,
+                                                 Value::Reg(Register::A),
+                                                 Value::Litteral(ref e))))
+                if *from == num(0x10) && *e == num(1) => (),
+            r => panic!("{:?}", r),
+        }
+        for cmd in &["asm 0x10 set a", "asm 0x10 nop", "asm 0x10"] {
+            assert!(parse_command(cmd).is_err(), "{}", cmd);
+        }
+    }
+}
@@ -0,0 +1,394 @@
+//! Full-screen terminal frontend for the debugger.
+//!
+//! Keys: `s` step, `n` next (steps over `JSR`), `c` continue, `b` toggle a
+//! breakpoint at PC, `PageUp`/`PageDown` scroll the memory view, `:` run a
+//! debugger command and `q` quit. Any key interrupts next and continue.
+
+use std::cmp;
+use std::io::{self, Write};
+use std::num::Wrapping;
+use std::thread;
+use std::time::Duration;
+
+use termion::{self, clear, color, cursor, style};
+use termion::event::Key;
+use termion::input::{Keys, TermRead};
+use termion::raw::{IntoRawMode, RawTerminal};
+use termion::screen::{ToAlternateScreen, ToMainScreen};
+
+use assembler::types::{Expression, Num};
+use emulator::debugger::{self, Debugger, Until};
+
+error_chain! {
+    foreign_links {
+        Io(io::Error);
+    }
+}
+
+/// Number of instructions executed between two checks for a key press.
+const POLL_INTERVAL: u32 = 4096;
+/// Number of LOG messages kept for the logs pane.
+const LOG_HISTORY: usize = 100;
+const MEMORY_COLUMNS: u16 = 8;
+
+pub fn run(debugger: &mut Debugger) -> Result<()> {
+    // Events are rendered by the panes, except while a command runs
+    debugger.clear_event_handler();
+    let mut tui = Tui {
+        debugger: debugger,
+        out: io::stdout(),
+        raw: Some(try!(io::stdout().into_raw_mode())),
+        keys: termion::async_stdin().keys(),
+        disasm_start: 0,
+        memory_start: 0,
+        logs: vec![],
+        status: "s: step, n: next, c: continue, b: breakpoint, :: command, \
+                 q: quit".into(),
+    };
+    try!(write!(tui.out, "{}{}", ToAlternateScreen, cursor::Hide));
+    let res = tui.main_loop();
+    try!(write!(tui.out, "{}{}", ToMainScreen, cursor::Show));
+    try!(tui.out.flush());
+    res
+}
+
+struct Tui<'a> {
+    debugger: &'a mut Debugger,
+    out: io::Stdout,
+    /// Restores the terminal mode when dropped.
+    raw: Option<RawTerminal<io::Stdout>>,
+    keys: Keys<termion::AsyncReader>,
+    /// First address of the disassembly pane.
+    disasm_start: u16,
+    /// First address of the memory pane.
+    memory_start: u16,
+    logs: Vec<String>,
+    status: String,
+}
+
+impl<'a> Tui<'a> {
+    fn main_loop(&mut self) -> Result<()> {
+        try!(self.draw());
+        loop {
+            match self.keys.next() {
+                Some(key) => {
+                    match try!(key) {
+                        Key::Char('q') | Key::Ctrl('c') | Key::Ctrl('d') => {
+                            return Ok(())
+                        }
+                        Key::Char('s') => {
+                            let res = self.debugger.step_checked();
+                            self.stopped(res.err(), "Step");
+                        }
+                        Key::Char('n') => {
+                            let until = self.debugger.step_over_until();
+                            try!(self.run_until(until, "Next"));
+                        }
+                        Key::Char('c') => try!(self.run_until(Until::Never, "")),
+                        Key::Char('b') => self.toggle_breakpoint(),
+                        Key::Char(':') => try!(self.command()),
+                        Key::PageDown => {
+                            self.memory_start = self.memory_start
+                                                    .wrapping_add(0x40)
+                        }
+                        Key::PageUp => {
+                            self.memory_start = self.memory_start
+                                                    .wrapping_sub(0x40)
+                        }
+                        _ => continue,
+                    }
+                    try!(self.draw());
+                }
+                None => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+    }
+
+    /// Run in slices until `until` is reached, stopping if a key is pressed.
+    fn run_until(&mut self, until: Until, reason: &str) -> Result<()> {
+        self.status = "Running, press any key to stop".into();
+        try!(self.draw());
+        loop {
+            match self.debugger.run_until(until, POLL_INTERVAL) {
+                Ok(true) => {
+                    self.stopped(None, reason);
+                    return Ok(());
+                }
+                Ok(false) => (),
+                Err(e) => {
+                    self.stopped(Some(e), "");
+                    return Ok(());
+                }
+            }
+            self.collect_logs();
+            if let Some(key) = self.keys.next() {
+                try!(key);
+                self.stopped(None, "Interrupted");
+                return Ok(());
+            }
+        }
+    }
+
+    fn stopped(&mut self, error: Option<super::Error>, reason: &str) {
+        self.status = match error {
+            Some(e) => format!("{}", e),
+            None => reason.into(),
+        };
+        self.collect_logs();
+    }
+
+    fn toggle_breakpoint(&mut self) {
+        let pc = self.debugger.cpu.pc.0;
+        if !self.debugger.delete_breakpoint_at(pc) {
+            self.debugger.add_breakpoint(pc, Expression::Num(Num::U(pc)));
+        }
+    }
+
+    /// Read a command, then run it outside of the full-screen mode.
+    fn command(&mut self) -> Result<()> {
+        let mut line = String::new();
+        loop {
+            self.status = format!(":{}", line);
+            try!(self.draw());
+            match try!(self.wait_key()) {
+                Key::Char('\n') => break,
+                Key::Char(c) => line.push(c),
+                Key::Backspace => {
+                    line.pop();
+                }
+                Key::Esc => {
+                    self.status = String::new();
+                    return Ok(());
+                }
+                _ => (),
+            }
+        }
+
+        try!(write!(self.out, "{}{}", ToMainScreen, cursor::Show));
+        try!(self.out.flush());
+        self.raw = None;
+        self.debugger.set_event_handler(debugger::print_event);
+        if let Err(e) = self.debugger.exec_line(&line, &mut debugger::print_output) {
+            println!("{}", e);
+        }
+        self.debugger.clear_event_handler();
+        print!("Press enter to go back to the debugger");
+        try!(io::stdout().flush());
+        // The async reader consumes stdin in the background
+        while try!(self.wait_key()) != Key::Char('\n') {}
+        self.raw = Some(try!(io::stdout().into_raw_mode()));
+        try!(write!(self.out, "{}{}", ToAlternateScreen, cursor::Hide));
+        self.status = format!(":{}", line);
+        self.collect_logs();
+        Ok(())
+    }
+
+    fn wait_key(&mut self) -> Result<Key> {
+        loop {
+            match self.keys.next() {
+                Some(key) => return Ok(try!(key)),
+                None => thread::sleep(Duration::from_millis(20)),
+            }
+        }
+    }
+
+    fn collect_logs(&mut self) {
+        for msg in self.debugger.take_logs() {
+            self.logs.push(msg.to_string());
+        }
+        if self.logs.len() > LOG_HISTORY {
+            let extra = self.logs.len() - LOG_HISTORY;
+            self.logs.drain(..extra);
+        }
+    }
+
+    fn draw(&mut self) -> Result<()> {
+        let (width, height) = try!(termion::terminal_size());
+        let left = cmp::max(width / 2, 40);
+        let right = width.saturating_sub(left);
+        let memory_height = 10;
+        let top_height = height.saturating_sub(memory_height + 1);
+
+        try!(write!(self.out, "{}", clear::All));
+
+        let registers = self.registers();
+        try!(self.pane(1, 1, left, 6, "Registers", &registers));
+        let disasm_height = top_height.saturating_sub(6);
+        let disasm = self.disassembly(disasm_height.saturating_sub(2));
+        try!(self.pane(1, 7, left, disasm_height, "Disassembly", &disasm));
+
+        let side_height = top_height / 3;
+        let stack = self.stack(side_height.saturating_sub(2));
+        try!(self.pane(left + 1, 1, right, side_height, "Stack", &stack));
+        let devices = self.devices();
+        try!(self.pane(left + 1,
+                       side_height + 1,
+                       right,
+                       side_height,
+                       "Devices",
+                       &devices));
+        let logs_height = top_height - 2 * side_height;
+        let logs = {
+            let n = cmp::min(logs_height.saturating_sub(2) as usize,
+                             self.logs.len());
+            self.logs[self.logs.len() - n..].to_vec()
+        };
+        try!(self.pane(left + 1,
+                       2 * side_height + 1,
+                       right,
+                       logs_height,
+                       "Logs",
+                       &logs));
+
+        let memory = self.memory(memory_height - 2);
+        try!(self.pane(1, top_height + 1, width, memory_height, "Memory", &memory));
+
+        let status: String = self.status.chars().take(width as usize).collect();
+        try!(write!(self.out,
+                    "{}{}{}{}",
+                    cursor::Goto(1, height),
+                    style::Invert,
+                    status,
+                    style::Reset));
+        try!(self.out.flush());
+        Ok(())
+    }
+
+    /// Draw a box with a title. Lines starting with `=>` are highlighted.
+    fn pane(&mut self,
+            x: u16,
+            y: u16,
+            width: u16,
+            height: u16,
+            title: &str,
+            lines: &[String]) -> Result<()> {
+        if width < 4 || height < 2 {
+            return Ok(());
+        }
+        let inner = (width - 2) as usize;
+        let title: String = title.chars().take(inner).collect();
+        try!(write!(self.out,
+                    "{}┌{}{}┐",
+                    cursor::Goto(x, y),
+                    title,
+                    "─".repeat(inner - title.chars().count())));
+        for i in 0..height - 2 {
+            let line = lines.get(i as usize).map(|l| &**l).unwrap_or("");
+            let line: String = line.chars().take(inner).collect();
+            let padding = " ".repeat(inner - line.chars().count());
+            try!(write!(self.out, "{}│", cursor::Goto(x, y + 1 + i)));
+            if line.starts_with("=>") {
+                try!(write!(self.out,
+                            "{}{}{}{}",
+                            color::Fg(color::Yellow),
+                            line,
+                            color::Fg(color::Reset),
+                            padding));
+            } else {
+                try!(write!(self.out, "{}{}", line, padding));
+            }
+            try!(write!(self.out, "│"));
+        }
+        try!(write!(self.out,
+                    "{}└{}┘",
+                    cursor::Goto(x, y + height - 1),
+                    "─".repeat(inner)));
+        Ok(())
+    }
+
+    fn registers(&self) -> Vec<String> {
+        let cpu = &self.debugger.cpu;
+        let regs = self.debugger.registers();
+        vec![format!(" A {:0>4x} |  B {:0>4x} |  C {:0>4x} |  I {:0>4x}",
+                     regs.a,
+                     regs.b,
+                     regs.c,
+                     regs.i),
+             format!(" X {:0>4x} |  Y {:0>4x} |  Z {:0>4x} |  J {:0>4x}",
+                     regs.x,
+                     regs.y,
+                     regs.z,
+                     regs.j),
+             format!("PC {:0>4x} | SP {:0>4x} | EX {:0>4x} | IA {:0>4x}",
+                     regs.pc,
+                     regs.sp,
+                     regs.ex,
+                     regs.ia),
+             format!("Queue: {} ({} pending) | Tick: {}{}",
+                     if cpu.is_queue_enabled { "on" } else { "off" },
+                     cpu.interrupts_queue.len(),
+                     regs.tick_number,
+                     if cpu.halted { " | HALTED" } else { "" })]
+    }
+
+    fn disassembly(&mut self, height: u16) -> Vec<String> {
+        let pc = self.debugger.cpu.pc.0;
+        let mut lines = self.disassemble_from(self.disasm_start, height);
+        if !lines.iter().any(|l| l.starts_with("=>")) {
+            self.disasm_start = pc;
+            lines = self.disassemble_from(pc, height);
+        }
+        lines
+    }
+
+    fn disassemble_from(&self, start: u16, height: u16) -> Vec<String> {
+        let debugger = &self.debugger;
+        let mut lines = vec![];
+        for (addr, instr) in debugger.disassemble(start, height) {
+            for (sym, infos) in &debugger.symbols {
+                if infos.addr == addr {
+                    lines.push(format!("        {}:", sym));
+                }
+            }
+            let marker = if addr == debugger.cpu.pc.0 {
+                "=>"
+            } else if debugger.breakpoints().iter().any(|b| b.addr == addr) {
+                " *"
+            } else {
+                "  "
+            };
+            lines.push(format!("{} 0x{:0>4x}: {}",
+                               marker,
+                               addr,
+                               instr.retrosolve(&debugger.symbols)));
+        }
+        lines.truncate(height as usize);
+        lines
+    }
+
+    fn stack(&self, height: u16) -> Vec<String> {
+        let cpu = &self.debugger.cpu;
+        let mut lines = vec![];
+        let mut addr = cpu.sp;
+        // The stack grows down from 0xffff
+        while addr.0 != 0xffff && lines.len() < height as usize {
+            lines.push(format!("0x{:0>4x}: {:0>4x}", addr.0, cpu.ram[addr]));
+            addr += Wrapping(1);
+        }
+        lines
+    }
+
+    fn devices(&self) -> Vec<String> {
+        let mut lines = vec![];
+        for (i, d) in self.debugger.devices().iter().enumerate() {
+            let info = d.inspect();
+            lines.push(format!("{}: {} ({:0>8x})", i, info.name, info.hardware_id));
+            for (key, value) in info.state {
+                lines.push(format!("   {}: {}", key, value));
+            }
+        }
+        lines
+    }
+
+    fn memory(&self, height: u16) -> Vec<String> {
+        let ram = &self.debugger.cpu.ram;
+        (0..height).map(|row| {
+            let addr = self.memory_start.wrapping_add(row * MEMORY_COLUMNS);
+            let words: Vec<String> = ram.iter_wrap(addr)
+                                        .take(MEMORY_COLUMNS as usize)
+                                        .map(|w| format!("{:0>4x}", w))
+                                        .collect();
+            format!("0x{:0>4x}: {}", addr, words.join(" "))
+        }).collect()
+    }
+}
@@ -0,0 +1,528 @@
+//! Boot from the m35fd drives like BBOS, the community bootloader and BIOS,
+//! and high-level emulation of its services.
+//!
+//! `boot` loads the boot sector of the first bootable m35fd at address 0 and
+//! puts its drive number in A. When a `Bbos` device is plugged, it also
+//! installs the services: the first LEM1802 shows a screen buffer at the top
+//! of the memory and an interrupt handler forwards `INT 0x4743` to the
+//! device, so BBOS programs run without a BIOS ROM. The memory from
+//! `RESERVED_START` belongs to BBOS and the stack starts below it.
+//!
+//! The function is in A and its arguments are pushed before the `INT`, the
+//! last one being at [SP+0]. The results replace the arguments from [SP+0],
+//! the caller reserving the space:
+//!
+//! - 0x0000 info: [SP+0] = version, [SP+1] = start of the reserved memory
+//! - 0x1000 screen present: [SP+0] = 1 if a LEM1802 is plugged
+//! - 0x1001 set cursor ([SP+1] x, [SP+0] y)
+//! - 0x1002 get cursor: [SP+1] = x, [SP+0] = y
+//! - 0x1003 write char ([SP+1] char, [SP+0] move the cursor if not 0), white
+//!   on black if the char has no colors
+//! - 0x1004 write string ([SP+1] zero-terminated string, [SP+0] go to the
+//!   next line after it if not 0)
+//! - 0x1005 scroll ([SP+0] lines)
+//! - 0x1006 screen size: [SP+1] = width, [SP+0] = height
+//! - 0x2000 boot drive: [SP+0] = drive
+//! - 0x2001 drive count: [SP+0] = number of m35fd
+//! - 0x2002 drive status ([SP+0] drive): [SP+0] = state << 8 | last error,
+//!   as polled on the m35fd, 0xffff if there is no such drive
+//! - 0x2003 drive parameters ([SP+1] address, [SP+0] drive): the words per
+//!   sector and the number of sectors are written to the address
+//! - 0x2004 read sector ([SP+2] drive, [SP+1] sector, [SP+0] address):
+//!   [SP+0] = 1 on success
+//! - 0x2005 write sector, same as read
+//! - 0x3000 keyboard present: [SP+0] = 1 if a keyboard is plugged
+//! - 0x3001 read char ([SP+0] wait for a key if not 0): [SP+0] = key or 0
+//!
+//! The sectors are transferred at once, without the m35fd timings.
+
+use std::any::Any;
+use std::num::Wrapping;
+
+use enum_primitive::FromPrimitive;
+
+use emulator::cpu::Cpu;
+use emulator::device::*;
+use emulator::device::m35fd::M35fd;
+use types::{BasicOp, Instruction, Register, SpecialOp, Value};
+
+pub const VERSION: u16 = 0x0100;
+pub const INTERRUPT_MESSAGE: u16 = 0x4743;
+/// Start of the memory used by BBOS: the screen buffer, then the interrupt
+/// handler.
+pub const RESERVED_START: u16 = 0xfe00;
+const SCREEN: u16 = RESERVED_START;
+const SCREEN_WIDTH: u16 = 32;
+const SCREEN_HEIGHT: u16 = 12;
+const SCREEN_SIZE: u16 = SCREEN_WIDTH * SCREEN_HEIGHT;
+const HANDLER: u16 = SCREEN + SCREEN_SIZE;
+/// White on black.
+const DEFAULT_COLOR: u16 = 0xf000;
+const NEWLINE: u16 = 0x0a;
+
+/// Current and old ids, see the "old-device-id" feature.
+const LEM1802_IDS: [u32; 2] = [0x7349f615, 0x734df615];
+const KEYBOARD_IDS: [u32; 2] = [0x30cf7406, 0x30c17406];
+const LEM1802_MEM_MAP_SCREEN: u16 = 0;
+const KEYBOARD_GET_NEXT: u16 = 1;
+const M35FD_ID: u32 = 0x4fd524c5;
+const M35FD_POLL_DEVICE: u16 = 0;
+/// Registers saved around the calls to the other devices.
+const REGISTERS: [Register; 8] = [Register::A,
+                                  Register::B,
+                                  Register::C,
+                                  Register::X,
+                                  Register::Y,
+                                  Register::Z,
+                                  Register::I,
+                                  Register::J];
+
+enum_from_primitive! {
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum Function {
+    GET_INFO = 0x0000,
+    SCREEN_PRESENT = 0x1000,
+    SET_CURSOR = 0x1001,
+    GET_CURSOR = 0x1002,
+    WRITE_CHAR = 0x1003,
+    WRITE_STRING = 0x1004,
+    SCROLL = 0x1005,
+    GET_SCREEN_SIZE = 0x1006,
+    GET_BOOT_DRIVE = 0x2000,
+    GET_DRIVE_COUNT = 0x2001,
+    CHECK_DRIVE = 0x2002,
+    GET_DRIVE_PARAMETERS = 0x2003,
+    READ_SECTOR = 0x2004,
+    WRITE_SECTOR = 0x2005,
+    KEYBOARD_PRESENT = 0x3000,
+    READ_CHAR = 0x3001,
+}
+}
+
+/// Load the boot sector of the first bootable m35fd at address 0 and install
+/// the plugged `Bbos` devices. Return the boot drive, also put in A.
+pub fn boot(cpu: &mut Cpu, devices: &mut [Box<Device>]) -> Result<u16> {
+    let drives: Vec<usize> = devices.iter()
+                                    .enumerate()
+                                    .filter(|&(_, d)| d.hardware_id() == M35FD_ID)
+                                    .map(|(i, _)| i)
+                                    .collect();
+    let drive = try!(drives.iter()
+                           .position(|&i| {
+                               as_m35fd(&mut devices[i])
+                                   .and_then(|d| d.floppy())
+                                   .map_or(false, |f| f.is_bootable())
+                           })
+                           .ok_or(ErrorKind::NoBootableDrive));
+    as_m35fd(&mut devices[drives[drive]])
+        .and_then(|d| d.floppy())
+        .unwrap()
+        .read_sector(0, cpu, 0);
+    cpu.registers[Register::A] = drive as u16;
+
+    for i in 0..devices.len() {
+        let (device, mut others) = OtherDevices::split(devices, i).unwrap();
+        if let Some(bbos) = device.as_any().downcast_mut::<Bbos>() {
+            try!(bbos.install(cpu, i as u16, &mut others, drive as u16));
+        }
+    }
+    Ok(drive as u16)
+}
+
+fn as_m35fd(device: &mut Box<Device>) -> Option<&mut M35fd> {
+    device.as_any().downcast_mut::<M35fd>()
+}
+
+/// Call the device as `HWI` would with A and B, keeping the registers.
+/// Return B and C.
+fn call(cpu: &mut Cpu, device: &mut Box<Device>, a: u16, b: u16) -> Result<(u16, u16)> {
+    let saved: Vec<u16> = REGISTERS.iter().map(|r| cpu.registers[*r]).collect();
+    cpu.registers[Register::A] = a;
+    cpu.registers[Register::B] = b;
+    let res = device.interrupt(cpu);
+    let out = (cpu.registers[Register::B], cpu.registers[Register::C]);
+    for (r, v) in REGISTERS.iter().zip(saved) {
+        cpu.registers[*r] = v;
+    }
+    try!(res);
+    Ok(out)
+}
+
+/// Argument or result `n` of the caller, above the A and PC pushed by the
+/// interrupt.
+fn arg(cpu: &Cpu, n: u16) -> u16 {
+    cpu.ram[cpu.sp.0.wrapping_add(2 + n)]
+}
+
+fn set_result(cpu: &mut Cpu, n: u16, value: u16) {
+    let address = cpu.sp.0.wrapping_add(2 + n);
+    cpu.write(address, value);
+}
+
+/// High-level emulation of the BBOS services. The device is specific to this
+/// emulator and only works once installed by `boot`.
+#[derive(Debug)]
+pub struct Bbos {
+    boot_drive: u16,
+    /// Position in the screen buffer.
+    cursor: u16,
+    /// `HWI` of the interrupt handler, executed again to wait for a key.
+    hwi_address: Option<u16>,
+}
+
+impl Bbos {
+    pub fn new() -> Bbos {
+        Bbos {
+            boot_drive: 0,
+            cursor: 0,
+            hwi_address: None,
+        }
+    }
+
+    /// Map the screen and set the interrupt handler, calling the device at
+    /// `index`.
+    fn install(&mut self,
+               cpu: &mut Cpu,
+               index: u16,
+               others: &mut OtherDevices,
+               boot_drive: u16)
+               -> Result<()> {
+        self.boot_drive = boot_drive;
+        self.cursor = 0;
+        for i in 0..SCREEN_SIZE {
+            cpu.ram[SCREEN + i] = 0;
+        }
+        if let Some(screen) = find(others, &LEM1802_IDS) {
+            try!(call(cpu,
+                      others.get_mut(screen).unwrap(),
+                      LEM1802_MEM_MAP_SCREEN,
+                      SCREEN));
+        }
+
+        let rfi = Instruction::SpecialOp(SpecialOp::RFI, Value::Litteral(0));
+        let mut address = HANDLER;
+        for op in &[Instruction::BasicOp(BasicOp::IFN,
+                                         Value::Reg(Register::A),
+                                         Value::Litteral(INTERRUPT_MESSAGE)),
+                    rfi] {
+            address += op.encode(&mut cpu.ram[address..]);
+        }
+        self.hwi_address = Some(address);
+        for op in &[Instruction::SpecialOp(SpecialOp::HWI, Value::Litteral(index)), rfi] {
+            address += op.encode(&mut cpu.ram[address..]);
+        }
+        cpu.ia = HANDLER;
+        cpu.sp = Wrapping(RESERVED_START);
+        Ok(())
+    }
+
+    fn write_char(&mut self, cpu: &mut Cpu, c: u16, move_cursor: bool) {
+        if c == NEWLINE {
+            self.newline(cpu);
+            return;
+        }
+        let c = if c & 0xff80 == 0 { c | DEFAULT_COLOR } else { c };
+        cpu.write(SCREEN + self.cursor, c);
+        if move_cursor {
+            self.cursor += 1;
+            if self.cursor == SCREEN_SIZE {
+                self.scroll(cpu, 1);
+            }
+        }
+    }
+
+    fn newline(&mut self, cpu: &mut Cpu) {
+        self.cursor = (self.cursor / SCREEN_WIDTH + 1) * SCREEN_WIDTH;
+        if self.cursor == SCREEN_SIZE {
+            self.scroll(cpu, 1);
+        }
+    }
+
+    /// Move the screen `lines` up, the cursor following.
+    fn scroll(&mut self, cpu: &mut Cpu, lines: u16) {
+        let shift = if lines < SCREEN_HEIGHT {
+            lines * SCREEN_WIDTH
+        } else {
+            SCREEN_SIZE
+        };
+        for i in 0..SCREEN_SIZE {
+            let value = if i + shift < SCREEN_SIZE {
+                cpu.ram[SCREEN + i + shift]
+            } else {
+                0
+            };
+            cpu.write(SCREEN + i, value);
+        }
+        self.cursor = self.cursor.saturating_sub(shift);
+    }
+
+    fn service(&mut self,
+               cpu: &mut Cpu,
+               others: &mut OtherDevices,
+               function: Function)
+               -> Result<()> {
+        match function {
+            Function::GET_INFO => {
+                set_result(cpu, 0, VERSION);
+                set_result(cpu, 1, RESERVED_START);
+            }
+            Function::SCREEN_PRESENT => {
+                let present = find(others, &LEM1802_IDS).is_some();
+                set_result(cpu, 0, present as u16);
+            }
+            Function::SET_CURSOR => {
+                let (x, y) = (arg(cpu, 1), arg(cpu, 0));
+                if x < SCREEN_WIDTH && y < SCREEN_HEIGHT {
+                    self.cursor = y * SCREEN_WIDTH + x;
+                }
+            }
+            Function::GET_CURSOR => {
+                let cursor = self.cursor;
+                set_result(cpu, 1, cursor % SCREEN_WIDTH);
+                set_result(cpu, 0, cursor / SCREEN_WIDTH);
+            }
+            Function::WRITE_CHAR => {
+                let (c, move_cursor) = (arg(cpu, 1), arg(cpu, 0) != 0);
+                self.write_char(cpu, c, move_cursor);
+            }
+            Function::WRITE_STRING => {
+                let (mut address, newline) = (arg(cpu, 1), arg(cpu, 0) != 0);
+                while cpu.ram[address] != 0 {
+                    let c = cpu.ram[address];
+                    self.write_char(cpu, c, true);
+                    address = address.wrapping_add(1);
+                }
+                if newline {
+                    self.newline(cpu);
+                }
+            }
+            Function::SCROLL => {
+                let lines = arg(cpu, 0);
+                self.scroll(cpu, lines);
+            }
+            Function::GET_SCREEN_SIZE => {
+                set_result(cpu, 1, SCREEN_WIDTH);
+                set_result(cpu, 0, SCREEN_HEIGHT);
+            }
+            Function::GET_BOOT_DRIVE => {
+                let drive = self.boot_drive;
+                set_result(cpu, 0, drive);
+            }
+            Function::GET_DRIVE_COUNT => {
+                let count = (0..others.count()).filter(|i| is_m35fd(others, *i)).count();
+                set_result(cpu, 0, count as u16);
+            }
+            Function::CHECK_DRIVE => {
+                let status = match drive(others, arg(cpu, 0)) {
+                    Some(i) => {
+                        let (state, error) = try!(call(cpu,
+                                                       others.get_mut(i).unwrap(),
+                                                       M35FD_POLL_DEVICE,
+                                                       0));
+                        state << 8 | error & 0xff
+                    }
+                    None => 0xffff,
+                };
+                set_result(cpu, 0, status);
+            }
+            Function::GET_DRIVE_PARAMETERS => {
+                let (address, drive_number) = (arg(cpu, 1), arg(cpu, 0));
+                if let Some(i) = drive(others, drive_number) {
+                    let sectors = as_m35fd(others.get_mut(i).unwrap())
+                                      .and_then(|d| d.floppy())
+                                      .map_or(0, |f| f.sectors());
+                    cpu.write(address, 512);
+                    cpu.write(address.wrapping_add(1), sectors);
+                }
+            }
+            Function::READ_SECTOR |
+            Function::WRITE_SECTOR => {
+                let (drive_number, sector, address) = (arg(cpu, 2), arg(cpu, 1), arg(cpu, 0));
+                let floppy = drive(others, drive_number)
+                                 .and_then(|i| others.get_mut(i))
+                                 .and_then(as_m35fd)
+                                 .and_then(|d| d.floppy_mut());
+                let success = match floppy {
+                    Some(ref f) if sector >= f.sectors() => false,
+                    Some(f) => {
+                        if function == Function::READ_SECTOR {
+                            f.read_sector(sector, cpu, address);
+                            true
+                        } else if f.is_write_protected() {
+                            false
+                        } else {
+                            try!(f.write_sector(sector, &cpu.ram, address));
+                            true
+                        }
+                    }
+                    None => false,
+                };
+                set_result(cpu, 0, success as u16);
+            }
+            Function::KEYBOARD_PRESENT => {
+                let present = find(others, &KEYBOARD_IDS).is_some();
+                set_result(cpu, 0, present as u16);
+            }
+            Function::READ_CHAR => {
+                let wait = arg(cpu, 0) != 0;
+                let key = match find(others, &KEYBOARD_IDS) {
+                    Some(i) => {
+                        try!(call(cpu, others.get_mut(i).unwrap(), KEYBOARD_GET_NEXT, 0)).1
+                    }
+                    None => 0,
+                };
+                match self.hwi_address {
+                    Some(address) if key == 0 && wait => cpu.pc = Wrapping(address),
+                    _ => set_result(cpu, 0, key),
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Default for Bbos {
+    fn default() -> Bbos {
+        Bbos::new()
+    }
+}
+
+/// Hardware index of the first device with one of these ids.
+fn find(others: &mut OtherDevices, ids: &[u32]) -> Option<usize> {
+    (0..others.count()).find(|i| {
+        others.get_mut(*i).map_or(false, |d| ids.contains(&d.hardware_id()))
+    })
+}
+
+fn is_m35fd(others: &mut OtherDevices, index: usize) -> bool {
+    others.get_mut(index).map_or(false, |d| d.hardware_id() == M35FD_ID)
+}
+
+/// Hardware index of the m35fd `drive`.
+fn drive(others: &mut OtherDevices, drive: u16) -> Option<usize> {
+    (0..others.count()).filter(|i| is_m35fd(others, *i)).nth(drive as usize)
+}
+
+impl Device for Bbos {
+    fn hardware_id(&self) -> u32 {
+        0x42424f53
+    }
+
+    fn hardware_version(&self) -> u16 {
+        VERSION
+    }
+
+    fn manufacturer(&self) -> u32 {
+        0
+    }
+
+    fn interrupt(&mut self, cpu: &mut Cpu) -> Result<InterruptDelay> {
+        self.interrupt_with_devices(cpu, &mut OtherDevices::none())
+    }
+
+    fn interrupt_with_devices(&mut self,
+                              cpu: &mut Cpu,
+                              others: &mut OtherDevices)
+                              -> Result<InterruptDelay> {
+        let a = cpu.registers[Register::A];
+        if a != INTERRUPT_MESSAGE {
+            return Err(ErrorKind::InvalidCommand(a).into());
+        }
+        // The caller's A, pushed by the interrupt
+        let f = cpu.ram[cpu.sp.0];
+        let function = try!(Function::from_u16(f).ok_or(ErrorKind::InvalidCommand(f)));
+        try!(self.service(cpu, others, function));
+        Ok(0)
+    }
+
+    fn tick(&mut self, _: &mut Cpu, _: u64) -> Result<TickResult> {
+        Ok(TickResult::Nothing)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        // The handler is gone with the interrupt address
+        self.cursor = 0;
+        self.hwi_address = None;
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        DeviceInfo::new("BBOS", self)
+            .with("installed", self.hwi_address.is_some())
+            .with("boot drive", self.boot_drive)
+            .with("cursor",
+                  format!("{}, {}",
+                          self.cursor % SCREEN_WIDTH,
+                          self.cursor / SCREEN_WIDTH))
+    }
+
+    fn as_any(&mut self) -> &mut Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use emulator::bbfs;
+    use emulator::{Computer, ExitConditions, ExitReason};
+    use emulator::device::m35fd::Floppy;
+    use emulator::test_utils::temp_path;
+    use types::{BasicOp, Instruction, Register, SpecialOp, Value};
+
+    /// Floppy writing 'A' with BBOS.
+    fn floppy() -> Floppy {
+        let push = |v| Instruction::BasicOp(BasicOp::SET, Value::Push, Value::Litteral(v));
+        let mut code = [0; 16];
+        let mut size = 0;
+        for op in &[push('A' as u16),
+                    push(1),
+                    Instruction::BasicOp(BasicOp::SET,
+                                         Value::Reg(Register::A),
+                                         Value::Litteral(Function::WRITE_CHAR as u16)),
+                    Instruction::SpecialOp(SpecialOp::INT, Value::Litteral(INTERRUPT_MESSAGE)),
+                    Instruction::SpecialOp(SpecialOp::HLT, Value::Litteral(0))] {
+            size += op.encode(&mut code[size as usize..]);
+        }
+        let mut image = bbfs::Image::format();
+        image.set_bootloader(&code[..size as usize]).unwrap();
+        let path = temp_path("boot.img");
+        image.save(&path).unwrap();
+        let floppy = Floppy::load(&path).unwrap();
+        fs::remove_file(&path).unwrap();
+        floppy
+    }
+
+    #[test]
+    fn test_boot() {
+        let mut devices: Vec<Box<Device>> = vec![Box::new(M35fd::new(None)),
+                                                 Box::new(Bbos::new()),
+                                                 Box::new(M35fd::new(floppy()))];
+        let mut cpu = Cpu::default();
+        assert_eq!(boot(&mut cpu, &mut devices).unwrap(), 1);
+        assert_eq!(cpu.registers[Register::A], 1);
+        assert_eq!(cpu.ia, HANDLER);
+
+        let mut c = Computer::new(cpu, devices);
+        let conditions = ExitConditions {
+            max_ticks: Some(100),
+            ..ExitConditions::default()
+        };
+        assert_eq!(c.run(&conditions, |_| ()), ExitReason::Halted);
+        assert_eq!(c.cpu.ram[SCREEN], DEFAULT_COLOR | 'A' as u16);
+        assert_eq!(c.cpu.registers[Register::A], Function::WRITE_CHAR as u16);
+
+        c.boot_on_reset(true);
+        c.reset().unwrap();
+        assert_eq!(c.cpu.registers[Register::A], 1);
+        assert_eq!(c.cpu.ia, HANDLER);
+        assert_eq!(c.run(&conditions, |_| ()), ExitReason::Halted);
+        assert_eq!(c.cpu.ram[SCREEN], DEFAULT_COLOR | 'A' as u16);
+
+        let mut devices: Vec<Box<Device>> = vec![Box::new(Bbos::new())];
+        assert!(boot(&mut c.cpu, &mut devices).is_err());
+    }
+}
@@ -1,4 +1,5 @@
 use std::any::Any;
+use std::cmp;
 
 use enum_primitive::FromPrimitive;
 use time::{empty_tm, now, Duration, Tm};
@@ -103,14 +104,33 @@ impl Device for Clock {
         })
     }
 
-    fn inspect(&self) {
-        println!("Generic clock");
+    fn reset(&mut self) -> Result<()> {
+        *self = Clock::new(self.ticks_per_second);
+        Ok(())
+    }
+
+    /// The in-game time jumps forward and the skipped ticks are counted as
+    /// if they happened.
+    fn skip_time(&mut self, ticks: u64) {
+        let seconds = ticks / self.ticks_per_second;
+        let ms = ticks % self.ticks_per_second * 1000 / self.ticks_per_second;
+        self.delta_time = self.delta_time + Duration::seconds(seconds as i64) +
+                          Duration::milliseconds(ms as i64);
+        if self.speed != 0 && self.int_msg != 0 {
+            let period = (self.speed as u64) * self.ticks_per_second / 60;
+            self.last_call += ticks / cmp::max(period, 1);
+            self.next_tick = self.next_tick.saturating_add(ticks);
+        }
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        let info = DeviceInfo::new("Generic clock", self);
         if self.speed == 0 || self.int_msg == 0 {
-            println!("Currently disabled");
+            info.with("state", "disabled")
         } else {
-            println!("FPS: {}", 60. / (self.speed as f32));
-            println!("Int message is 0x{:x}", self.int_msg);
-            println!("Last call was {} ticks ago", self.last_call);
+            info.with("fps", 60. / (self.speed as f32))
+                .with("int message", format!("0x{:x}", self.int_msg))
+                .with("last call", format!("{} ticks ago", self.last_call))
         }
     }
 
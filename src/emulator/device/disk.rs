@@ -0,0 +1,262 @@
+//! Media of the HMD2043 and M525HD drives: sectors with a geometry, loaded
+//! from image files of little endian words like the m35fd floppies.
+
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+use byteorder::{self, ReadBytesExt, WriteBytesExt};
+use emulator::{Cpu, Ram};
+
+/// Sectors addressable with a 16 bits index.
+pub const MAX_SECTORS: u32 = 0x10000;
+
+/// Fill `words` with the little endian words of the image at `path`. Missing
+/// words are left unchanged and extra ones ignored.
+pub fn load_image<'a, P, I>(path: P, words: I) -> io::Result<()>
+    where P: AsRef<Path>,
+          I: Iterator<Item = &'a mut u16>
+{
+    let mut input = try!(File::open(path));
+    for (from, to) in input.iter_items::<u16, byteorder::LittleEndian>().zip(words) {
+        *to = from;
+    }
+    Ok(())
+}
+
+/// Write `words` to a new image at `path`.
+pub fn save_image<P: AsRef<Path>>(path: P, words: &[u16]) -> io::Result<()> {
+    let mut output = io::BufWriter::new(try!(File::create(path)));
+    try!(output.write_all_items::<u16, byteorder::LittleEndian>(words));
+    output.flush()
+}
+
+/// What happens to the sectors written by the program.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum WriteMode {
+    /// They are only kept in memory, the image is not modified.
+    #[serde(rename = "copy_on_write")]
+    CopyOnWrite,
+    /// They are written to the image as soon as the operation completes.
+    #[serde(rename = "write_back")]
+    WriteBack,
+}
+
+impl Default for WriteMode {
+    fn default() -> WriteMode {
+        WriteMode::CopyOnWrite
+    }
+}
+
+/// Image receiving the written sectors in write-back mode, kept open while
+/// it is in use.
+pub struct Backing {
+    path: PathBuf,
+    image: io::BufWriter<File>,
+}
+
+impl Backing {
+    /// Open the existing image at `path`.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<Backing> {
+        let image = try!(OpenOptions::new().write(true).open(&path));
+        Ok(Backing {
+            path: path.as_ref().into(),
+            image: io::BufWriter::new(image),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Write `words` from the word `offset` of the image.
+    pub fn write(&mut self, offset: usize, words: &[u16]) -> io::Result<()> {
+        try!(self.image.seek(SeekFrom::Start(offset as u64 * 2)));
+        try!(self.image.write_all_items::<u16, byteorder::LittleEndian>(words));
+        self.image.flush()
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Geometry {
+    pub tracks: u16,
+    pub sectors_per_track: u16,
+    pub words_per_sector: u16,
+}
+
+impl Geometry {
+    /// No field is 0 and every sector can be addressed.
+    pub fn is_valid(&self) -> bool {
+        self.tracks != 0 && self.sectors_per_track != 0 &&
+        self.words_per_sector != 0 && self.sectors() <= MAX_SECTORS
+    }
+
+    pub fn sectors(&self) -> u32 {
+        self.tracks as u32 * self.sectors_per_track as u32
+    }
+
+    pub fn words(&self) -> usize {
+        self.sectors() as usize * self.words_per_sector as usize
+    }
+
+    /// Ticks to move the head from sector `from` to sector `to`, `track_seek`
+    /// being the time to go to the next track. Modeled like the m35fd.
+    pub fn seek_ticks(&self, from: u16, to: u16, track_seek: u64) -> u64 {
+        let by_track = self.sectors_per_track;
+        let sectors_to_skip = ((from % by_track) as i32 - (to % by_track) as i32)
+                                  .abs() as u64;
+        let tracks_to_skip = ((from / by_track) as i32 - (to / by_track) as i32)
+                                 .abs() as u64;
+        tracks_to_skip * track_seek + sectors_to_skip * track_seek / by_track as u64
+    }
+}
+
+pub struct Media {
+    geometry: Geometry,
+    data: Vec<u16>,
+    pub write_protected: bool,
+    backing: Option<Backing>,
+}
+
+impl fmt::Debug for Media {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f,
+               "A {}x{} sectors media",
+               self.geometry.tracks,
+               self.geometry.sectors_per_track)
+    }
+}
+
+impl Media {
+    /// Blank media. `geometry` must be valid.
+    pub fn new(geometry: Geometry) -> Media {
+        Media {
+            geometry: geometry,
+            data: vec![0; geometry.words()],
+            write_protected: false,
+            backing: None,
+        }
+    }
+
+    /// Load an image, missing words being 0 and extra ones ignored.
+    pub fn load<P: AsRef<Path>>(path: P, geometry: Geometry) -> io::Result<Media> {
+        let mut media = Media::new(geometry);
+        try!(load_image(path, media.data.iter_mut()));
+        Ok(media)
+    }
+
+    /// Load an image, saving the written sectors to it in write-back mode.
+    pub fn open<P: AsRef<Path>>(path: P,
+                                geometry: Geometry,
+                                mode: WriteMode) -> io::Result<Media> {
+        let mut media = try!(Media::load(&path, geometry));
+        if mode == WriteMode::WriteBack {
+            media.backing = Some(try!(Backing::open(path)));
+        }
+        Ok(media)
+    }
+
+    /// Write the whole media to an image.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        save_image(path, &self.data)
+    }
+
+    /// Image receiving the written sectors, in write-back mode.
+    pub fn backing_path(&self) -> Option<&Path> {
+        self.backing.as_ref().map(|b| b.path())
+    }
+
+    pub fn geometry(&self) -> Geometry {
+        self.geometry
+    }
+
+    /// Copy `count` sectors starting at `sector` to the ram.
+    pub fn read(&self, sector: u16, count: u16, cpu: &mut Cpu, address: u16) {
+        let (start, end) = self.range(sector, count);
+        cpu.copy(self.data[start..end].iter(), address);
+    }
+
+    /// Copy `count` sectors from the ram, starting at `sector`, and to the
+    /// image in write-back mode.
+    pub fn write(&mut self,
+                 sector: u16,
+                 count: u16,
+                 ram: &Ram,
+                 address: u16) -> io::Result<()> {
+        let (start, end) = self.range(sector, count);
+        for (from, to) in ram.iter_wrap(address).zip(self.data[start..end].iter_mut()) {
+            *to = *from;
+        }
+        match self.backing {
+            Some(ref mut backing) => backing.write(start, &self.data[start..end]),
+            None => Ok(()),
+        }
+    }
+
+    fn range(&self, sector: u16, count: u16) -> (usize, usize) {
+        let size = self.geometry.words_per_sector as usize;
+        let start = sector as usize * size;
+        (start, start + count as usize * size)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use emulator::test_utils::temp_path;
+
+    use super::*;
+
+    #[test]
+    fn test_seek_ticks() {
+        let geometry = Geometry {
+            tracks: 80,
+            sectors_per_track: 18,
+            words_per_sector: 512,
+        };
+        assert_eq!(geometry.seek_ticks(3, 3, 1800), 0);
+        assert_eq!(geometry.seek_ticks(0, 18 * 2 + 1, 1800), 2 * 1800 + 100);
+        assert_eq!(geometry.seek_ticks(18 * 2 + 1, 0, 1800), 2 * 1800 + 100);
+    }
+
+    #[test]
+    fn test_is_valid() {
+        let geometry = Geometry {
+            tracks: 256,
+            sectors_per_track: 256,
+            words_per_sector: 512,
+        };
+        assert!(geometry.is_valid());
+        assert!(!Geometry { tracks: 257, ..geometry }.is_valid());
+        assert!(!Geometry { tracks: 0, ..geometry }.is_valid());
+        assert!(!Geometry { sectors_per_track: 0, ..geometry }.is_valid());
+        assert!(!Geometry { words_per_sector: 0, ..geometry }.is_valid());
+    }
+
+    #[test]
+    fn test_write_back() {
+        let geometry = Geometry {
+            tracks: 2,
+            sectors_per_track: 2,
+            words_per_sector: 4,
+        };
+        let path = temp_path("write-back.img");
+        Media::new(geometry).save(&path).unwrap();
+        let mut cpu = Cpu::default();
+        cpu.copy([1, 2, 3, 4].iter(), 0);
+
+        let mut media = Media::open(&path, geometry, WriteMode::CopyOnWrite).unwrap();
+        media.write(1, 1, &cpu.ram, 0).unwrap();
+        let mut media = Media::open(&path, geometry, WriteMode::WriteBack).unwrap();
+        media.read(1, 1, &mut cpu, 0x100);
+        assert_eq!(&cpu.ram[0x100..0x104], &[0, 0, 0, 0]);
+
+        media.write(2, 1, &cpu.ram, 0).unwrap();
+        Media::load(&path, geometry).unwrap().read(2, 1, &mut cpu, 0x100);
+        assert_eq!(&cpu.ram[0x100..0x104], &[1, 2, 3, 4]);
+        assert_eq!(fs::metadata(&path).unwrap().len(), geometry.words() as u64 * 2);
+        fs::remove_file(&path).unwrap();
+    }
+}
@@ -32,8 +32,12 @@ impl Device for Dummy {
         Ok(TickResult::Nothing)
     }
 
-    fn inspect(&self) {
-        println!("Dummy device");
+    fn reset(&mut self) -> Result<()> {
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        DeviceInfo::new("Dummy device", self)
     }
 
     fn as_any(&mut self) -> &mut Any {
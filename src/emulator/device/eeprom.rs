@@ -85,9 +85,21 @@ impl<D: Device> Device for Eeprom<D> {
         self.inner.tick(cpu, current_tick)
     }
 
-    fn inspect(&self) {
-        self.inner.inspect();
-        println!("Eeprom: {:?}", self.mem);
+    /// The eeprom content survives resets.
+    fn reset(&mut self) -> Result<()> {
+        self.inner.reset()
+    }
+
+    fn skip_time(&mut self, ticks: u64) {
+        self.inner.skip_time(ticks)
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        let words: Vec<String> = self.mem
+                                     .iter()
+                                     .map(|w| format!("{:0>4x}", w))
+                                     .collect();
+        self.inner.inspect().with("eeprom", words.join(" "))
     }
 
     fn as_any(&mut self) -> &mut Any {
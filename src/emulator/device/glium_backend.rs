@@ -76,6 +76,7 @@ fn thread_main(thread_command: mpsc::Receiver<ThreadCommand>,
         .build_glium());
     let mut current_screen =
         Box::new(lem1802::Screen([lem1802::Color::default(); 12288]));
+    let mut border = lem1802::Color::default();
 
     let vertex_buffer = {
         #[derive(Copy, Clone)]
@@ -119,6 +120,7 @@ fn thread_main(thread_command: mpsc::Receiver<ThreadCommand>,
         #version 130
         #define SCREEN_WIDTH 128.
         #define SCREEN_HEIGHT 96.
+        #define BORDER_SIZE 8.
 
         in uint i;
         in uint j;
@@ -132,8 +134,10 @@ fn thread_main(thread_command: mpsc::Receiver<ThreadCommand>,
         void main() {
             v_color = color;
             gl_Position = vec4(
-                ((position[0] + float(i)) / SCREEN_WIDTH - 0.5) * 2.,
-                (-(position[1] + float(j)) / SCREEN_HEIGHT + 0.5) * 2.,
+                ((position[0] + float(i) + BORDER_SIZE)
+                 / (SCREEN_WIDTH + 2. * BORDER_SIZE) - 0.5) * 2.,
+                (-(position[1] + float(j) + BORDER_SIZE)
+                 / (SCREEN_HEIGHT + 2. * BORDER_SIZE) + 0.5) * 2.,
                 0.,
                 1.
             );
@@ -153,6 +157,7 @@ fn thread_main(thread_command: mpsc::Receiver<ThreadCommand>,
         'pote2: loop {
             match screen_receiver.try_recv() {
                 Ok(ScreenCommand::Show(screen)) => {
+                    border = screen.border_color();
                     current_screen = screen.into();
                     display.get_window().map(|w| w.show());
                 }
@@ -174,7 +179,7 @@ fn thread_main(thread_command: mpsc::Receiver<ThreadCommand>,
         }
 
         let mut target = display.draw();
-        target.clear_color(0.0, 0.0, 1.0, 1.0);
+        target.clear_color(border.r, border.g, border.b, 1.0);
         let aspect_ratio = {
             let (width, height) = target.get_dimensions();
             height as f32 / width as f32
@@ -252,7 +252,7 @@ impl Device for HIC {
                     Some(port) => {
                         let dst_addr = cpu.registers[Register::B];
                         if dst_addr <= 0xffff - 8 {
-                            cpu.ram.copy(port.name.iter(), dst_addr);
+                            cpu.copy(port.name.iter(), dst_addr);
                             LoadNameError::Success
                         } else {
                             LoadNameError::InvalidAddress
@@ -291,8 +291,27 @@ impl Device for HIC {
         Ok(TickResult::Nothing)
     }
 
-    fn inspect(&self) {
-        println!("{:?}", self);
+    fn reset(&mut self) -> Result<()> {
+        self.int_msg_recv = 0;
+        self.int_msg_transmit = 0;
+        self.send_buffer = Buffer::default();
+        for port in self.ports.iter_mut() {
+            port.recv_buffer = Buffer::default();
+            port.overflowed = false;
+        }
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        let mut info = DeviceInfo::new("HIC", self)
+                           .with("receive int", format!("0x{:x}", self.int_msg_recv))
+                           .with("transmit int",
+                                 format!("0x{:x}", self.int_msg_transmit))
+                           .with("send buffer", self.send_buffer.size());
+        for (i, port) in self.ports.iter().enumerate() {
+            info = info.with(&format!("port {}", i), port.describe());
+        }
+        info
     }
 
     fn as_any(&mut self) -> &mut Any {
@@ -309,6 +328,17 @@ pub struct Port {
 }
 
 impl Port {
+    fn describe(&self) -> String {
+        let connection = match self.connection {
+            Some((other, _)) => format!("connected to port {}", other),
+            None => "unconnected".into(),
+        };
+        format!("{}, {} words received{}",
+                connection,
+                self.recv_buffer.size(),
+                if self.overflowed { ", overflowed" } else { "" })
+    }
+
     fn try_send(&mut self, val: u16) -> Result<()> {
         if let Some((port_num, ref sender)) = self.connection {
             match sender.send(Message {
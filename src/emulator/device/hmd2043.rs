@@ -0,0 +1,382 @@
+//! Harold HMD2043 media drive. The media can have any geometry, the standard
+//! HMU1440 being 1440 sectors of 512 words.
+//!
+//! In blocking mode, the cpu waits for the end of the transfers. In
+//! non-blocking mode, the commands return at once and an interrupt tells
+//! when the transfer is done.
+
+use std::any::Any;
+use std::cmp;
+use std::io;
+
+use enum_primitive::FromPrimitive;
+
+use emulator::cpu::Cpu;
+use emulator::device::*;
+use emulator::device::disk::{Geometry, Media};
+use types::Register;
+
+/// Geometry of the HMU1440 media.
+pub const HMU1440: Geometry = Geometry {
+    tracks: 80,
+    sectors_per_track: 18,
+    words_per_sector: 512,
+};
+/// Ticks to move the head to the next track.
+const TRACK_SEEKING_TIME: u64 = 100_000 * 3 / 1000;
+/// Ticks to transfer a sector under the head.
+const SECTOR_TIME: u64 = 100_000 * 11 / 1000;
+
+const FLAG_NON_BLOCKING: u16 = 1 << 0;
+const FLAG_MEDIA_STATUS_INTERRUPT: u16 = 1 << 1;
+
+enum_from_primitive! {
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+enum Command {
+    QUERY_MEDIA_PRESENT = 0x0000,
+    QUERY_MEDIA_PARAMETERS = 0x0001,
+    QUERY_DEVICE_FLAGS = 0x0002,
+    UPDATE_DEVICE_FLAGS = 0x0003,
+    QUERY_INTERRUPT_TYPE = 0x0004,
+    SET_INTERRUPT_MESSAGE = 0x0005,
+    READ_SECTORS = 0x0010,
+    WRITE_SECTORS = 0x0011,
+    QUERY_MEDIA_QUALITY = 0xffff,
+}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ErrorCode {
+    None = 0,
+    NoMedia = 1,
+    InvalidSector = 2,
+    Pending = 3,
+    /// Write on a write-protected media.
+    Locked = 4,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum InterruptType {
+    None = 0,
+    MediaStatus = 1,
+    ReadComplete = 2,
+    WriteComplete = 3,
+}
+
+#[derive(Debug)]
+struct Operation {
+    tick_delay: u64,
+    sector: u16,
+    count: u16,
+    address: u16,
+    side: Side,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Side {
+    Read,
+    Write,
+}
+
+#[derive(Debug)]
+pub struct Hmd2043 {
+    media: Option<Media>,
+    flags: u16,
+    int_msg: u16,
+    last_interrupt: InterruptType,
+    current_operation: Option<Operation>,
+    current_sector: u16,
+    /// Indicate a media status interrupt should be launched on next tick.
+    media_changed: bool,
+}
+
+impl Hmd2043 {
+    pub fn new<M: Into<Option<Media>>>(media: M) -> Hmd2043 {
+        Hmd2043 {
+            media: media.into(),
+            flags: 0,
+            int_msg: 0,
+            last_interrupt: InterruptType::None,
+            current_operation: None,
+            current_sector: 0,
+            media_changed: false,
+        }
+    }
+
+    /// Remove the media, aborting the current operation.
+    pub fn eject(&mut self) -> Option<Media> {
+        self.current_operation = None;
+        self.media_changed = true;
+        self.media.take()
+    }
+
+    pub fn load(&mut self, media: Media) {
+        self.current_operation = None;
+        self.media_changed = true;
+        self.media = Some(media);
+    }
+
+    fn start_operation(&mut self, cpu: &mut Cpu, side: Side) -> Result<InterruptDelay> {
+        let sector = cpu.registers[Register::B];
+        let count = cpu.registers[Register::C];
+        let address = cpu.registers[Register::X];
+        let (error, delay) = match self.media {
+            None => (ErrorCode::NoMedia, 0),
+            Some(_) if self.current_operation.is_some() => (ErrorCode::Pending, 0),
+            Some(ref media) if sector as u32 + count as u32 >
+                               media.geometry().sectors() => {
+                (ErrorCode::InvalidSector, 0)
+            }
+            Some(ref media) if side == Side::Write && media.write_protected => {
+                (ErrorCode::Locked, 0)
+            }
+            Some(ref media) => {
+                let delay = media.geometry().seek_ticks(self.current_sector,
+                                                        sector,
+                                                        TRACK_SEEKING_TIME) +
+                            count as u64 * SECTOR_TIME;
+                (ErrorCode::None, delay)
+            }
+        };
+        cpu.registers[Register::A] = error as u16;
+        if error != ErrorCode::None {
+            return Ok(0);
+        }
+
+        let operation = Operation {
+            tick_delay: delay,
+            sector: sector,
+            count: count,
+            address: address,
+            side: side,
+        };
+        if self.flags & FLAG_NON_BLOCKING != 0 {
+            self.current_operation = Some(operation);
+            Ok(0)
+        } else {
+            try!(self.do_operation(&operation, cpu));
+            Ok(delay.min(InterruptDelay::max_value() as u64) as InterruptDelay)
+        }
+    }
+
+    fn do_operation(&mut self, operation: &Operation, cpu: &mut Cpu) -> io::Result<()> {
+        self.current_sector = operation.sector.wrapping_add(operation.count);
+        match self.media {
+            Some(ref mut media) => {
+                match operation.side {
+                    Side::Read => {
+                        media.read(operation.sector,
+                                   operation.count,
+                                   cpu,
+                                   operation.address);
+                        Ok(())
+                    }
+                    Side::Write => media.write(operation.sector,
+                                               operation.count,
+                                               &cpu.ram,
+                                               operation.address),
+                }
+            }
+            None => Ok(()),
+        }
+    }
+}
+
+impl Device for Hmd2043 {
+    fn hardware_id(&self) -> u32 {
+        0x74fa4cae
+    }
+
+    fn hardware_version(&self) -> u16 {
+        0x07c2
+    }
+
+    fn manufacturer(&self) -> u32 {
+        0x21544948
+    }
+
+    fn interrupt(&mut self, cpu: &mut Cpu) -> Result<InterruptDelay> {
+        let a = cpu.registers[Register::A];
+        let b = cpu.registers[Register::B];
+        let error = match try!(Command::from_u16(a)
+                                       .ok_or(ErrorKind::InvalidCommand(a))) {
+            Command::QUERY_MEDIA_PRESENT => {
+                cpu.registers[Register::B] = self.media.is_some() as u16;
+                ErrorCode::None
+            }
+            Command::QUERY_MEDIA_PARAMETERS => match self.media {
+                Some(ref media) => {
+                    let geometry = media.geometry();
+                    cpu.registers[Register::B] = geometry.words_per_sector;
+                    // C can't hold `disk::MAX_SECTORS`, report the largest
+                    // value instead.
+                    cpu.registers[Register::C] =
+                        cmp::min(geometry.sectors(), 0xffff) as u16;
+                    cpu.registers[Register::X] = media.write_protected as u16;
+                    ErrorCode::None
+                }
+                None => ErrorCode::NoMedia,
+            },
+            Command::QUERY_DEVICE_FLAGS => {
+                cpu.registers[Register::B] = self.flags;
+                ErrorCode::None
+            }
+            Command::UPDATE_DEVICE_FLAGS => {
+                self.flags = b & (FLAG_NON_BLOCKING | FLAG_MEDIA_STATUS_INTERRUPT);
+                ErrorCode::None
+            }
+            Command::QUERY_INTERRUPT_TYPE => {
+                cpu.registers[Register::B] = self.last_interrupt as u16;
+                ErrorCode::None
+            }
+            Command::SET_INTERRUPT_MESSAGE => {
+                self.int_msg = b;
+                ErrorCode::None
+            }
+            Command::READ_SECTORS => return self.start_operation(cpu, Side::Read),
+            Command::WRITE_SECTORS => return self.start_operation(cpu, Side::Write),
+            Command::QUERY_MEDIA_QUALITY => match self.media {
+                Some(ref media) => {
+                    cpu.registers[Register::B] = if media.geometry() == HMU1440 {
+                        0x7fff
+                    } else {
+                        0xffff
+                    };
+                    ErrorCode::None
+                }
+                None => ErrorCode::NoMedia,
+            },
+        };
+        cpu.registers[Register::A] = error as u16;
+        Ok(0)
+    }
+
+    fn tick(&mut self, cpu: &mut Cpu, _current_tick: u64) -> Result<TickResult> {
+        let finished = if let Some(ref mut op) = self.current_operation {
+            if op.tick_delay == 0 {
+                true
+            } else {
+                op.tick_delay -= 1;
+                false
+            }
+        } else {
+            false
+        };
+        let interrupt = if finished {
+            let op = self.current_operation.take().unwrap();
+            try!(self.do_operation(&op, cpu));
+            Some(match op.side {
+                Side::Read => InterruptType::ReadComplete,
+                Side::Write => InterruptType::WriteComplete,
+            })
+        } else if self.media_changed {
+            self.media_changed = false;
+            if self.flags & FLAG_MEDIA_STATUS_INTERRUPT != 0 {
+                Some(InterruptType::MediaStatus)
+            } else {
+                None
+            }
+        } else {
+            None
+        };
+
+        Ok(match interrupt {
+            Some(kind) if self.int_msg != 0 => {
+                self.last_interrupt = kind;
+                TickResult::Interrupt(self.int_msg)
+            }
+            _ => TickResult::Nothing,
+        })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.flags = 0;
+        self.int_msg = 0;
+        self.last_interrupt = InterruptType::None;
+        self.current_operation = None;
+        self.current_sector = 0;
+        self.media_changed = false;
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        DeviceInfo::new("HMD2043", self)
+            .with("int message", format!("0x{:x}", self.int_msg))
+            .with("media", match self.media {
+                Some(ref media) => {
+                    let geometry = media.geometry();
+                    let mode = match media.backing_path() {
+                        _ if media.write_protected => ", read only".into(),
+                        Some(path) => format!(", saved to {}", path.display()),
+                        None => String::new(),
+                    };
+                    format!("{} sectors of {} words{}",
+                            geometry.sectors(),
+                            geometry.words_per_sector,
+                            mode)
+                }
+                None => "none".into(),
+            })
+            .with("non-blocking", self.flags & FLAG_NON_BLOCKING != 0)
+            .with("operation in progress", self.current_operation.is_some())
+    }
+
+    fn as_any(&mut self) -> &mut Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use emulator::test_utils::command;
+
+    use super::*;
+
+    #[test]
+    fn test_read() {
+        let mut media = Media::new(HMU1440);
+        media.write_protected = true;
+        let mut hmd = Hmd2043::new(media);
+        let mut cpu = Cpu::default();
+        command(&mut hmd, &mut cpu, Command::QUERY_MEDIA_PARAMETERS as u16, &[]);
+        assert_eq!((cpu.registers[Register::B], cpu.registers[Register::C]),
+                   (512, 1440));
+
+        // Blocking
+        cpu.registers[Register::C] = 2;
+        cpu.registers[Register::X] = 0x1000;
+        let delay = command(&mut hmd, &mut cpu, Command::READ_SECTORS as u16,
+                            &[(Register::B, 1)]);
+        assert_eq!(cpu.registers[Register::A], ErrorCode::None as u16);
+        assert!(delay > 0);
+        assert_eq!(cpu.ram[0x1000 + 1023], 0);
+        assert_eq!(cpu.ram[0x1000 + 1024], 0x3e0);
+        command(&mut hmd, &mut cpu, Command::WRITE_SECTORS as u16, &[(Register::B, 1)]);
+        assert_eq!(cpu.registers[Register::A], ErrorCode::Locked as u16);
+
+        // Non-blocking, with an interrupt at the end
+        command(&mut hmd, &mut cpu, Command::SET_INTERRUPT_MESSAGE as u16,
+                &[(Register::B, 7)]);
+        command(&mut hmd, &mut cpu, Command::UPDATE_DEVICE_FLAGS as u16,
+                &[(Register::B, 1)]);
+        cpu.registers[Register::C] = 1;
+        cpu.registers[Register::X] = 0x3000;
+        assert_eq!(command(&mut hmd, &mut cpu, Command::READ_SECTORS as u16,
+                           &[(Register::B, 1439)]),
+                   0);
+        command(&mut hmd, &mut cpu, Command::READ_SECTORS as u16, &[(Register::B, 0)]);
+        assert_eq!(cpu.registers[Register::A], ErrorCode::Pending as u16);
+        let mut ticks = 0;
+        while hmd.tick(&mut cpu, ticks).unwrap() == TickResult::Nothing {
+            ticks += 1;
+        }
+        assert!(ticks > 0);
+        assert_eq!(cpu.ram[0x3000], 0);
+        command(&mut hmd, &mut cpu, Command::QUERY_INTERRUPT_TYPE as u16, &[]);
+        assert_eq!(cpu.registers[Register::B], InterruptType::ReadComplete as u16);
+
+        command(&mut hmd, &mut cpu, Command::READ_SECTORS as u16, &[(Register::B, 1440)]);
+        assert_eq!(cpu.registers[Register::A], ErrorCode::InvalidSector as u16);
+    }
+}
@@ -93,10 +93,16 @@ impl<B: Backend> Device for Keyboard<B> {
         })
     }
 
-    fn inspect(&self) {
-        println!("Generic Keyboard");
-        println!("Int message is 0x{:x}", self.int_msg);
-        println!("{} keys in the buffer", self.key_buffer.len());
+    fn reset(&mut self) -> Result<()> {
+        self.key_buffer.clear();
+        self.int_msg = 0;
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        DeviceInfo::new("Generic Keyboard", self)
+            .with("int message", format!("0x{:x}", self.int_msg))
+            .with("key buffer", format!("{:?}", self.key_buffer))
     }
 
     fn as_any(&mut self) -> &mut Any {
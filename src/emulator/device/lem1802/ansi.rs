@@ -0,0 +1,185 @@
+//! Rendering of the screen with ANSI escape codes, either as a grid of
+//! characters or with one half block per pixel.
+
+use std::io::{self, Write};
+
+use emulator::device::lem1802::screen::*;
+
+#[derive(Serialize, Deserialize)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum ColorMode {
+    #[serde(rename = "truecolor")]
+    TrueColor,
+    /// 6x6x6 color cube of 256-color terminals.
+    #[serde(rename = "256")]
+    Ansi256,
+}
+
+impl Default for ColorMode {
+    fn default() -> ColorMode {
+        ColorMode::TrueColor
+    }
+}
+
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq)]
+pub struct Options {
+    /// Draw the pixels with half blocks instead of the ASCII characters of
+    /// the cells.
+    pub pixels: bool,
+    pub colors: ColorMode,
+}
+
+/// Draw `screen` and its border from the top left corner of the terminal.
+pub fn render<W: Write>(output: &mut W,
+                        screen: &RawScreen,
+                        options: Options) -> io::Result<()> {
+    let mut frame = Frame {
+        text: "\x1b[H".into(),
+        colors: options.colors,
+        current: None,
+    };
+    let border = screen.border_color();
+    if options.pixels {
+        let mut pixels = Screen([border; SCREEN_SIZE as usize]);
+        for offset in 0..NB_CHARS {
+            screen.add_char(&mut pixels, offset);
+        }
+        let width = SCREEN_WIDTH as usize;
+        frame.border_row(border, width + 2);
+        for y in 0..(SCREEN_HEIGHT / 2) as usize {
+            frame.push(border, border, ' ');
+            for x in 0..width {
+                let top = pixels.0[2 * y * width + x];
+                let bottom = pixels.0[(2 * y + 1) * width + x];
+                frame.push(top, bottom, '▀');
+            }
+            frame.push(border, border, ' ');
+            frame.end_row();
+        }
+        frame.border_row(border, width + 2);
+    } else {
+        frame.border_row(border, 34);
+        for row in 0..12 {
+            frame.push(border, border, ' ');
+            for col in 0..32 {
+                let cell = screen.cell(row * 32 + col);
+                let c = match cell.char_idx {
+                    c @ 0x21...0x7e => c as u8 as char,
+                    _ => ' ',
+                };
+                frame.push(cell.fg, cell.bg, c);
+            }
+            frame.push(border, border, ' ');
+            frame.end_row();
+        }
+        frame.border_row(border, 34);
+    }
+    output.write_all(frame.text.as_bytes())
+}
+
+struct Frame {
+    text: String,
+    colors: ColorMode,
+    /// Escape codes of the current colors.
+    current: Option<String>,
+}
+
+impl Frame {
+    fn push(&mut self, fg: Color, bg: Color, c: char) {
+        let codes = format!("\x1b[{};{}m",
+                            self.code(38, fg),
+                            self.code(48, bg));
+        if self.current.as_ref() != Some(&codes) {
+            self.text.push_str(&codes);
+            self.current = Some(codes);
+        }
+        self.text.push(c);
+    }
+
+    fn border_row(&mut self, border: Color, width: usize) {
+        for _ in 0..width {
+            self.push(border, border, ' ');
+        }
+        self.end_row();
+    }
+
+    fn end_row(&mut self) {
+        // The terminal is in raw mode
+        self.text.push_str("\x1b[0m\r\n");
+        self.current = None;
+    }
+
+    fn code(&self, layer: u8, color: Color) -> String {
+        let (r, g, b) = (component(color.r),
+                         component(color.g),
+                         component(color.b));
+        match self.colors {
+            ColorMode::TrueColor => format!("{};2;{};{};{}", layer, r, g, b),
+            ColorMode::Ansi256 => {
+                let cube = |c| (c as u16 * 5 + 127) / 255;
+                format!("{};5;{}",
+                        layer,
+                        16 + 36 * cube(r) + 6 * cube(g) + cube(b))
+            }
+        }
+    }
+}
+
+fn component(c: f32) -> u8 {
+    (c * 255.).round() as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::device::lem1802::{DEFAULT_FONT, DEFAULT_PALETTE};
+
+    fn screen() -> RawScreen {
+        let mut vram = Vram([0; 386]);
+        // White 'H' on black, then a blinking 'i'
+        vram.0[0] = 0xf048;
+        vram.0[1] = 0xf069 | MASK_BLINKING;
+        RawScreen {
+            vram: vram,
+            font: Font(DEFAULT_FONT),
+            palette: DEFAULT_PALETTE,
+            border: 0x00f,
+            blink_on: false,
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let mut output = vec![];
+        render(&mut output,
+               &screen(),
+               Options {
+                   pixels: false,
+                   colors: ColorMode::Ansi256,
+               })
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.split("\r\n").collect();
+        assert_eq!(lines.len(), 15);
+        assert!(lines[0].starts_with("\x1b[H\x1b[38;5;21;48;5;21m "));
+        assert!(lines[1].starts_with("\x1b[38;5;21;48;5;21m \
+                                      \x1b[38;5;231;48;5;16mH\
+                                      \x1b[38;5;16;48;5;16mi"));
+    }
+
+    #[test]
+    fn test_pixels() {
+        let mut output = vec![];
+        render(&mut output,
+               &screen(),
+               Options {
+                   pixels: true,
+                   colors: ColorMode::TrueColor,
+               })
+            .unwrap();
+        let output = String::from_utf8(output).unwrap();
+        let lines: Vec<&str> = output.split("\r\n").collect();
+        assert_eq!(lines.len(), 51);
+        assert_eq!(lines[1].matches('▀').count(), 128);
+    }
+}
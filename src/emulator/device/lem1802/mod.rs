@@ -1,4 +1,7 @@
+pub mod ansi;
+mod font;
 pub mod generic_backend;
+pub mod raster;
 mod screen;
 mod serde;
 
@@ -10,9 +13,15 @@ use enum_primitive::FromPrimitive;
 
 use emulator::cpu::Cpu;
 use emulator::device::*;
+pub use emulator::device::lem1802::font::*;
 pub use emulator::device::lem1802::screen::*;
+use emulator::device::lem1802::generic_backend::ScreenBackend;
+use emulator::device::record::RecordBackend;
 use types::Register;
 
+/// Number of ticks between two toggles of the blinking characters.
+pub const BLINK_TICKS: u64 = 50_000;
+
 enum_from_primitive! {
 #[allow(non_camel_case_types)]
 #[derive(Debug)]
@@ -21,6 +30,8 @@ enum Command {
     MEM_MAP_FONT = 0x1,
     MEM_MAP_PALETTE = 0x2,
     SET_BORDER_COLOR = 0x3,
+    MEM_DUMP_FONT = 0x4,
+    MEM_DUMP_PALETTE = 0x5,
 }
 }
 
@@ -36,6 +47,8 @@ pub struct LEM1802<B: Backend> {
     font_map: Wrapping<u16>,
     palette_map: Wrapping<u16>,
     border_color_index: u16,
+    /// Whether the blinking characters are currently shown.
+    blink_on: bool,
     backend: B,
 }
 
@@ -46,6 +59,7 @@ impl<B: Backend> LEM1802<B> {
             font_map: Wrapping(0),
             palette_map: Wrapping(0),
             border_color_index: 0,
+            blink_on: true,
             backend: backend,
         }
     }
@@ -87,34 +101,50 @@ impl<B: Backend> Device for LEM1802<B> {
             Command::MEM_MAP_PALETTE => self.palette_map = Wrapping(b),
             Command::SET_BORDER_COLOR =>
                 self.border_color_index = b & MASK_INDEX,
+            Command::MEM_DUMP_FONT => {
+                cpu.copy(DEFAULT_FONT.iter(), b);
+                return Ok(256);
+            }
+            Command::MEM_DUMP_PALETTE => {
+                cpu.copy(DEFAULT_PALETTE.iter(), b);
+                return Ok(16);
+            }
         }
         Ok(0)
     }
 
     fn tick(&mut self, cpu: &mut Cpu, tick_count: u64) -> Result<TickResult> {
+        self.blink_on = (tick_count / BLINK_TICKS) % 2 == 0;
         try!(self.backend.tick(cpu, self, tick_count));
         Ok(TickResult::Nothing)
     }
 
-    fn inspect(&self) {
-        println!("LEM1802");
+    fn reset(&mut self) -> Result<()> {
+        self.video_map = Wrapping(0);
+        self.font_map = Wrapping(0);
+        self.palette_map = Wrapping(0);
+        self.border_color_index = 0;
+        self.blink_on = true;
+        self.backend.hide()
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        let info = DeviceInfo::new("LEM1802", self);
         if self.video_map.0 == 0 {
-            println!("Currently disabled");
-        } else {
-            println!("Video ram starts at 0x{:x}", self.video_map.0);
-            if self.font_map.0 == 0 {
-                println!("Use builtin font");
+            return info.with("video ram", "disabled");
+        }
+        info.with("video ram", format!("0x{:0>4x}", self.video_map.0))
+            .with("font", if self.font_map.0 == 0 {
+                "builtin".into()
             } else {
-                println!("Font starts at 0x{:x}", self.font_map.0);
-            }
-            if self.palette_map.0 == 0 {
-                println!("Use builtin palette");
+                format!("0x{:0>4x}", self.font_map.0)
+            })
+            .with("palette", if self.palette_map.0 == 0 {
+                "builtin".into()
             } else {
-                println!("Palette starts at 0x{:x}", self.palette_map.0);
-            }
-            println!("Border color is {:?}",
-                     Color::from_packed(self.border_color_index));
-        }
+                format!("0x{:0>4x}", self.palette_map.0)
+            })
+            .with("border color", format!("{}", self.border_color_index))
     }
 
     fn as_any(&mut self) -> &mut Any {
@@ -123,13 +153,13 @@ impl<B: Backend> Device for LEM1802<B> {
 }
 
 impl<B: Backend> LEM1802<B> {
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
     pub fn get_raw_screen(&self, cpu: &Cpu) -> Option<Box<RawScreen>> {
         if self.video_map.0 != 0 {
-            let mut raw_screen = Box::new(RawScreen {
-                vram: Vram([0; 386]),
-                font: Font(self.get_raw_font(cpu)),
-                palette: self.get_raw_palette(cpu),
-            });
+            let mut raw_screen = self.get_blank_screen(cpu);
             for (from, to) in cpu.ram
                                  .iter_wrap(self.video_map.0)
                                  .zip(raw_screen.vram.0.iter_mut()) {
@@ -141,6 +171,18 @@ impl<B: Backend> LEM1802<B> {
         }
     }
 
+    /// Blank screen shown while the video ram is not mapped.
+    fn get_blank_screen(&self, cpu: &Cpu) -> Box<RawScreen> {
+        let palette = self.get_raw_palette(cpu);
+        Box::new(RawScreen {
+            vram: Vram([0; 386]),
+            font: Font(self.get_raw_font(cpu)),
+            palette: palette,
+            border: palette[self.border_color_index as usize],
+            blink_on: self.blink_on,
+        })
+    }
+
     fn get_raw_font(&self, cpu: &Cpu) -> [u16; 256] {
         if self.font_map.0 == 0 {
             DEFAULT_FONT
@@ -170,46 +212,60 @@ impl<B: Backend> LEM1802<B> {
     }
 }
 
-// Taken from
-// https://github.com/azertyfun/DCPU-Toolchain/blob/master/src/tk/azertyfun/dcputoolchain/emulator/LEM1802.java
-const DEFAULT_FONT: [u16; 256] = [
-    0x000F, 0x0808, 0x080F, 0x0808, 0x08F8, 0x0808, 0x00FF, 0x0808,
-    0x0808, 0x0808, 0x08FF, 0x0808, 0x00FF, 0x1414, 0xFF00, 0xFF08,
-    0x1F10, 0x1714, 0xFC04, 0xF414, 0x1710, 0x1714, 0xF404, 0xF414,
-    0xFF00, 0xF714, 0x1414, 0x1414, 0xF700, 0xF714, 0x1417, 0x1414,
-    0x0F08, 0x0F08, 0x14F4, 0x1414, 0xF808, 0xF808, 0x0F08, 0x0F08,
-    0x001F, 0x1414, 0x00FC, 0x1414, 0xF808, 0xF808, 0xFF08, 0xFF08,
-    0x14FF, 0x1414, 0x080F, 0x0000, 0x00F8, 0x0808, 0xFFFF, 0xFFFF,
-    0xF0F0, 0xF0F0, 0xFFFF, 0x0000, 0x0000, 0xFFFF, 0x0F0F, 0x0F0F,
-    0x0000, 0x0000, 0x005f, 0x0000, 0x0300, 0x0300, 0x3e14, 0x3e00,
-    0x266b, 0x3200, 0x611c, 0x4300, 0x3629, 0x7650, 0x0002, 0x0100,
-    0x1c22, 0x4100, 0x4122, 0x1c00, 0x1408, 0x1400, 0x081c, 0x0800,
-    0x4020, 0x0000, 0x0808, 0x0800, 0x0040, 0x0000, 0x601c, 0x0300,
-    0x3e49, 0x3e00, 0x427f, 0x4000, 0x6259, 0x4600, 0x2249, 0x3600,
-    0x0f08, 0x7f00, 0x2745, 0x3900, 0x3e49, 0x3200, 0x6119, 0x0700,
-    0x3649, 0x3600, 0x2649, 0x3e00, 0x0024, 0x0000, 0x4024, 0x0000,
-    0x0814, 0x2200, 0x1414, 0x1400, 0x2214, 0x0800, 0x0259, 0x0600,
-    0x3e59, 0x5e00, 0x7e09, 0x7e00, 0x7f49, 0x3600, 0x3e41, 0x2200,
-    0x7f41, 0x3e00, 0x7f49, 0x4100, 0x7f09, 0x0100, 0x3e41, 0x7a00,
-    0x7f08, 0x7f00, 0x417f, 0x4100, 0x2040, 0x3f00, 0x7f08, 0x7700,
-    0x7f40, 0x4000, 0x7f06, 0x7f00, 0x7f01, 0x7e00, 0x3e41, 0x3e00,
-    0x7f09, 0x0600, 0x3e61, 0x7e00, 0x7f09, 0x7600, 0x2649, 0x3200,
-    0x017f, 0x0100, 0x3f40, 0x7f00, 0x1f60, 0x1f00, 0x7f30, 0x7f00,
-    0x7708, 0x7700, 0x0778, 0x0700, 0x7149, 0x4700, 0x007f, 0x4100,
-    0x031c, 0x6000, 0x417f, 0x0000, 0x0201, 0x0200, 0x8080, 0x8000,
-    0x0001, 0x0200, 0x2454, 0x7800, 0x7f44, 0x3800, 0x3844, 0x2800,
-    0x3844, 0x7f00, 0x3854, 0x5800, 0x087e, 0x0900, 0x4854, 0x3c00,
-    0x7f04, 0x7800, 0x047d, 0x0000, 0x2040, 0x3d00, 0x7f10, 0x6c00,
-    0x017f, 0x0000, 0x7c18, 0x7c00, 0x7c04, 0x7800, 0x3844, 0x3800,
-    0x7c14, 0x0800, 0x0814, 0x7c00, 0x7c04, 0x0800, 0x4854, 0x2400,
-    0x043e, 0x4400, 0x3c40, 0x7c00, 0x1c60, 0x1c00, 0x7c30, 0x7c00,
-    0x6c10, 0x6c00, 0x4c50, 0x3c00, 0x6454, 0x4c00, 0x0836, 0x4100,
-    0x0077, 0x0000, 0x4136, 0x0800, 0x0201, 0x0201, 0x0205, 0x0200,
-];
-
-// Taken from
-// https://github.com/azertyfun/DCPU-Toolchain/blob/master/src/tk/azertyfun/dcputoolchain/emulator/LEM1802.java
-const DEFAULT_PALETTE: [u16; 16] = [
-    0x000, 0x00a, 0x0a0, 0x0aa, 0xa00, 0xa0a, 0xa50, 0xaaa,
-    0x555, 0x55f, 0x5f5, 0x5ff, 0xf55, 0xf5f, 0xff5, 0xfff,
-];
+/// Screen of `device` if it is a LEM1802 using one of the backends of this
+/// crate. An unmapped screen is blank.
+pub fn screen_of(device: &mut Device, cpu: &Cpu) -> Option<Box<RawScreen>> {
+    fn get<B: Backend>(lem: &LEM1802<B>, cpu: &Cpu) -> Box<RawScreen> {
+        lem.get_raw_screen(cpu).unwrap_or_else(|| lem.get_blank_screen(cpu))
+    }
+
+    let any = device.as_any();
+    if let Some(lem) = any.downcast_ref::<LEM1802<ScreenBackend>>() {
+        return Some(get(lem, cpu));
+    }
+    any.downcast_ref::<LEM1802<RecordBackend>>().map(|lem| get(lem, cpu))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+
+    use super::*;
+
+    #[test]
+    fn test_dump_and_blink() {
+        let backend = ScreenBackend::new(Arc::new(Mutex::new(())), |_| Ok(()));
+        let mut lem = LEM1802::new(backend);
+        let mut cpu = Cpu::default();
+
+        cpu.registers[Register::A] = Command::MEM_DUMP_FONT as u16;
+        cpu.registers[Register::B] = 0xff80;
+        assert_eq!(lem.interrupt(&mut cpu).unwrap(), 256);
+        assert_eq!(cpu.ram[0xff80], DEFAULT_FONT[0]);
+        assert_eq!(cpu.ram[0x007f], DEFAULT_FONT[255]);
+        cpu.registers[Register::A] = Command::MEM_DUMP_PALETTE as u16;
+        cpu.registers[Register::B] = 0x1000;
+        assert_eq!(lem.interrupt(&mut cpu).unwrap(), 16);
+        assert_eq!(&cpu.ram[0x1000..0x1010], &DEFAULT_PALETTE[..]);
+
+        // Blinking white 'A' on black, in the top left corner
+        cpu.ram[0x8000] = 0xf041 | MASK_BLINKING;
+        cpu.registers[Register::A] = Command::MEM_MAP_SCREEN as u16;
+        cpu.registers[Register::B] = 0x8000;
+        lem.interrupt(&mut cpu).unwrap();
+        cpu.registers[Register::A] = Command::SET_BORDER_COLOR as u16;
+        cpu.registers[Register::B] = 2;
+        lem.interrupt(&mut cpu).unwrap();
+
+        let lit = |lem: &LEM1802<ScreenBackend>, cpu: &Cpu| {
+            let raw = lem.get_raw_screen(cpu).unwrap();
+            assert_eq!(raw.border, DEFAULT_PALETTE[2]);
+            let screen: Box<Screen> = raw.into();
+            screen.0[..CHAR_WIDTH as usize].iter().any(|c| c.r > 0.)
+        };
+        lem.tick(&mut cpu, 1).unwrap();
+        assert!(lit(&lem, &cpu));
+        lem.tick(&mut cpu, BLINK_TICKS).unwrap();
+        assert!(!lit(&lem, &cpu));
+    }
+}
@@ -0,0 +1,48 @@
+//! Software rendering of the screen and its border to RGB frames.
+
+use emulator::device::lem1802::screen::*;
+use emulator::device::record::Frame;
+
+/// Render `screen` in its current blink phase, each LEM1802 pixel being a
+/// `scale` x `scale` square.
+pub fn render(screen: &RawScreen, scale: usize) -> Frame {
+    let border = screen.border_color();
+    let mut pixels = Screen([border; SCREEN_SIZE as usize]);
+    for offset in 0..NB_CHARS {
+        screen.add_char(&mut pixels, offset);
+    }
+    let mut frame = filled(rgb(border), scale);
+    let margin = BORDER_SIZE as usize;
+    for (i, color) in pixels.0.iter().enumerate() {
+        let x = margin + i % SCREEN_WIDTH as usize;
+        let y = margin + i / SCREEN_WIDTH as usize;
+        fill_pixel(&mut frame, x, y, scale, rgb(*color));
+    }
+    frame
+}
+
+/// Black frame of a disconnected screen.
+pub fn blank(scale: usize) -> Frame {
+    filled([0; 3], scale)
+}
+
+fn filled(color: [u8; 3], scale: usize) -> Frame {
+    Frame::filled((SCREEN_WIDTH + 2 * BORDER_SIZE) as usize * scale,
+                  (SCREEN_HEIGHT + 2 * BORDER_SIZE) as usize * scale,
+                  color)
+}
+
+fn fill_pixel(frame: &mut Frame, x: usize, y: usize, scale: usize, color: [u8; 3]) {
+    for dy in 0..scale {
+        let start = (y * scale + dy) * frame.width + x * scale;
+        for pixel in &mut frame.pixels[start..start + scale] {
+            *pixel = color;
+        }
+    }
+}
+
+fn rgb(color: Color) -> [u8; 3] {
+    [(color.r * 255.).round() as u8,
+     (color.g * 255.).round() as u8,
+     (color.b * 255.).round() as u8]
+}
@@ -14,6 +14,8 @@ pub const MASK_COLOR_IDX: u16 = 0xf;
 pub const MASK_CHAR: u16 = 0x7f;
 pub const SHIFT_FG: u16 = 12;
 pub const SHIFT_BG: u16 = 8;
+/// Width of the border around the screen, in pixels.
+pub const BORDER_SIZE: u16 = 8;
 
 /// Wrappers for easier serde implementation
 pub struct Vram(pub [u16; 386]);
@@ -24,6 +26,11 @@ pub struct RawScreen {
     pub vram: Vram,
     pub font: Font,
     pub palette: [u16; 16],
+    /// Packed color of the border.
+    pub border: u16,
+    /// Whether the blinking characters are shown or replaced by their
+    /// background.
+    pub blink_on: bool,
 }
 
 impl fmt::Debug for RawScreen {
@@ -46,7 +53,11 @@ impl From<Box<RawScreen>> for Box<Screen> {
 impl RawScreen {
     pub fn add_char(&self, screen: &mut Screen, char_offset: u16) {
         let video_word = self.get_video_word(char_offset);
-        let font_item = self.get_font(video_word.char_idx);
+        let font_item = if video_word.blinking && !self.blink_on {
+            0
+        } else {
+            self.get_font(video_word.char_idx)
+        };
         // x and y are coordinates from top left, but the font items have a different layout so we
         // have to correct it.
         for x in 0..CHAR_WIDTH {
@@ -69,6 +80,43 @@ impl RawScreen {
         }
     }
 
+    /// Character and colors at `char_offset`. Hidden blinking characters
+    /// have the background color as foreground.
+    pub fn cell(&self, char_offset: u16) -> Cell {
+        let video_word = self.get_video_word(char_offset);
+        let bg = self.get_color(video_word.bg_idx);
+        Cell {
+            char_idx: video_word.char_idx,
+            fg: if video_word.blinking && !self.blink_on {
+                bg
+            } else {
+                self.get_color(video_word.fg_idx)
+            },
+            bg: bg,
+        }
+    }
+
+    /// Characters of the screen, one line by row. Printable ASCII characters
+    /// are kept, `0` is shown as a space and the others as `?`.
+    pub fn text(&self) -> String {
+        let mut text = String::new();
+        for row in 0..12 {
+            for col in 0..32 {
+                text.push(match self.get_video_word(row * 32 + col).char_idx {
+                    0 => ' ',
+                    c @ 0x20...0x7e => c as u8 as char,
+                    _ => '?',
+                });
+            }
+            text.push('\n');
+        }
+        text
+    }
+
+    pub fn border_color(&self) -> Color {
+        Color::from_packed(self.border)
+    }
+
     fn get_video_word(&self, char_offset: u16) -> VideoWord {
         VideoWord::from_packed(self.vram.0[char_offset as usize])
     }
@@ -85,6 +133,13 @@ impl RawScreen {
     }
 }
 
+#[derive(Debug, Copy, Clone)]
+pub struct Cell {
+    pub char_idx: u16,
+    pub fg: Color,
+    pub bg: Color,
+}
+
 pub struct Screen(pub [Color; SCREEN_SIZE as usize]);
 
 impl fmt::Debug for Screen {
@@ -1,20 +1,32 @@
 use std::{fmt, io};
 use std::fs::File;
+use std::io::Write;
 use std::path::Path;
 use std::any::Any;
 
 use enum_primitive::FromPrimitive;
 
-use byteorder::{self, ReadBytesExt};
+use byteorder::{self, WriteBytesExt};
 use emulator::Cpu;
+use emulator::bbfs;
 use emulator::device::*;
+use emulator::device::disk::{self, Backing, Geometry};
+pub use emulator::device::disk::WriteMode;
 use emulator::Ram;
 use types::Register;
 
+/// Words by sector.
+pub const SECTOR_SIZE: usize = 512;
+/// Sectors of a floppy.
+pub const SECTORS: usize = 1440;
 const NB_SECTORS_BY_TRACK: u16 = 18;
-const NB_SECTORS_TOTAL: u16 = 1440;
-const SECTOR_SIZE_WORD: u32 = 512;
+const NB_SECTORS_TOTAL: u16 = SECTORS as u16;
 const TRACK_SEEKING_TIME: u64 = 100_000 * 10_000 / 24;
+const GEOMETRY: Geometry = Geometry {
+    tracks: NB_SECTORS_TOTAL / NB_SECTORS_BY_TRACK,
+    sectors_per_track: NB_SECTORS_BY_TRACK,
+    words_per_sector: SECTOR_SIZE as u16,
+};
 
 enum_from_primitive! {
 #[allow(non_camel_case_types)]
@@ -49,8 +61,10 @@ enum ErrorCode {
 }
 
 pub struct Floppy {
-    data: Box<[Sector; NB_SECTORS_TOTAL as usize]>,
+    data: Box<[Sector; SECTORS]>,
     write_protected: bool,
+    /// Image receiving the written sectors in write-back mode.
+    backing: Option<Backing>,
 }
 
 impl fmt::Debug for Floppy {
@@ -59,7 +73,7 @@ impl fmt::Debug for Floppy {
     }
 }
 
-type Sector = [u16; SECTOR_SIZE_WORD as usize];
+type Sector = [u16; SECTOR_SIZE];
 
 #[derive(Debug)]
 pub struct M35fd {
@@ -149,10 +163,11 @@ impl Device for M35fd {
     }
 
     fn tick(&mut self, cpu: &mut Cpu, _current_tick: u64) -> Result<TickResult> {
+        let mut result = Ok(());
         let modification = if let Some(ref mut op) = self.current_operation {
             if let Some(ref mut f) = self.floppy {
                 if op.tick_delay == 0 {
-                    f.do_operation(op, &mut cpu.ram);
+                    result = f.do_operation(op, cpu);
                     self.last_error = ErrorCode::None;
                     true
                 } else {
@@ -170,6 +185,7 @@ impl Device for M35fd {
         if modification {
             self.current_operation = None;
         }
+        try!(result);
 
         Ok(if (modification | self.do_int_next_tick) && self.int_msg != 0 {
             self.do_int_next_tick = false;
@@ -179,24 +195,28 @@ impl Device for M35fd {
         })
     }
 
-    fn inspect(&self) {
-        println!("m35fd");
-        println!("Int message is 0x{:x}", self.int_msg);
-        if let Some(ref floppy) = self.floppy {
-            println!("Floppy loaded ({})", if floppy.write_protected {
-                "read-write"
-            } else {
-                "read only"
+    fn reset(&mut self) -> Result<()> {
+        self.last_error = ErrorCode::None;
+        self.int_msg = 0;
+        self.current_operation = None;
+        self.current_sector = 0;
+        self.do_int_next_tick = false;
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        DeviceInfo::new("m35fd", self)
+            .with("int message", format!("0x{:x}", self.int_msg))
+            .with("floppy", match self.floppy {
+                Some(ref floppy) if floppy.write_protected => "read only".into(),
+                Some(Floppy { backing: Some(ref backing), .. }) => {
+                    format!("read-write, saved to {}", backing.path().display())
+                }
+                Some(_) => "read-write".into(),
+                None => "none".into(),
             })
-        } else {
-            println!("No floppy loaded");
-        }
-        if self.current_operation.is_some() {
-            println!("Disk operation in progress");
-        } else {
-            println!("No disk operation in progress");
-        }
-        println!("Last error: {:?}", self.last_error);
+            .with("operation in progress", self.current_operation.is_some())
+            .with("last error", format!("{:?}", self.last_error))
     }
 
     fn as_any(&mut self) -> &mut Any {
@@ -205,36 +225,92 @@ impl Device for M35fd {
 }
 
 impl Floppy {
-    fn do_operation(&mut self, op: &DiskOperation, ram: &mut Ram) {
+    fn do_operation(&mut self, op: &DiskOperation, cpu: &mut Cpu) -> io::Result<()> {
         match op.side {
-            Side::Read => ram.copy(self.data[op.sector as usize].iter(),
-                                   op.address),
-            Side::Write => {
-                for (from, to) in ram.iter_wrap(op.address)
-                                     .zip(self.data[op.sector as usize]
-                                          .iter_mut()) {
-                    *to = *from;
-                }
+            Side::Read => {
+                self.read_sector(op.sector, cpu, op.address);
+                Ok(())
             }
+            Side::Write => self.write_sector(op.sector, &cpu.ram, op.address),
         }
     }
 
+    /// Copy `sector` to the ram at once, without the drive timings.
+    pub fn read_sector(&self, sector: u16, cpu: &mut Cpu, address: u16) {
+        cpu.copy(self.data[sector as usize].iter(), address);
+    }
+
+    /// Copy the ram to `sector` at once, and to the image in write-back
+    /// mode. The write protection is left to the caller.
+    pub fn write_sector(&mut self, sector: u16, ram: &Ram, address: u16) -> io::Result<()> {
+        for (from, to) in ram.iter_wrap(address)
+                             .zip(self.data[sector as usize].iter_mut()) {
+            *to = *from;
+        }
+        match self.backing {
+            Some(ref mut backing) => {
+                backing.write(sector as usize * SECTOR_SIZE, &self.data[sector as usize])
+            }
+            None => Ok(()),
+        }
+    }
+
+    pub fn sectors(&self) -> u16 {
+        NB_SECTORS_TOTAL
+    }
+
+    /// Whether the last word of the boot sector is the BBOS signature.
+    pub fn is_bootable(&self) -> bool {
+        self.data[0][SECTOR_SIZE - 1] == bbfs::BOOT_SIGNATURE
+    }
+
+    /// Load an image, missing words being 0 and extra ones ignored.
     pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Floppy> {
-        let mut input = try!(File::open(path));
-        let words = input.iter_items::<u16, byteorder::LittleEndian>();
         let mut floppy = Floppy::default();
-        for (from, to) in words.zip(floppy.data.iter_mut().flat_map(|s| s.iter_mut())) {
-            *to = from;
+        try!(disk::load_image(path, floppy.data.iter_mut().flat_map(|s| s.iter_mut())));
+        Ok(floppy)
+    }
+
+    /// Load an image, saving the written sectors to it in write-back mode.
+    pub fn open<P: AsRef<Path>>(path: P, mode: WriteMode) -> io::Result<Floppy> {
+        let mut floppy = try!(Floppy::load(&path));
+        if mode == WriteMode::WriteBack {
+            floppy.backing = Some(try!(Backing::open(path)));
         }
         Ok(floppy)
     }
+
+    /// Write a blank image of the size of a floppy and return the floppy.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Floppy> {
+        let floppy = Floppy::default();
+        try!(floppy.save(path));
+        Ok(floppy)
+    }
+
+    /// Write the whole floppy to an image.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let mut output = io::BufWriter::new(try!(File::create(path)));
+        for sector in self.data.iter() {
+            try!(output.write_all_items::<u16, byteorder::LittleEndian>(sector));
+        }
+        output.flush()
+    }
+
+    pub fn is_write_protected(&self) -> bool {
+        self.write_protected
+    }
+
+    pub fn set_write_protected(&mut self, write_protected: bool) {
+        self.write_protected = write_protected;
+    }
 }
 
 impl Default for Floppy {
     fn default() -> Floppy {
         Floppy {
-            data: Box::new([[0; SECTOR_SIZE_WORD as usize]; NB_SECTORS_TOTAL as usize]),
+            data: Box::new([[0; SECTOR_SIZE]; SECTORS]),
             write_protected: false,
+            backing: None,
         }
     }
 }
@@ -261,6 +337,14 @@ impl M35fd {
         self.floppy = Some(floppy);
     }
 
+    pub fn floppy(&self) -> Option<&Floppy> {
+        self.floppy.as_ref()
+    }
+
+    pub fn floppy_mut(&mut self) -> Option<&mut Floppy> {
+        self.floppy.as_mut()
+    }
+
     fn pote(&mut self) {
         if self.current_operation.is_some() {
             self.current_operation = None;
@@ -294,10 +378,43 @@ impl M35fd {
 }
 
 fn sector_distance(from: u16, to: u16) -> u64 {
-    let sectors_to_skip = ((from % NB_SECTORS_BY_TRACK) as i16
-                           - (to % NB_SECTORS_BY_TRACK) as i16).abs() as u64;
-    let tracks_to_skip = ((from / NB_SECTORS_BY_TRACK) as i16
-                          - (to / NB_SECTORS_BY_TRACK) as i16).abs() as u64;
-    tracks_to_skip * TRACK_SEEKING_TIME
-        + sectors_to_skip * TRACK_SEEKING_TIME / (NB_SECTORS_BY_TRACK as u64)
+    GEOMETRY.seek_ticks(from, to, TRACK_SEEKING_TIME)
+}
+
+#[cfg(test)]
+mod tests {
+    use emulator::test_utils::temp_path;
+
+    use super::*;
+
+    #[test]
+    fn test_write_back() {
+        let path = temp_path("write-back.img");
+        Floppy::create(&path).unwrap();
+        let mut m35fd = M35fd::new(Floppy::open(&path, WriteMode::WriteBack).unwrap());
+        let mut cpu = Cpu::default();
+        cpu.registers[Register::A] = Command::WRITE_SECTOR as u16;
+        cpu.registers[Register::X] = 0;
+        cpu.registers[Register::Y] = 0x1000;
+        cpu.ram[0x1000] = 0xcafe;
+        m35fd.interrupt(&mut cpu).unwrap();
+        assert_eq!(cpu.registers[Register::B], 1);
+        m35fd.tick(&mut cpu, 0).unwrap();
+
+        let image = Floppy::load(&path).unwrap();
+        assert_eq!(image.data[0][0], 0xcafe);
+        assert_eq!(image.data[0][1], 0x3e0);
+        assert_eq!(image.data[1][0], 0);
+
+        // Through the same handle
+        cpu.ram[0x1000] = 0xbeef;
+        m35fd.floppy_mut().unwrap().write_sector(5, &cpu.ram, 0x1000).unwrap();
+        let image = Floppy::load(&path).unwrap();
+        assert_eq!(image.data[0][0], 0xcafe);
+        assert_eq!(image.data[5][0], 0xbeef);
+        m35fd.floppy().unwrap().save(&path).unwrap();
+        assert_eq!(::std::fs::metadata(&path).unwrap().len(),
+                   SECTORS as u64 * SECTOR_SIZE as u64 * 2);
+        ::std::fs::remove_file(&path).unwrap();
+    }
 }
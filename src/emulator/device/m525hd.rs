@@ -0,0 +1,279 @@
+//! Mackapar M525HD hard disk. It is driven like the m35fd, with sectors of
+//! 512 words and a configurable number of tracks, plus commands to park the
+//! heads. A parked disk needs `SPIN_UP_TIME` ticks to be ready again.
+
+use std::any::Any;
+
+use enum_primitive::FromPrimitive;
+
+use emulator::cpu::Cpu;
+use emulator::device::*;
+use emulator::device::disk::{Geometry, Media};
+use types::Register;
+
+/// Default geometry, 5120 sectors.
+pub const M525HD: Geometry = Geometry {
+    tracks: 128,
+    sectors_per_track: 40,
+    words_per_sector: 512,
+};
+/// Ticks to move the head to the next track.
+const TRACK_SEEKING_TIME: u64 = 100_000 / 1000;
+/// Ticks to transfer a sector under the head.
+const SECTOR_TIME: u64 = 100_000 / 1000;
+const SPIN_UP_TIME: u64 = 100_000;
+
+enum_from_primitive! {
+#[allow(non_camel_case_types)]
+#[derive(Debug, Copy, Clone)]
+enum Command {
+    POLL_DEVICE = 0,
+    SET_INT = 1,
+    READ_SECTOR = 2,
+    WRITE_SECTOR = 3,
+    SPIN_DOWN = 4,
+    SPIN_UP = 5,
+}
+}
+
+#[derive(Debug, Copy, Clone)]
+enum StateCode {
+    Ready = 1,
+    ReadyWP = 2,
+    Busy = 3,
+    Parked = 4,
+    SpinningUp = 5,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ErrorCode {
+    None = 0,
+    Busy = 1,
+    Protected = 3,
+    BadSector = 5,
+    Parked = 6,
+}
+
+#[derive(Debug)]
+struct DiskOperation {
+    tick_delay: u64,
+    sector: u16,
+    address: u16,
+    side: Side,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Side {
+    Read,
+    Write,
+    SpinUp,
+}
+
+#[derive(Debug)]
+pub struct M525hd {
+    media: Media,
+    parked: bool,
+    last_error: ErrorCode,
+    int_msg: u16,
+    current_operation: Option<DiskOperation>,
+    current_sector: u16,
+}
+
+impl M525hd {
+    pub fn new(media: Media) -> M525hd {
+        M525hd {
+            media: media,
+            parked: false,
+            last_error: ErrorCode::None,
+            int_msg: 0,
+            current_operation: None,
+            current_sector: 0,
+        }
+    }
+
+    fn state(&self) -> StateCode {
+        match self.current_operation {
+            Some(DiskOperation { side: Side::SpinUp, .. }) => StateCode::SpinningUp,
+            Some(_) => StateCode::Busy,
+            None if self.parked => StateCode::Parked,
+            None if self.media.write_protected => StateCode::ReadyWP,
+            None => StateCode::Ready,
+        }
+    }
+
+    fn start_operation(&mut self, cpu: &mut Cpu, side: Side) {
+        let sector = cpu.registers[Register::X];
+        let address = cpu.registers[Register::Y];
+        self.last_error = if self.current_operation.is_some() {
+            ErrorCode::Busy
+        } else if self.parked {
+            ErrorCode::Parked
+        } else if sector as u32 >= self.media.geometry().sectors() {
+            ErrorCode::BadSector
+        } else if side == Side::Write && self.media.write_protected {
+            ErrorCode::Protected
+        } else {
+            let delay = self.media.geometry().seek_ticks(self.current_sector,
+                                                         sector,
+                                                         TRACK_SEEKING_TIME) +
+                        SECTOR_TIME;
+            self.current_operation = Some(DiskOperation {
+                tick_delay: delay,
+                sector: sector,
+                address: address,
+                side: side,
+            });
+            ErrorCode::None
+        };
+        cpu.registers[Register::B] = (self.last_error == ErrorCode::None) as u16;
+    }
+}
+
+impl Device for M525hd {
+    fn hardware_id(&self) -> u32 {
+        0x4ac5f6a9
+    }
+
+    fn hardware_version(&self) -> u16 {
+        0x0004
+    }
+
+    fn manufacturer(&self) -> u32 {
+        0x1eb37e91
+    }
+
+    fn interrupt(&mut self, cpu: &mut Cpu) -> Result<InterruptDelay> {
+        let a = cpu.registers[Register::A];
+        match try!(Command::from_u16(a)
+                           .ok_or(ErrorKind::InvalidCommand(a))) {
+            Command::POLL_DEVICE => {
+                cpu.registers[Register::B] = self.state() as u16;
+                cpu.registers[Register::C] = self.last_error as u16;
+            }
+            Command::SET_INT => self.int_msg = cpu.registers[Register::X],
+            Command::READ_SECTOR => self.start_operation(cpu, Side::Read),
+            Command::WRITE_SECTOR => self.start_operation(cpu, Side::Write),
+            Command::SPIN_DOWN => {
+                if self.current_operation.is_none() {
+                    self.parked = true;
+                    self.current_sector = 0;
+                }
+            }
+            Command::SPIN_UP => {
+                if self.parked && self.current_operation.is_none() {
+                    self.current_operation = Some(DiskOperation {
+                        tick_delay: SPIN_UP_TIME,
+                        sector: 0,
+                        address: 0,
+                        side: Side::SpinUp,
+                    });
+                }
+            }
+        }
+        Ok(0)
+    }
+
+    fn tick(&mut self, cpu: &mut Cpu, _current_tick: u64) -> Result<TickResult> {
+        let finished = if let Some(ref mut op) = self.current_operation {
+            if op.tick_delay == 0 {
+                true
+            } else {
+                op.tick_delay -= 1;
+                false
+            }
+        } else {
+            false
+        };
+        if !finished {
+            return Ok(TickResult::Nothing);
+        }
+
+        let op = self.current_operation.take().unwrap();
+        match op.side {
+            Side::Read => self.media.read(op.sector, 1, cpu, op.address),
+            Side::Write => try!(self.media.write(op.sector, 1, &cpu.ram, op.address)),
+            Side::SpinUp => self.parked = false,
+        }
+        if op.side != Side::SpinUp {
+            self.current_sector = op.sector;
+        }
+        self.last_error = ErrorCode::None;
+        Ok(if self.int_msg != 0 {
+            TickResult::Interrupt(self.int_msg)
+        } else {
+            TickResult::Nothing
+        })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.parked = false;
+        self.last_error = ErrorCode::None;
+        self.int_msg = 0;
+        self.current_operation = None;
+        self.current_sector = 0;
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        let geometry = self.media.geometry();
+        DeviceInfo::new("M525HD", self)
+            .with("int message", format!("0x{:x}", self.int_msg))
+            .with("geometry",
+                  format!("{} tracks of {} sectors",
+                          geometry.tracks,
+                          geometry.sectors_per_track))
+            .with("state", format!("{:?}", self.state()))
+            .with("last error", format!("{:?}", self.last_error))
+    }
+
+    fn as_any(&mut self) -> &mut Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use emulator::test_utils::command;
+
+    use super::*;
+
+    fn wait_interrupt(hd: &mut M525hd, cpu: &mut Cpu) -> u64 {
+        let mut ticks = 0;
+        while hd.tick(cpu, ticks).unwrap() == TickResult::Nothing {
+            ticks += 1;
+        }
+        ticks
+    }
+
+    #[test]
+    fn test_park_and_read() {
+        let mut hd = M525hd::new(Media::new(M525HD));
+        let mut cpu = Cpu::default();
+        cpu.registers[Register::X] = 1;
+        command(&mut hd, &mut cpu, Command::SET_INT as u16, &[]);
+
+        command(&mut hd, &mut cpu, Command::SPIN_DOWN as u16, &[]);
+        cpu.registers[Register::X] = 5119;
+        cpu.registers[Register::Y] = 0x1000;
+        command(&mut hd, &mut cpu, Command::READ_SECTOR as u16, &[]);
+        assert_eq!(cpu.registers[Register::B], 0);
+        command(&mut hd, &mut cpu, Command::SPIN_UP as u16, &[]);
+        assert_eq!(wait_interrupt(&mut hd, &mut cpu), SPIN_UP_TIME);
+
+        cpu.registers[Register::X] = 5119;
+        command(&mut hd, &mut cpu, Command::READ_SECTOR as u16, &[]);
+        assert_eq!(cpu.registers[Register::B], 1);
+        // 127 tracks and 39 sectors away
+        assert_eq!(wait_interrupt(&mut hd, &mut cpu),
+                   127 * TRACK_SEEKING_TIME + 39 * TRACK_SEEKING_TIME / 40 +
+                   SECTOR_TIME);
+        assert_eq!(cpu.ram[0x1000], 0);
+        command(&mut hd, &mut cpu, Command::POLL_DEVICE as u16, &[]);
+        assert_eq!(cpu.registers[Register::C], ErrorCode::None as u16);
+
+        cpu.registers[Register::X] = 5120;
+        command(&mut hd, &mut cpu, Command::READ_SECTOR as u16, &[]);
+        command(&mut hd, &mut cpu, Command::POLL_DEVICE as u16, &[]);
+        assert_eq!(cpu.registers[Register::C], ErrorCode::BadSector as u16);
+    }
+}
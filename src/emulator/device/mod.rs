@@ -1,36 +1,69 @@
+pub mod bbos;
 pub mod clock;
+pub mod disk;
 pub mod dummy;
 pub mod eeprom;
 pub mod hic;
+pub mod hmd2043;
 pub mod keyboard;
 pub mod lem1802;
 pub mod m35fd;
+pub mod m525hd;
+pub mod record;
+pub mod spc2000;
+pub mod sped3;
 
 #[cfg(feature = "glium")]
 pub mod glium_backend;
+#[cfg(feature = "termscreen")]
+pub mod term_backend;
 
 use std::any::Any;
-use std::fmt::Debug;
+use std::fmt::{self, Debug};
 
 use emulator::cpu::Cpu;
 
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum TickResult {
     Nothing,
     Interrupt(u16),
+    /// Jump this number of ticks forward without executing instructions.
+    /// All the devices are told with `Device::skip_time`.
+    SkipTime(u64),
 }
 
 pub type InterruptDelay = u16;
 
+/// Highest tick number reached by skipping time, leaving room for the
+/// devices to schedule their next events without overflowing.
+pub const MAX_TICK: u64 = ::std::u64::MAX / 2;
+
+/// Number of ticks that can be skipped from `current_tick`.
+pub fn clamp_skip(current_tick: u64, ticks: u64) -> u64 {
+    ::std::cmp::min(ticks, MAX_TICK.saturating_sub(current_tick))
+}
+
 error_chain!(
+    foreign_links {
+        Io(::std::io::Error);
+    }
+
     errors {
         InvalidCommand(cmd: u16) {
             description("invalid hardware command")
             display("invalid hardware command: {:#x}", cmd)
         }
+        InvalidArgument(value: u16) {
+            description("invalid hardware command argument")
+            display("invalid hardware command argument: {:#x}", value)
+        }
         BackendStopped(which: String) {
             description("the backend stopped")
             display("the {} backend stopped", which)
         }
+        NoBootableDrive {
+            description("no m35fd with a bootable floppy")
+        }
     }
 );
 
@@ -40,8 +73,113 @@ pub trait Device: Debug + Send + Any {
     fn manufacturer(&self) -> u32;
 
     fn interrupt(&mut self, &mut Cpu) -> Result<InterruptDelay>;
+    /// Called by `HWI` instead of `interrupt`, for devices emulating a
+    /// firmware which drives the other devices.
+    fn interrupt_with_devices(&mut self,
+                              cpu: &mut Cpu,
+                              _others: &mut OtherDevices)
+                              -> Result<InterruptDelay> {
+        self.interrupt(cpu)
+    }
     fn tick(&mut self, &mut Cpu, current_tick: u64) -> Result<TickResult>;
 
-    fn inspect(&self);
+    /// Put the device back in its power-on state. Physical state like an
+    /// inserted floppy or a connection is kept.
+    fn reset(&mut self) -> Result<()>;
+    /// Called when `ticks` ticks are skipped, before the next tick. Only
+    /// devices keeping track of the time need it.
+    fn skip_time(&mut self, _ticks: u64) {}
+    fn inspect(&self) -> DeviceInfo;
     fn as_any(&mut self) -> &mut Any;
 }
+
+/// The devices plugged besides one of them, by hardware index.
+pub struct OtherDevices<'a> {
+    before: &'a mut [Box<Device>],
+    after: &'a mut [Box<Device>],
+}
+
+impl<'a> OtherDevices<'a> {
+    /// Split `devices` between the device at `index` and the others.
+    pub fn split(devices: &'a mut [Box<Device>],
+                 index: usize)
+                 -> Option<(&'a mut Box<Device>, OtherDevices<'a>)> {
+        if index >= devices.len() {
+            return None;
+        }
+        let (before, rest) = devices.split_at_mut(index);
+        let (device, after) = rest.split_first_mut().unwrap();
+        Some((device, OtherDevices {
+            before: before,
+            after: after,
+        }))
+    }
+
+    /// No other device.
+    pub fn none() -> OtherDevices<'a> {
+        OtherDevices {
+            before: &mut [],
+            after: &mut [],
+        }
+    }
+
+    /// Number of devices, including the one left out.
+    pub fn count(&self) -> usize {
+        self.before.len() + 1 + self.after.len()
+    }
+
+    /// The device at `index`, `None` for the one left out.
+    pub fn get_mut(&mut self, index: usize) -> Option<&mut Box<Device>> {
+        let split = self.before.len();
+        if index < split {
+            Some(&mut self.before[index])
+        } else if index == split {
+            None
+        } else {
+            self.after.get_mut(index - split - 1)
+        }
+    }
+}
+
+/// Description of a device and of its current state.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct DeviceInfo {
+    pub name: String,
+    pub hardware_id: u32,
+    pub hardware_version: u16,
+    pub manufacturer: u32,
+    /// Device specific state, in display order.
+    pub state: Vec<(String, String)>,
+}
+
+impl DeviceInfo {
+    pub fn new<D: Device + ?Sized>(name: &str, device: &D) -> DeviceInfo {
+        DeviceInfo {
+            name: name.into(),
+            hardware_id: device.hardware_id(),
+            hardware_version: device.hardware_version(),
+            manufacturer: device.manufacturer(),
+            state: vec![],
+        }
+    }
+
+    pub fn with<V: ToString>(mut self, key: &str, value: V) -> DeviceInfo {
+        self.state.push((key.into(), value.to_string()));
+        self
+    }
+}
+
+impl fmt::Display for DeviceInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f,
+                    "{} (id 0x{:0>8x}, version 0x{:0>4x}, manufacturer 0x{:0>8x})",
+                    self.name,
+                    self.hardware_id,
+                    self.hardware_version,
+                    self.manufacturer));
+        for &(ref key, ref value) in &self.state {
+            try!(write!(f, "\n  {}: {}", key, value));
+        }
+        Ok(())
+    }
+}
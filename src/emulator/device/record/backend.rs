@@ -0,0 +1,218 @@
+//! Headless backend recording the screen of a LEM1802 or a SPED-3 to an
+//! animated GIF or to a sequence of PNG files.
+//!
+//! The backend can be cloned to keep a handle on it after giving it to the
+//! LEM1802, for example to take screenshots.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+use emulator::cpu;
+use emulator::device::{Device, Result};
+use emulator::device::lem1802;
+use emulator::device::record::{Frame, GifWriter};
+use emulator::device::sped3;
+
+/// Ticks by hundredth of second, for the GIF delays.
+const TICKS_PER_CENTISECOND: u64 = 1_000;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Output {
+    /// One animated GIF for the whole session. Identical consecutive frames
+    /// are merged.
+    Gif(PathBuf),
+    /// One PNG by frame, `{}` being replaced by the frame number.
+    Png(String),
+    /// Only keep the last frame for the screenshots.
+    Nothing,
+}
+
+impl Output {
+    /// `.gif` files are animated GIF, other paths are PNG patterns.
+    pub fn from_path(path: &str) -> Output {
+        if path.ends_with(".gif") {
+            Output::Gif(path.into())
+        } else {
+            Output::Png(path.into())
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct RecordBackend {
+    state: Arc<Mutex<State>>,
+}
+
+struct State {
+    output: Output,
+    /// Ticks between two frames.
+    frame_ticks: u64,
+    scale: usize,
+    gif: Option<GifWriter<BufWriter<File>>>,
+    last: Option<Frame>,
+    /// Number of ticks the last frame was shown so far.
+    pending_ticks: u64,
+    frame_count: u64,
+}
+
+impl RecordBackend {
+    /// Capture a frame every `frame_ticks`, each LEM1802 pixel being a
+    /// `scale` x `scale` square. Both must not be 0.
+    pub fn new(output: Output, frame_ticks: u64, scale: usize) -> RecordBackend {
+        RecordBackend {
+            state: Arc::new(Mutex::new(State {
+                output: output,
+                frame_ticks: frame_ticks,
+                scale: scale,
+                gif: None,
+                last: None,
+                pending_ticks: 0,
+                frame_count: 0,
+            })),
+        }
+    }
+
+    /// Write the last captured frame to a PNG file.
+    pub fn screenshot<P: AsRef<Path>>(&self, path: P) -> io::Result<()> {
+        let state = self.state.lock().unwrap();
+        match state.last {
+            Some(ref frame) => {
+                let mut output = BufWriter::new(try!(File::create(path)));
+                frame.write_png(&mut output)
+            }
+            None => Err(io::Error::new(io::ErrorKind::NotFound,
+                                       "no frame was captured")),
+        }
+    }
+
+    /// Set the duration of the last GIF frame and close the file, the next
+    /// frames are not recorded anymore. Also done when the last handle is
+    /// dropped, but errors are then only logged. The GIF is valid even
+    /// without it.
+    pub fn finish(&self) -> io::Result<()> {
+        self.state.lock().unwrap().finish()
+    }
+}
+
+/// Recording backend of `device`, if it is a LEM1802 or a SPED-3 plugged
+/// with `recorder=` or `sped3=`.
+pub fn recorder_of(device: &mut Device) -> Option<RecordBackend> {
+    let any = device.as_any();
+    if let Some(lem) = any.downcast_ref::<lem1802::LEM1802<RecordBackend>>() {
+        return Some(lem.backend().clone());
+    }
+    any.downcast_ref::<sped3::Sped3<RecordBackend>>().map(|sped| sped.backend().clone())
+}
+
+impl fmt::Debug for RecordBackend {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Recording backend")
+    }
+}
+
+impl lem1802::Backend for RecordBackend {
+    fn tick<B: lem1802::Backend>(&self,
+                                 cpu: &cpu::Cpu,
+                                 lem: &lem1802::LEM1802<B>,
+                                 tick_count: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if tick_count % state.frame_ticks == 0 {
+            let frame = match lem.get_raw_screen(cpu) {
+                Some(screen) => lem1802::raster::render(&screen, state.scale),
+                None => lem1802::raster::blank(state.scale),
+            };
+            try!(state.record(frame));
+        }
+        Ok(())
+    }
+
+    fn hide(&self) -> Result<()> {
+        Ok(())
+    }
+
+    fn show<B: lem1802::Backend>(&self,
+                                 _: &cpu::Cpu,
+                                 _: &lem1802::LEM1802<B>) -> Result<()> {
+        Ok(())
+    }
+}
+
+/// The SPED-3 frames are `sped3::raster::FRAME_SIZE / 2 * scale` pixels wide.
+impl sped3::Backend for RecordBackend {
+    fn tick<B: sped3::Backend>(&self,
+                               cpu: &cpu::Cpu,
+                               sped: &sped3::Sped3<B>,
+                               tick_count: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        if tick_count % state.frame_ticks == 0 {
+            let size = sped3::raster::FRAME_SIZE / 2 * state.scale;
+            let frame = sped3::raster::render(sped.get_model(cpu).as_ref(), size);
+            try!(state.record(frame));
+        }
+        Ok(())
+    }
+}
+
+impl State {
+    fn record(&mut self, frame: Frame) -> io::Result<()> {
+        if let Output::Png(ref pattern) = self.output {
+            let path = pattern.replace("{}", &format!("{:06}", self.frame_count));
+            let mut output = BufWriter::new(try!(File::create(path)));
+            try!(frame.write_png(&mut output));
+        }
+        self.frame_count += 1;
+        if self.last.as_ref() != Some(&frame) {
+            try!(self.end_frame());
+            try!(self.write_gif(&frame));
+            self.last = Some(frame);
+        }
+        self.pending_ticks += self.frame_ticks;
+        Ok(())
+    }
+
+    /// Add `frame` to the GIF right away, so the file is complete even if
+    /// the emulator is killed. Its delay is set by `end_frame`.
+    fn write_gif(&mut self, frame: &Frame) -> io::Result<()> {
+        let path = match self.output {
+            Output::Gif(ref path) => path.clone(),
+            _ => return Ok(()),
+        };
+        if self.gif.is_none() {
+            let output = BufWriter::new(try!(File::create(path)));
+            self.gif = Some(try!(GifWriter::new(output, frame.width, frame.height)));
+        }
+        self.gif.as_mut().unwrap().frame(frame, 1)
+    }
+
+    /// Set the delay of the last GIF frame to the time it was shown.
+    fn end_frame(&mut self) -> io::Result<()> {
+        let delay = self.pending_ticks / TICKS_PER_CENTISECOND;
+        self.pending_ticks = 0;
+        match self.gif {
+            Some(ref mut gif) => gif.set_delay(delay.min(0xffff).max(1) as u16),
+            None => Ok(()),
+        }
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        try!(self.end_frame());
+        if let Output::Gif(_) = self.output {
+            self.output = Output::Nothing;
+        }
+        match self.gif.take() {
+            Some(gif) => gif.finish().map(|_| ()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl Drop for State {
+    fn drop(&mut self) {
+        if let Err(e) = self.finish() {
+            error!("Error while writing the recording: {}", e);
+        }
+    }
+}
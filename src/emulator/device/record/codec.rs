@@ -0,0 +1,252 @@
+//! PNG and animated GIF encoding of the frames, with the `png` and `gif`
+//! crates. Without the "images" feature, every function fails.
+
+#[cfg(feature = "images")]
+pub use self::enabled::*;
+#[cfg(not(feature = "images"))]
+pub use self::disabled::*;
+
+#[cfg(feature = "images")]
+mod enabled {
+    use std::collections::HashMap;
+    use std::io::{self, Read, Seek, SeekFrom, Write};
+    use std::mem;
+    use std::sync::{Arc, Mutex};
+
+    use gif;
+    use png::{self, HasParameters};
+
+    use emulator::device::record::Frame;
+
+    /// GIF trailer, kept at the end of the file after each frame.
+    const TRAILER: u8 = 0x3b;
+
+    pub fn write_png<W: Write>(frame: &Frame, output: W) -> io::Result<()> {
+        let mut encoder = png::Encoder::new(output,
+                                            frame.width as u32,
+                                            frame.height as u32);
+        encoder.set(png::ColorType::RGB).set(png::BitDepth::Eight);
+        let mut writer = try!(encoder.write_header());
+        let data: Vec<u8> = frame.pixels.iter().flat_map(|p| p.iter().cloned()).collect();
+        try!(writer.write_image_data(&data));
+        Ok(())
+    }
+
+    /// Read a PNG of any color type with a depth of at most 8 bits.
+    pub fn read_png<R: Read>(input: R) -> io::Result<Frame> {
+        let mut decoder = png::Decoder::new(input);
+        // Palettes and small depths to 8-bit RGB or grayscale
+        decoder.set(png::TRANSFORM_EXPAND);
+        let (info, mut reader) = try!(decoder.read_info());
+        if info.bit_depth != png::BitDepth::Eight {
+            return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                      "16-bit PNG are not supported"));
+        }
+        let mut data = vec![0; info.buffer_size()];
+        try!(reader.next_frame(&mut data));
+        let bpp = data.len() / (info.width * info.height) as usize;
+        let pixels = data.chunks(bpp)
+                         .map(|p| match info.color_type {
+                             png::ColorType::Grayscale |
+                             png::ColorType::GrayscaleAlpha => [p[0]; 3],
+                             _ => [p[0], p[1], p[2]],
+                         })
+                         .collect();
+        Ok(Frame {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels: pixels,
+        })
+    }
+
+    /// Bytes written by the encoder, moved to the output after each frame.
+    #[derive(Clone, Default)]
+    struct Buffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Buffer {
+        fn take(&self) -> Vec<u8> {
+            mem::replace(&mut *self.0.lock().unwrap(), vec![])
+        }
+    }
+
+    impl Write for Buffer {
+        fn write(&mut self, bytes: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(bytes)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    /// Animated GIF, looping forever, with one color table by frame.
+    ///
+    /// The output is a complete GIF after each frame, so a recording
+    /// survives an emulator killed or exiting without dropping it.
+    pub struct GifWriter<W: Write + Seek> {
+        output: W,
+        encoder: gif::Encoder<Buffer>,
+        buffer: Buffer,
+        width: usize,
+        height: usize,
+        /// Position of the delay of the last frame.
+        last_delay: Option<u64>,
+    }
+
+    impl<W: Write + Seek> GifWriter<W> {
+        pub fn new(output: W, width: usize, height: usize) -> io::Result<GifWriter<W>> {
+            let buffer = Buffer::default();
+            let mut encoder = try!(gif::Encoder::new(buffer.clone(),
+                                                     width as u16,
+                                                     height as u16,
+                                                     &[]));
+            let repeat = gif::ExtensionData::Repetitions(gif::Repeat::Infinite);
+            try!(encoder.write_extension(repeat));
+            let mut writer = GifWriter {
+                output: output,
+                encoder: encoder,
+                buffer: buffer,
+                width: width,
+                height: height,
+                last_delay: None,
+            };
+            try!(writer.commit());
+            Ok(writer)
+        }
+
+        /// Add a frame shown for `delay` hundredths of a second, which can be
+        /// changed later with `set_delay`. It must have at most 256 colors.
+        pub fn frame(&mut self, frame: &Frame, delay: u16) -> io::Result<()> {
+            assert_eq!((frame.width, frame.height), (self.width, self.height));
+            let mut palette = vec![];
+            let mut indexes = HashMap::new();
+            let mut data = Vec::with_capacity(frame.pixels.len());
+            for pixel in &frame.pixels {
+                let index = *indexes.entry(*pixel).or_insert_with(|| {
+                    palette.extend_from_slice(pixel);
+                    palette.len() / 3 - 1
+                });
+                if index > 0xff {
+                    return Err(io::Error::new(io::ErrorKind::InvalidInput,
+                                              "more than 256 colors in a GIF frame"));
+                }
+                data.push(index as u8);
+            }
+            let mut gif_frame = gif::Frame::default();
+            gif_frame.width = self.width as u16;
+            gif_frame.height = self.height as u16;
+            gif_frame.delay = delay;
+            gif_frame.palette = Some(palette);
+            gif_frame.buffer = data.into();
+
+            let start = try!(self.output.seek(SeekFrom::Current(0)));
+            try!(self.encoder.write_frame(&gif_frame));
+            try!(self.commit());
+            // After the extension introducer, label, size and flags
+            self.last_delay = Some(start + 4);
+            Ok(())
+        }
+
+        /// Change the delay of the last frame.
+        pub fn set_delay(&mut self, delay: u16) -> io::Result<()> {
+            if let Some(position) = self.last_delay {
+                let end = try!(self.output.seek(SeekFrom::Current(0)));
+                try!(self.output.seek(SeekFrom::Start(position)));
+                try!(self.output.write_all(&[delay as u8, (delay >> 8) as u8]));
+                try!(self.output.seek(SeekFrom::Start(end)));
+                try!(self.output.flush());
+            }
+            Ok(())
+        }
+
+        /// Flush the output and return it. The GIF is already complete.
+        pub fn finish(mut self) -> io::Result<W> {
+            try!(self.output.flush());
+            // The trailer written by the encoder when dropped is discarded
+            let GifWriter { output, .. } = self;
+            Ok(output)
+        }
+
+        /// Write what the encoder produced followed by the trailer, which
+        /// the next write overwrites.
+        fn commit(&mut self) -> io::Result<()> {
+            let mut bytes = self.buffer.take();
+            bytes.push(TRAILER);
+            try!(self.output.write_all(&bytes));
+            try!(self.output.flush());
+            try!(self.output.seek(SeekFrom::Current(-1)));
+            Ok(())
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use std::io::Cursor;
+
+        use gif;
+
+        use super::*;
+
+        #[test]
+        fn test_gif() {
+            let mut frame = Frame::filled(144, 112, [0; 3]);
+            let mut gif = GifWriter::new(Cursor::new(vec![]), frame.width, frame.height)
+                              .unwrap();
+            gif.frame(&frame, 1).unwrap();
+            frame.pixels[3] = [1, 2, 3];
+            gif.frame(&frame, 1).unwrap();
+            gif.set_delay(42).unwrap();
+            assert_eq!(gif.output.get_ref().last(), Some(&TRAILER));
+            let bytes = gif.finish().unwrap().into_inner();
+
+            let mut reader = gif::Decoder::new(&bytes[..]).read_info().unwrap();
+            assert_eq!(reader.read_next_frame().unwrap().unwrap().delay, 1);
+            let second = reader.read_next_frame().unwrap().unwrap();
+            assert_eq!(second.delay, 42);
+            assert_eq!(second.buffer[3], 1);
+            assert!(reader.read_next_frame().unwrap().is_none());
+        }
+    }
+}
+
+#[cfg(not(feature = "images"))]
+mod disabled {
+    use std::io::{self, Read, Seek, Write};
+    use std::marker::PhantomData;
+
+    use emulator::device::record::Frame;
+
+    fn disabled() -> io::Error {
+        io::Error::new(io::ErrorKind::Other,
+                       "PNG and GIF are disabled, activate the \"images\" feature")
+    }
+
+    pub fn write_png<W: Write>(_: &Frame, _: W) -> io::Result<()> {
+        Err(disabled())
+    }
+
+    pub fn read_png<R: Read>(_: R) -> io::Result<Frame> {
+        Err(disabled())
+    }
+
+    /// Cannot be created without the "images" feature.
+    pub struct GifWriter<W: Write + Seek>(PhantomData<W>);
+
+    impl<W: Write + Seek> GifWriter<W> {
+        pub fn new(_: W, _: usize, _: usize) -> io::Result<GifWriter<W>> {
+            Err(disabled())
+        }
+
+        pub fn frame(&mut self, _: &Frame, _: u16) -> io::Result<()> {
+            Err(disabled())
+        }
+
+        pub fn set_delay(&mut self, _: u16) -> io::Result<()> {
+            Err(disabled())
+        }
+
+        pub fn finish(self) -> io::Result<W> {
+            Err(disabled())
+        }
+    }
+}
@@ -0,0 +1,85 @@
+//! RGB frames of the screens, written as PNG or animated GIF by `codec`, and
+//! the headless backend recording them for the LEM1802 and the SPED-3.
+//!
+//! The devices render their frames in their own `raster` module.
+
+mod backend;
+mod codec;
+
+use std::io::{self, Read, Write};
+
+pub use emulator::device::record::backend::{Output, RecordBackend, recorder_of};
+pub use emulator::device::record::codec::GifWriter;
+
+/// RGB image of a screen.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Frame {
+    pub width: usize,
+    pub height: usize,
+    /// Row-major pixels.
+    pub pixels: Vec<[u8; 3]>,
+}
+
+impl Frame {
+    /// `width` x `height` frame of a single color.
+    pub fn filled(width: usize, height: usize, color: [u8; 3]) -> Frame {
+        Frame {
+            width: width,
+            height: height,
+            pixels: vec![color; width * height],
+        }
+    }
+
+    /// Write the frame as an 8-bit RGB PNG.
+    pub fn write_png<W: Write>(&self, output: W) -> io::Result<()> {
+        codec::write_png(self, output)
+    }
+
+    /// Read a PNG, the alpha channel being ignored.
+    pub fn read_png<R: Read>(input: R) -> io::Result<Frame> {
+        codec::read_png(input)
+    }
+
+    /// Number of pixels differing from `other` and an image showing them in
+    /// red over a faded version of `self`. `None` if the sizes differ.
+    pub fn diff(&self, other: &Frame) -> Option<(usize, Frame)> {
+        if (self.width, self.height) != (other.width, other.height) {
+            return None;
+        }
+        let mut count = 0;
+        let pixels = self.pixels
+                         .iter()
+                         .zip(&other.pixels)
+                         .map(|(a, b)| if a == b {
+                             let gray = (a[0] as u16 + a[1] as u16 + a[2] as u16) / 3;
+                             [(gray / 4 + 96) as u8; 3]
+                         } else {
+                             count += 1;
+                             [255, 0, 0]
+                         })
+                         .collect();
+        Some((count,
+              Frame {
+                  width: self.width,
+                  height: self.height,
+                  pixels: pixels,
+              }))
+    }
+}
+
+#[cfg(all(test, feature = "images"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_png() {
+        let mut frame = Frame::filled(144, 112, [0; 3]);
+        frame.pixels[3] = [1, 2, 3];
+        let mut output = vec![];
+        frame.write_png(&mut output).unwrap();
+        assert_eq!(Frame::read_png(&output[..]).unwrap(), frame);
+        assert_eq!(&output[..8], b"\x89PNG\r\n\x1a\n");
+        // IHDR: 144x112
+        assert_eq!(&output[16..24], &[0, 0, 0, 144, 0, 0, 0, 112]);
+    }
+}
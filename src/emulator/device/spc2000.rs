@@ -0,0 +1,191 @@
+//! SPC2000 suspended particle cryo-chamber. The whole ship sleeps for the
+//! programmed duration, which the emulator skips at once: the next tick
+//! returns `TickResult::SkipTime`.
+//!
+//! The duration is a 64 bit number of units read most significant word
+//! first. The units are milliseconds, minutes, days or 365 days years.
+
+use std::any::Any;
+
+use enum_primitive::FromPrimitive;
+
+use emulator::cpu::Cpu;
+use emulator::device::*;
+use types::Register;
+
+enum_from_primitive! {
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+enum Command {
+    GET_STATUS = 0,
+    SET_UNIT_TO_SKIP = 1,
+    TRIGGER_DEVICE = 2,
+    SET_SKIP_UNIT = 3,
+}
+}
+
+enum_from_primitive! {
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum Unit {
+    Milliseconds = 0,
+    Minutes = 1,
+    Days = 2,
+    Years = 3,
+}
+}
+
+/// The chamber is always in working conditions.
+const ERROR_NONE: u16 = 0;
+
+#[derive(Debug)]
+pub struct Spc2000 {
+    ticks_per_second: u64,
+    units_to_skip: u64,
+    unit: Unit,
+    /// Ticks to skip on the next tick.
+    triggered: Option<u64>,
+}
+
+impl Spc2000 {
+    pub fn new(ticks_per_second: u64) -> Spc2000 {
+        Spc2000 {
+            ticks_per_second: ticks_per_second,
+            units_to_skip: 0,
+            unit: Unit::Milliseconds,
+            triggered: None,
+        }
+    }
+
+    fn ticks_to_skip(&self) -> u64 {
+        let tps = self.ticks_per_second;
+        let units = self.units_to_skip;
+        match self.unit {
+            // Not `tps / 1000`, which is 0 below 1 kHz
+            Unit::Milliseconds => {
+                (units / 1000).saturating_mul(tps)
+                              .saturating_add(units % 1000 * tps / 1000)
+            }
+            Unit::Minutes => units.saturating_mul(tps.saturating_mul(60)),
+            Unit::Days => units.saturating_mul(tps.saturating_mul(86_400)),
+            Unit::Years => units.saturating_mul(tps.saturating_mul(86_400 * 365)),
+        }
+    }
+}
+
+impl Device for Spc2000 {
+    fn hardware_id(&self) -> u32 {
+        0x40e41d9d
+    }
+
+    fn hardware_version(&self) -> u16 {
+        0x005e
+    }
+
+    fn manufacturer(&self) -> u32 {
+        0x1c6c8b36
+    }
+
+    fn interrupt(&mut self, cpu: &mut Cpu) -> Result<InterruptDelay> {
+        let a = cpu.registers[Register::A];
+        let b = cpu.registers[Register::B];
+        match try!(Command::from_u16(a)
+                           .ok_or(ErrorKind::InvalidCommand(a))) {
+            Command::GET_STATUS => {
+                cpu.registers[Register::C] = 1;
+                cpu.registers[Register::B] = ERROR_NONE;
+            }
+            Command::SET_UNIT_TO_SKIP => {
+                self.units_to_skip = cpu.ram
+                                        .iter_wrap(b)
+                                        .take(4)
+                                        .fold(0, |n, w| n << 16 | *w as u64);
+            }
+            Command::TRIGGER_DEVICE => {
+                self.triggered = Some(self.ticks_to_skip());
+                cpu.registers[Register::B] = ERROR_NONE;
+            }
+            Command::SET_SKIP_UNIT => {
+                self.unit = try!(Unit::from_u16(b)
+                                      .ok_or(ErrorKind::InvalidArgument(b)));
+            }
+        }
+        Ok(0)
+    }
+
+    fn tick(&mut self, _: &mut Cpu, _: u64) -> Result<TickResult> {
+        Ok(match self.triggered.take() {
+            Some(ticks) => TickResult::SkipTime(ticks),
+            None => TickResult::Nothing,
+        })
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        *self = Spc2000::new(self.ticks_per_second);
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        DeviceInfo::new("SPC2000", self)
+            .with("sleep duration",
+                  format!("{} {:?}", self.units_to_skip, self.unit))
+    }
+
+    fn as_any(&mut self) -> &mut Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_trigger() {
+        let mut spc = Spc2000::new(100_000);
+        let mut cpu = Cpu::default();
+        cpu.ram[0x10..0x14].copy_from_slice(&[0, 0, 1, 2]);
+        cpu.registers[Register::A] = Command::SET_UNIT_TO_SKIP as u16;
+        cpu.registers[Register::B] = 0x10;
+        spc.interrupt(&mut cpu).unwrap();
+        cpu.registers[Register::A] = Command::SET_SKIP_UNIT as u16;
+        cpu.registers[Register::B] = Unit::Minutes as u16;
+        spc.interrupt(&mut cpu).unwrap();
+
+        assert_eq!(spc.tick(&mut cpu, 0).unwrap(), TickResult::Nothing);
+        cpu.registers[Register::A] = Command::TRIGGER_DEVICE as u16;
+        spc.interrupt(&mut cpu).unwrap();
+        assert_eq!(spc.tick(&mut cpu, 1).unwrap(),
+                   TickResult::SkipTime(0x10002 * 60 * 100_000));
+        assert_eq!(spc.tick(&mut cpu, 2).unwrap(), TickResult::Nothing);
+
+        cpu.registers[Register::A] = Command::SET_SKIP_UNIT as u16;
+        cpu.registers[Register::B] = Unit::Milliseconds as u16;
+        spc.interrupt(&mut cpu).unwrap();
+        cpu.registers[Register::A] = Command::TRIGGER_DEVICE as u16;
+        spc.interrupt(&mut cpu).unwrap();
+        assert_eq!(spc.tick(&mut cpu, 3).unwrap(),
+                   TickResult::SkipTime(0x10002 * 100));
+    }
+
+    #[test]
+    fn test_invalid_unit() {
+        let mut spc = Spc2000::new(100_000);
+        let mut cpu = Cpu::default();
+        cpu.registers[Register::A] = Command::SET_SKIP_UNIT as u16;
+        cpu.registers[Register::B] = 4;
+        match spc.interrupt(&mut cpu) {
+            Err(Error(ErrorKind::InvalidArgument(4), _)) => (),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_slow_clock() {
+        let mut spc = Spc2000::new(500);
+        spc.units_to_skip = 3;
+        assert_eq!(spc.ticks_to_skip(), 1);
+        spc.units_to_skip = ::std::u64::MAX;
+        spc.unit = Unit::Years;
+        assert_eq!(spc.ticks_to_skip(), ::std::u64::MAX);
+    }
+}
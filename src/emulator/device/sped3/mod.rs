@@ -0,0 +1,286 @@
+//! SPED-3 suspended particle exciter display: a wireframe of up to 128
+//! vertices projected in 3D, turning around its vertical axis.
+//!
+//! Each vertex takes two words, `YYYYYYYY XXXXXXXX` then `00000ICC ZZZZZZZZ`
+//! with `Z` the height, `C` the color (black, red, green or blue) and `I` the
+//! intensity. A line is drawn from each vertex to the next one in the color
+//! of the second vertex.
+
+pub mod raster;
+
+use std::any::Any;
+use std::fmt::Debug;
+
+use enum_primitive::FromPrimitive;
+
+use emulator::cpu::Cpu;
+use emulator::device::*;
+use types::Register;
+
+pub const MAX_VERTICES: u16 = 128;
+/// Degrees by second.
+const ROTATION_SPEED: u64 = 50;
+/// Unit of the rotation, so that the device turns of one unit by tick.
+const UNITS_BY_DEGREE: u64 = 100_000 / ROTATION_SPEED;
+const FULL_TURN: u64 = 360 * UNITS_BY_DEGREE;
+
+enum_from_primitive! {
+#[allow(non_camel_case_types)]
+#[derive(Debug)]
+enum Command {
+    POLL_DEVICE = 0,
+    MEM_MAP_REGION = 1,
+    ROTATE_DEVICE = 2,
+}
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum StateCode {
+    NoData = 0,
+    Running = 1,
+    Turning = 2,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum VertexColor {
+    Black = 0,
+    Red = 1,
+    Green = 2,
+    Blue = 3,
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct Vertex {
+    pub x: u8,
+    pub y: u8,
+    pub z: u8,
+    pub color: VertexColor,
+    pub bright: bool,
+}
+
+impl Vertex {
+    pub fn from_words(first: u16, second: u16) -> Vertex {
+        Vertex {
+            x: first as u8,
+            y: (first >> 8) as u8,
+            z: second as u8,
+            color: match (second >> 8) & 0x3 {
+                0 => VertexColor::Black,
+                1 => VertexColor::Red,
+                2 => VertexColor::Green,
+                _ => VertexColor::Blue,
+            },
+            bright: second & 0x400 != 0,
+        }
+    }
+}
+
+/// What the device currently projects.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Model {
+    pub vertices: Vec<Vertex>,
+    /// Degrees, in `[0, 360)`.
+    pub rotation: f64,
+}
+
+pub trait Backend: Debug + Send + Any {
+    fn tick<B: Backend>(&self, &Cpu, &Sped3<B>, tick_count: u64) -> Result<()>;
+}
+
+#[derive(Debug)]
+pub struct Sped3<B: Backend> {
+    region: u16,
+    /// 0 when the device is in stand-by.
+    nb_vertices: u16,
+    /// In `1 / UNITS_BY_DEGREE` degrees.
+    rotation: u64,
+    target_rotation: u64,
+    backend: B,
+}
+
+impl<B: Backend> Sped3<B> {
+    pub fn new(backend: B) -> Sped3<B> {
+        Sped3 {
+            region: 0,
+            nb_vertices: 0,
+            rotation: 0,
+            target_rotation: 0,
+            backend: backend,
+        }
+    }
+
+    fn state(&self) -> StateCode {
+        if self.nb_vertices == 0 {
+            StateCode::NoData
+        } else if self.rotation != self.target_rotation {
+            StateCode::Turning
+        } else {
+            StateCode::Running
+        }
+    }
+
+    /// Backend receiving the models to display.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Vertices read from the ram and current rotation, or `None` in
+    /// stand-by.
+    pub fn get_model(&self, cpu: &Cpu) -> Option<Model> {
+        if self.nb_vertices == 0 {
+            return None;
+        }
+        let words: Vec<u16> = cpu.ram
+                                 .iter_wrap(self.region)
+                                 .take(2 * self.nb_vertices as usize)
+                                 .cloned()
+                                 .collect();
+        Some(Model {
+            vertices: words.chunks(2)
+                           .map(|w| Vertex::from_words(w[0], w[1]))
+                           .collect(),
+            rotation: self.rotation as f64 / UNITS_BY_DEGREE as f64,
+        })
+    }
+}
+
+impl<B: Backend> Device for Sped3<B> {
+    fn hardware_id(&self) -> u32 {
+        0x42babf3c
+    }
+
+    fn hardware_version(&self) -> u16 {
+        0x0003
+    }
+
+    fn manufacturer(&self) -> u32 {
+        0x1eb37e91
+    }
+
+    fn interrupt(&mut self, cpu: &mut Cpu) -> Result<InterruptDelay> {
+        let a = cpu.registers[Register::A];
+        let x = cpu.registers[Register::X];
+        let y = cpu.registers[Register::Y];
+        match try!(Command::from_u16(a)
+                           .ok_or(ErrorKind::InvalidCommand(a))) {
+            Command::POLL_DEVICE => {
+                cpu.registers[Register::B] = self.state() as u16;
+                // The device never breaks
+                cpu.registers[Register::C] = 0;
+            }
+            Command::MEM_MAP_REGION => {
+                self.region = x;
+                self.nb_vertices = y.min(MAX_VERTICES);
+            }
+            Command::ROTATE_DEVICE => {
+                self.target_rotation = (x % 360) as u64 * UNITS_BY_DEGREE;
+            }
+        }
+        Ok(0)
+    }
+
+    fn tick(&mut self, cpu: &mut Cpu, tick_count: u64) -> Result<TickResult> {
+        if self.rotation != self.target_rotation {
+            // Shortest way to the target
+            let forward = (self.target_rotation + FULL_TURN - self.rotation) %
+                          FULL_TURN;
+            self.rotation = if forward <= FULL_TURN / 2 {
+                (self.rotation + 1) % FULL_TURN
+            } else {
+                (self.rotation + FULL_TURN - 1) % FULL_TURN
+            };
+        }
+        try!(self.backend.tick(cpu, self, tick_count));
+        Ok(TickResult::Nothing)
+    }
+
+    fn reset(&mut self) -> Result<()> {
+        self.region = 0;
+        self.nb_vertices = 0;
+        self.target_rotation = self.rotation;
+        Ok(())
+    }
+
+    fn inspect(&self) -> DeviceInfo {
+        let info = DeviceInfo::new("SPED-3", self)
+            .with("state", format!("{:?}", self.state()));
+        if self.nb_vertices == 0 {
+            return info;
+        }
+        let rotation = self.rotation as f64 / UNITS_BY_DEGREE as f64;
+        info.with("vertices",
+                  format!("{} at 0x{:0>4x}", self.nb_vertices, self.region))
+            .with("rotation", format!("{:.1} degrees", rotation))
+            .with("target rotation",
+                  format!("{} degrees", self.target_rotation / UNITS_BY_DEGREE))
+    }
+
+    fn as_any(&mut self) -> &mut Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use emulator::test_utils::command;
+
+    use super::*;
+
+    #[derive(Debug)]
+    struct NoBackend;
+
+    impl Backend for NoBackend {
+        fn tick<B: Backend>(&self, _: &Cpu, _: &Sped3<B>, _: u64) -> Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn test_commands() {
+        let mut sped = Sped3::new(NoBackend);
+        let mut cpu = Cpu::default();
+        assert!(sped.get_model(&cpu).is_none());
+
+        cpu.ram[0x1000] = 0x2010;
+        cpu.ram[0x1001] = 0x0630;
+        cpu.ram[0x1002] = 0xffff;
+        cpu.ram[0x1003] = 0x0100;
+        command(&mut sped, &mut cpu, Command::MEM_MAP_REGION as u16,
+                &[(Register::X, 0x1000), (Register::Y, 2)]);
+        command(&mut sped, &mut cpu, Command::POLL_DEVICE as u16, &[]);
+        assert_eq!(cpu.registers[Register::B], StateCode::Running as u16);
+        let model = sped.get_model(&cpu).unwrap();
+        assert_eq!(model.vertices,
+                   vec![Vertex {
+                            x: 0x10,
+                            y: 0x20,
+                            z: 0x30,
+                            color: VertexColor::Green,
+                            bright: true,
+                        },
+                        Vertex {
+                            x: 0xff,
+                            y: 0xff,
+                            z: 0,
+                            color: VertexColor::Red,
+                            bright: false,
+                        }]);
+
+        // Turns backwards, at 50 degrees by second
+        command(&mut sped, &mut cpu, Command::ROTATE_DEVICE as u16,
+                &[(Register::X, 350)]);
+        command(&mut sped, &mut cpu, Command::POLL_DEVICE as u16, &[]);
+        assert_eq!(cpu.registers[Register::B], StateCode::Turning as u16);
+        for tick in 0..20_000 {
+            sped.tick(&mut cpu, tick).unwrap();
+        }
+        assert_eq!(sped.get_model(&cpu).unwrap().rotation, 350.);
+        command(&mut sped, &mut cpu, Command::POLL_DEVICE as u16, &[]);
+        assert_eq!(cpu.registers[Register::B], StateCode::Running as u16);
+
+        command(&mut sped, &mut cpu, Command::MEM_MAP_REGION as u16,
+                &[(Register::X, 0), (Register::Y, 0)]);
+        command(&mut sped, &mut cpu, Command::POLL_DEVICE as u16, &[]);
+        assert_eq!(cpu.registers[Register::B], StateCode::NoData as u16);
+    }
+}
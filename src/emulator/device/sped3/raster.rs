@@ -0,0 +1,115 @@
+//! Software rasterizer drawing the SPED-3 wireframe to RGB frames.
+//!
+//! The model is seen from the front, 30 degrees above its horizontal plane,
+//! without perspective. The particles don't hide each other.
+
+use emulator::device::record::Frame;
+use emulator::device::sped3::{Model, Vertex, VertexColor};
+
+/// Angle between the view and the horizontal plane, in degrees.
+const VIEW_ELEVATION: f64 = 30.;
+/// Width of the view, in vertex coordinates. The projection of the
+/// 256 x 256 x 256 cube turning around its vertical axis fits in it.
+const VIEW_SIZE: f64 = 416.;
+/// Side of the frames when a vertex unit is a pixel.
+pub const FRAME_SIZE: usize = 416;
+
+/// Render `model` in a `size` x `size` frame, black in stand-by.
+pub fn render(model: Option<&Model>, size: usize) -> Frame {
+    let mut frame = Frame::filled(size, size, [0; 3]);
+    if let Some(model) = model {
+        let points: Vec<(i64, i64)> = model.vertices
+                                           .iter()
+                                           .map(|v| project(v, model.rotation, size))
+                                           .collect();
+        for (i, vertex) in model.vertices.iter().enumerate().skip(1) {
+            line(&mut frame, points[i - 1], points[i], color(vertex));
+        }
+        // A single vertex is a dot
+        if points.len() == 1 {
+            line(&mut frame, points[0], points[0], color(&model.vertices[0]));
+        }
+    }
+    frame
+}
+
+/// Position of `vertex` in the frame.
+fn project(vertex: &Vertex, rotation: f64, size: usize) -> (i64, i64) {
+    let (x, y, z) = (vertex.x as f64 - 127.5,
+                     vertex.y as f64 - 127.5,
+                     vertex.z as f64 - 127.5);
+    let (sin, cos) = rotation.to_radians().sin_cos();
+    let (x, depth) = (x * cos - y * sin, x * sin + y * cos);
+    let (sin, cos) = VIEW_ELEVATION.to_radians().sin_cos();
+    let y = depth * sin - z * cos;
+    let scale = size as f64 / VIEW_SIZE;
+    let half = size as f64 / 2.;
+    ((half + x * scale).floor() as i64, (half + y * scale).floor() as i64)
+}
+
+fn color(vertex: &Vertex) -> [u8; 3] {
+    match (vertex.color, vertex.bright) {
+        (VertexColor::Black, false) => [0x20, 0x20, 0x20],
+        (VertexColor::Black, true) => [0x40, 0x40, 0x40],
+        (VertexColor::Red, false) => [0xa0, 0, 0],
+        (VertexColor::Red, true) => [0xff, 0x50, 0x50],
+        (VertexColor::Green, false) => [0, 0xa0, 0],
+        (VertexColor::Green, true) => [0x50, 0xff, 0x50],
+        (VertexColor::Blue, false) => [0x20, 0x20, 0xc0],
+        (VertexColor::Blue, true) => [0x60, 0x60, 0xff],
+    }
+}
+
+/// Bresenham line, clipped to the frame.
+fn line(frame: &mut Frame, from: (i64, i64), to: (i64, i64), color: [u8; 3]) {
+    let (mut x, mut y) = from;
+    let (dx, dy) = ((to.0 - x).abs(), -(to.1 - y).abs());
+    let (step_x, step_y) = (if x < to.0 { 1 } else { -1 }, if y < to.1 { 1 } else { -1 });
+    let mut error = dx + dy;
+    loop {
+        if x >= 0 && y >= 0 && (x as usize) < frame.width && (y as usize) < frame.height {
+            let width = frame.width;
+            frame.pixels[y as usize * width + x as usize] = color;
+        }
+        if (x, y) == to {
+            return;
+        }
+        let double = 2 * error;
+        if double >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if double <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_render() {
+        assert!(render(None, 8).pixels.iter().all(|p| *p == [0; 3]));
+
+        // Vertical red segment in the middle of the view
+        let bottom = Vertex::from_words(0x8080, 0x0500);
+        let top = Vertex::from_words(0x8080, 0x05ff);
+        let model = Model {
+            vertices: vec![bottom, top],
+            rotation: 0.,
+        };
+        let frame = render(Some(&model), FRAME_SIZE);
+        let lit: Vec<(usize, usize)> = frame.pixels
+                                            .iter()
+                                            .enumerate()
+                                            .filter(|&(_, p)| *p == [0xff, 0x50, 0x50])
+                                            .map(|(i, _)| (i % FRAME_SIZE, i / FRAME_SIZE))
+                                            .collect();
+        assert!(lit.iter().all(|&(x, _)| x == 208));
+        // 255 * cos(30) pixels high, both ends included
+        assert_eq!(lit.len(), 222);
+    }
+}
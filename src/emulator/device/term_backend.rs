@@ -0,0 +1,155 @@
+//! Terminal backend for the LEM1802 and the keyboard, for machines without
+//! OpenGL. The screen is drawn with ANSI escape codes and the keys are read
+//! from stdin in raw mode. `Ctrl-C` closes the terminal screen.
+//!
+//! Terminals don't report key releases, so a key is released as soon as it
+//! is typed.
+
+use std::io::{self, Write};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread;
+use std::time::Duration;
+
+use termion::{self, clear, cursor};
+use termion::event::Key;
+use termion::input::TermRead;
+use termion::raw::IntoRawMode;
+use termion::screen::{ToAlternateScreen, ToMainScreen};
+
+use emulator::device::{self, keyboard};
+use emulator::device::keyboard::mpsc_backend::*;
+use emulator::device::lem1802::ansi;
+use emulator::device::lem1802::generic_backend::*;
+
+/// Time between two polls of the screen and the keyboard.
+const POLL_INTERVAL_MS: u64 = 10;
+
+enum ThreadCommand {
+    Stop,
+}
+
+struct CommonBackend {
+    thread_handle: Option<thread::JoinHandle<Result<()>>>,
+    thread_command: mpsc::Sender<ThreadCommand>,
+}
+
+impl Drop for CommonBackend {
+    fn drop(&mut self) {
+        if let Some(handle) = self.thread_handle.take() {
+            let _ = self.thread_command.send(ThreadCommand::Stop);
+            match handle.join() {
+                Ok(res) => if let Err(e) = res {
+                    error!("Terminal backend error: {}", e);
+                },
+                Err(_) => error!("The terminal backend thread panicked"),
+            }
+        }
+    }
+}
+
+pub fn start(options: ansi::Options) -> (ScreenBackend, KeyboardBackend) {
+    let (tx1, rx1) = mpsc::channel();
+    let (tx2, rx2) = mpsc::channel();
+    let (tx3, rx3) = mpsc::channel();
+    let handle = thread::Builder::new()
+        .name("termscreen".into())
+        .spawn(move || thread_main(options, rx1, tx2, rx3))
+        .unwrap();
+    let common = Arc::new(Mutex::new(CommonBackend {
+        thread_handle: Some(handle),
+        thread_command: tx1,
+    }));
+    let callback = move |s| {
+        tx3.send(s).map_err(|_| {
+            device::ErrorKind::BackendStopped("termscreen".into()).into()
+        })
+    };
+    (ScreenBackend::new(common.clone(), callback),
+     KeyboardBackend::new(common, rx2))
+}
+
+error_chain! {
+    foreign_links {
+        Io(io::Error);
+        MpscSendError(mpsc::SendError<KeyboardEvent>);
+    }
+}
+
+fn thread_main(options: ansi::Options,
+               thread_command: mpsc::Receiver<ThreadCommand>,
+               keyboard_sender: mpsc::Sender<KeyboardEvent>,
+               screen_receiver: mpsc::Receiver<ScreenCommand>)
+    -> Result<()> {
+    // Restores the terminal mode when dropped
+    let mut out = try!(io::stdout().into_raw_mode());
+    try!(write!(out, "{}{}{}", ToAlternateScreen, cursor::Hide, clear::All));
+    try!(out.flush());
+    let res = main_loop(&mut out,
+                        options,
+                        thread_command,
+                        keyboard_sender,
+                        screen_receiver);
+    try!(write!(out, "{}{}", ToMainScreen, cursor::Show));
+    try!(out.flush());
+    res
+}
+
+fn main_loop<W: Write>(out: &mut W,
+                       options: ansi::Options,
+                       thread_command: mpsc::Receiver<ThreadCommand>,
+                       keyboard_sender: mpsc::Sender<KeyboardEvent>,
+                       screen_receiver: mpsc::Receiver<ScreenCommand>)
+    -> Result<()> {
+    let mut keys = termion::async_stdin().keys();
+    loop {
+        match thread_command.try_recv() {
+            Ok(ThreadCommand::Stop) |
+            Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+            Err(mpsc::TryRecvError::Empty) => (),
+        }
+
+        // Only the last frame is drawn
+        let mut frame = None;
+        loop {
+            match screen_receiver.try_recv() {
+                Ok(ScreenCommand::Show(screen)) => frame = Some(Some(screen)),
+                Ok(ScreenCommand::Hide) => frame = Some(None),
+                Err(mpsc::TryRecvError::Empty) => break,
+                Err(mpsc::TryRecvError::Disconnected) => return Ok(()),
+            }
+        }
+        match frame {
+            Some(Some(screen)) => try!(ansi::render(out, &screen, options)),
+            Some(None) => try!(write!(out, "{}", clear::All)),
+            None => (),
+        }
+        try!(out.flush());
+
+        while let Some(key) = keys.next() {
+            match try!(key) {
+                Key::Ctrl('c') => return Ok(()),
+                key => if let Some(converted) = convert_key(key) {
+                    try!(keyboard_sender.send(KeyboardEvent::KeyPressed(converted)));
+                    try!(keyboard_sender.send(KeyboardEvent::KeyReleased(converted)));
+                },
+            }
+        }
+
+        thread::sleep(Duration::from_millis(POLL_INTERVAL_MS));
+    }
+}
+
+fn convert_key(key: Key) -> Option<keyboard::Key> {
+    match key {
+        Key::Char('\n') => Some(keyboard::Key::Return),
+        Key::Char(c) => keyboard::Key::from_char(c).ok(),
+        Key::Backspace => Some(keyboard::Key::Backspace),
+        Key::Delete => Some(keyboard::Key::Delete),
+        Key::Insert => Some(keyboard::Key::Insert),
+        Key::Up => Some(keyboard::Key::Up),
+        Key::Down => Some(keyboard::Key::Down),
+        Key::Left => Some(keyboard::Key::Left),
+        Key::Right => Some(keyboard::Key::Right),
+        _ => None,
+    }
+}
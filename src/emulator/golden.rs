@@ -0,0 +1,175 @@
+//! Comparison of the LEM1802 screen with reference files, for automated UI
+//! tests.
+//!
+//! A reference is either a text file with the characters of the screen, as
+//! given by `RawScreen::text`, or a PNG image of the screen and its border
+//! with one image pixel by screen pixel.
+
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader, BufWriter, Read, Write};
+use std::path::Path;
+
+use emulator::device::lem1802::RawScreen;
+use emulator::device::lem1802::raster;
+use emulator::device::record::Frame;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Reference {
+    Text(String),
+    Image(Frame),
+}
+
+impl Reference {
+    /// Read a reference, `.png` files being images and the others text.
+    pub fn load<P: AsRef<Path>>(path: P) -> io::Result<Reference> {
+        let mut input = BufReader::new(try!(File::open(&path)));
+        if is_png(path.as_ref()) {
+            Frame::read_png(&mut input).map(Reference::Image)
+        } else {
+            let mut text = String::new();
+            try!(input.read_to_string(&mut text));
+            Ok(Reference::Text(text))
+        }
+    }
+
+    /// Write `screen` as a reference, its kind being chosen like `load`.
+    pub fn save<P: AsRef<Path>>(screen: &RawScreen, path: P) -> io::Result<()> {
+        let mut output = BufWriter::new(try!(File::create(&path)));
+        if is_png(path.as_ref()) {
+            raster::render(screen, 1).write_png(&mut output)
+        } else {
+            output.write_all(screen.text().as_bytes())
+        }
+    }
+
+    pub fn check(&self, screen: &RawScreen) -> Result<(), Mismatch> {
+        match *self {
+            Reference::Text(ref expected) => check_text(expected, &screen.text()),
+            Reference::Image(ref expected) => {
+                let actual = raster::render(screen, 1);
+                match actual.diff(expected) {
+                    Some((0, _)) => Ok(()),
+                    Some((count, diff)) => Err(Mismatch::Pixels {
+                        count: count,
+                        diff: diff,
+                    }),
+                    None => Err(Mismatch::Size {
+                        expected: (expected.width, expected.height),
+                        actual: (actual.width, actual.height),
+                    }),
+                }
+            }
+        }
+    }
+}
+
+fn is_png(path: &Path) -> bool {
+    path.extension().map_or(false, |e| e.to_string_lossy().to_lowercase() == "png")
+}
+
+/// Trailing spaces and empty lines are ignored.
+fn check_text(expected: &str, actual: &str) -> Result<(), Mismatch> {
+    let lines = |text: &str| -> Vec<String> {
+        let mut lines: Vec<String> = text.lines()
+                                         .map(|l| l.trim_right().into())
+                                         .collect();
+        while lines.last().map_or(false, |l| l.is_empty()) {
+            lines.pop();
+        }
+        lines
+    };
+    let (expected, actual) = (lines(expected), lines(actual));
+    for i in 0..expected.len().max(actual.len()) {
+        let e = expected.get(i).cloned().unwrap_or_else(String::new);
+        let a = actual.get(i).cloned().unwrap_or_else(String::new);
+        if e != a {
+            return Err(Mismatch::Text {
+                line: i + 1,
+                expected: e,
+                actual: a,
+            });
+        }
+    }
+    Ok(())
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Mismatch {
+    /// First differing line, starting at 1.
+    Text {
+        line: usize,
+        expected: String,
+        actual: String,
+    },
+    Size {
+        expected: (usize, usize),
+        actual: (usize, usize),
+    },
+    /// Number of differing pixels, and the screen with these pixels in red.
+    Pixels {
+        count: usize,
+        diff: Frame,
+    },
+}
+
+impl fmt::Display for Mismatch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Mismatch::Text { line, ref expected, ref actual } => {
+                write!(f,
+                       "line {} differs: expected \"{}\", got \"{}\"",
+                       line,
+                       expected,
+                       actual)
+            }
+            Mismatch::Size { expected, actual } => {
+                write!(f,
+                       "expected a {}x{} image, got {}x{}",
+                       expected.0,
+                       expected.1,
+                       actual.0,
+                       actual.1)
+            }
+            Mismatch::Pixels { count, .. } => write!(f, "{} pixels differ", count),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use emulator::device::lem1802::{DEFAULT_FONT, DEFAULT_PALETTE, Font, Vram};
+
+    #[test]
+    fn test_check() {
+        let mut vram = Vram([0; 386]);
+        vram.0[32] = 0xf048;
+        vram.0[33] = 0xf069;
+        let screen = RawScreen {
+            vram: vram,
+            font: Font(DEFAULT_FONT),
+            palette: DEFAULT_PALETTE,
+            border: 0,
+            blink_on: true,
+        };
+        assert_eq!(Reference::Text("\nHi  \n\n".into()).check(&screen), Ok(()));
+        assert_eq!(Reference::Text("\nHo".into()).check(&screen),
+                   Err(Mismatch::Text {
+                       line: 2,
+                       expected: "Ho".into(),
+                       actual: "Hi".into(),
+                   }));
+
+        let image = raster::render(&screen, 1);
+        assert_eq!(Reference::Image(image.clone()).check(&screen), Ok(()));
+        let mut other = image;
+        other.pixels[0] = [1, 2, 3];
+        match Reference::Image(other).check(&screen) {
+            Err(Mismatch::Pixels { count: 1, diff }) => {
+                assert_eq!(diff.pixels[0], [255, 0, 0])
+            }
+            res => panic!("{:?}", res),
+        }
+    }
+}
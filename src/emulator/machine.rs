@@ -0,0 +1,773 @@
+//! Description of a whole machine: the ROM images loaded in ram and the
+//! devices plugged, usually read from a JSON file like this one:
+//!
+//! ```json
+//! {
+//!     "roms": [{"path": "boot.bin"}, {"path": "data.bin", "address": 32768}],
+//!     "boot": false,
+//!     "devices": [
+//!         {"clock": {"ticks_per_second": 100000, "eeprom": true}},
+//!         "spc2000",
+//!         {"m35fd": {"floppy": "disk.img", "mode": "write_back"}},
+//!         {"hmd2043": {"media": "media.img", "mode": "write_back"}},
+//!         {"m525hd": {"image": "hd.img", "geometry":
+//!             {"tracks": 256, "sectors_per_track": 40, "words_per_sector": 512}}},
+//!         {"hic": {"ports": 16}},
+//!         "keyscreen",
+//!         {"termscreen": {"pixels": true, "colors": "256"}},
+//!         {"recorder": {"output": "session.gif", "scale": 2}},
+//!         {"sped3": {"output": "sped3.gif"}},
+//!         "bbos"
+//!     ]
+//! }
+//! ```
+
+use std::cmp;
+use std::fs::File;
+use std::io;
+use std::path::Path;
+use std::str::FromStr;
+
+use byteorder::{self, ReadBytesExt};
+use emulator::{bbfs, Computer, Cpu, Debugger};
+use emulator::device::{self, bbos, clock, eeprom, hic, hmd2043, m35fd, m525hd,
+                       spc2000, sped3, Device};
+use emulator::device::disk::{self, Geometry, Media, WriteMode};
+use emulator::device::lem1802;
+#[cfg(any(feature = "glium", feature = "termscreen"))]
+use emulator::device::keyboard;
+#[cfg(feature = "glium")]
+use emulator::device::glium_backend;
+use emulator::device::lem1802::ansi;
+use emulator::device::record::{self, RecordBackend};
+#[cfg(feature = "termscreen")]
+use emulator::device::term_backend;
+
+error_chain! {
+    links {
+        Device(device::Error, device::ErrorKind);
+    }
+
+    foreign_links {
+        Io(io::Error);
+    }
+
+    errors {
+        UnknownDevice(d: String) {
+            description("unknown device")
+            display("unknown device \"{}\"", d)
+        }
+        UnknownOption(d: String, option: String) {
+            description("unknown device option")
+            display("unknown option \"{}\" for the {} device", option, d)
+        }
+        InvalidHicPorts(n: usize) {
+            description("invalid number of HIC ports")
+            display("invalid number of HIC ports: {} (must be 8, 16 or 32)", n)
+        }
+        InvalidRecording(frame_ticks: u64, scale: usize) {
+            description("invalid recording settings")
+            display("invalid recording settings: frame_ticks {} and scale {} \
+                     must not be 0",
+                    frame_ticks,
+                    scale)
+        }
+        InvalidGeometry(g: Geometry) {
+            description("invalid disk geometry")
+            display("invalid disk geometry: {} tracks of {} sectors of {} words \
+                     (none must be 0, and there must be at most {} sectors)",
+                    g.tracks,
+                    g.sectors_per_track,
+                    g.words_per_sector,
+                    disk::MAX_SECTORS)
+        }
+        Disabled(d: String, feature: String) {
+            description("device disabled at compile time")
+            display("the {} device is disabled, activate the \"{}\" feature",
+                    d,
+                    feature)
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct Machine {
+    /// Binary files loaded in ram, in this order.
+    #[serde(default)]
+    pub roms: Vec<Rom>,
+    /// Devices, in hardware index order.
+    #[serde(default)]
+    pub devices: Vec<DeviceDescription>,
+    /// Boot from the first bootable m35fd after loading the ROMs, see
+    /// `bbos::boot`.
+    #[serde(default)]
+    pub boot: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Rom {
+    /// Relative paths start from the machine file directory.
+    pub path: String,
+    #[serde(default)]
+    pub address: u16,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum DeviceDescription {
+    #[serde(rename = "clock")]
+    Clock {
+        #[serde(default = "default_ticks_per_second")]
+        ticks_per_second: u64,
+        /// Wrapped in an EEPROM unless disabled, like the emulator always
+        /// did with `-d clock`.
+        #[serde(default = "default_clock_eeprom")]
+        eeprom: bool,
+    },
+    #[serde(rename = "spc2000")]
+    Spc2000 {
+        #[serde(default = "default_ticks_per_second")]
+        ticks_per_second: u64,
+    },
+    #[serde(rename = "m35fd")]
+    M35fd {
+        /// Floppy image to insert, `"empty"` for a blank floppy.
+        #[serde(default)]
+        floppy: Option<String>,
+        #[serde(default)]
+        write_protected: bool,
+        #[serde(default)]
+        mode: WriteMode,
+        /// Create a blank image if the floppy file doesn't exist.
+        #[serde(default)]
+        create: bool,
+        #[serde(default)]
+        eeprom: bool,
+    },
+    #[serde(rename = "hmd2043")]
+    Hmd2043 {
+        /// Media image to insert, `"empty"` for a blank media.
+        #[serde(default)]
+        media: Option<String>,
+        #[serde(default = "default_hmd2043_geometry")]
+        geometry: Geometry,
+        #[serde(default)]
+        mode: WriteMode,
+    },
+    #[serde(rename = "m525hd")]
+    M525hd {
+        /// Disk image, blank if not given.
+        #[serde(default)]
+        image: Option<String>,
+        #[serde(default = "default_m525hd_geometry")]
+        geometry: Geometry,
+        #[serde(default)]
+        mode: WriteMode,
+    },
+    #[serde(rename = "hic")]
+    Hic {
+        #[serde(default = "default_hic_ports")]
+        ports: usize,
+        #[serde(default)]
+        eeprom: bool,
+    },
+    /// LEM1802 and keyboard sharing a window.
+    #[serde(rename = "keyscreen")]
+    Keyscreen,
+    /// LEM1802 and keyboard using the terminal.
+    #[serde(rename = "termscreen")]
+    Termscreen {
+        /// Draw each pixel instead of the ASCII characters.
+        #[serde(default)]
+        pixels: bool,
+        #[serde(default)]
+        colors: ansi::ColorMode,
+    },
+    /// LEM1802 recording its frames to an animated GIF if `output` ends with
+    /// `.gif`, else to PNG files named after `output`, `{}` being replaced
+    /// by the frame number.
+    #[serde(rename = "recorder")]
+    Recorder {
+        output: String,
+        #[serde(default = "default_frame_ticks")]
+        frame_ticks: u64,
+        #[serde(default = "default_scale")]
+        scale: usize,
+    },
+    /// SPED-3 recording its frames like `Recorder` if `output` is given.
+    #[serde(rename = "sped3")]
+    Sped3 {
+        #[serde(default)]
+        output: Option<String>,
+        #[serde(default = "default_frame_ticks")]
+        frame_ticks: u64,
+        #[serde(default = "default_scale")]
+        scale: usize,
+    },
+    /// High-level emulation of the BBOS services, installed by `bbos::boot`.
+    #[serde(rename = "bbos")]
+    Bbos,
+}
+
+fn default_ticks_per_second() -> u64 {
+    100_000
+}
+
+fn default_clock_eeprom() -> bool {
+    true
+}
+
+fn default_hmd2043_geometry() -> Geometry {
+    hmd2043::HMU1440
+}
+
+fn default_m525hd_geometry() -> Geometry {
+    m525hd::M525HD
+}
+
+fn default_hic_ports() -> usize {
+    8
+}
+
+fn default_frame_ticks() -> u64 {
+    10_000
+}
+
+fn default_scale() -> usize {
+    2
+}
+
+impl DeviceDescription {
+    /// Create the described devices, loading files relative to `base`.
+    pub fn build<P: AsRef<Path>>(&self, base: P) -> Result<Vec<Box<Device>>> {
+        match *self {
+            DeviceDescription::Clock { ticks_per_second, eeprom } =>
+                Ok(vec![wrap(clock::Clock::new(ticks_per_second), eeprom)]),
+            DeviceDescription::Spc2000 { ticks_per_second } =>
+                Ok(vec![Box::new(spc2000::Spc2000::new(ticks_per_second))]),
+            DeviceDescription::M35fd { ref floppy,
+                                       write_protected,
+                                       mode,
+                                       create,
+                                       eeprom } => {
+                let mut floppy = match floppy.as_ref().map(|s| s.as_str()) {
+                    Some("empty") => Some(m35fd::Floppy::default()),
+                    Some(path) => {
+                        let path = base.as_ref().join(path);
+                        if create && !path.exists() {
+                            try!(m35fd::Floppy::create(&path));
+                        }
+                        Some(try!(m35fd::Floppy::open(path, mode)))
+                    }
+                    None => None,
+                };
+                if let Some(ref mut floppy) = floppy {
+                    floppy.set_write_protected(write_protected);
+                }
+                Ok(vec![wrap(m35fd::M35fd::new(floppy), eeprom)])
+            }
+            DeviceDescription::Hmd2043 { ref media, geometry, mode } => {
+                try!(check_geometry(geometry));
+                let media = match media.as_ref().map(|s| s.as_str()) {
+                    Some("empty") => Some(Media::new(geometry)),
+                    Some(path) => {
+                        Some(try!(Media::open(base.as_ref().join(path), geometry, mode)))
+                    }
+                    None => None,
+                };
+                Ok(vec![Box::new(hmd2043::Hmd2043::new(media))])
+            }
+            DeviceDescription::M525hd { ref image, geometry, mode } => {
+                try!(check_geometry(geometry));
+                let media = match *image {
+                    Some(ref path) => {
+                        try!(Media::open(base.as_ref().join(path), geometry, mode))
+                    }
+                    None => Media::new(geometry),
+                };
+                Ok(vec![Box::new(m525hd::M525hd::new(media))])
+            }
+            DeviceDescription::Hic { ports, eeprom } => {
+                let hic = try!(hic::HIC::new(ports)
+                                        .ok_or(ErrorKind::InvalidHicPorts(ports)));
+                Ok(vec![wrap(hic, eeprom)])
+            }
+            DeviceDescription::Keyscreen => keyscreen(),
+            DeviceDescription::Termscreen { pixels, colors } => {
+                termscreen(ansi::Options {
+                    pixels: pixels,
+                    colors: colors,
+                })
+            }
+            DeviceDescription::Recorder { ref output, frame_ticks, scale } => {
+                let output = record::Output::from_path(output);
+                let backend = try!(recorder(output, frame_ticks, scale));
+                Ok(vec![Box::new(lem1802::LEM1802::new(backend))])
+            }
+            DeviceDescription::Sped3 { ref output, frame_ticks, scale } => {
+                let output = match *output {
+                    Some(ref output) => record::Output::from_path(output),
+                    None => record::Output::Nothing,
+                };
+                let backend = try!(recorder(output, frame_ticks, scale));
+                Ok(vec![Box::new(sped3::Sped3::new(backend))])
+            }
+            DeviceDescription::Bbos => Ok(vec![Box::new(bbos::Bbos::new())]),
+        }
+    }
+}
+
+/// Parse the `-d` syntax of the emulator: `clock`, `spc2000`, `keyscreen`,
+/// `termscreen(=<option>(,<option>)*)?` with the options `pixels` and `256`,
+/// `recorder=<output>`, `sped3(=<output>)?`, `hic(=<n>)?`,
+/// `m35fd(=(<floppy>(,<option>)*|empty))?` with the options `ro`, `wb` and
+/// `create` read from the end, so that `<floppy>` can contain commas,
+/// `hmd2043(=(<media>(,wb)?|empty))?`, `m525hd(=<image>(,wb)?)?` or `bbos`.
+impl FromStr for DeviceDescription {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<DeviceDescription> {
+        let mut components = s.splitn(2, '=');
+        match (components.next().unwrap(), components.next()) {
+            ("clock", None) => Ok(DeviceDescription::Clock {
+                ticks_per_second: default_ticks_per_second(),
+                eeprom: default_clock_eeprom(),
+            }),
+            ("spc2000", None) => Ok(DeviceDescription::Spc2000 {
+                ticks_per_second: default_ticks_per_second(),
+            }),
+            ("keyscreen", None) => Ok(DeviceDescription::Keyscreen),
+            ("bbos", None) => Ok(DeviceDescription::Bbos),
+            ("termscreen", options) => {
+                let mut pixels = false;
+                let mut colors = ansi::ColorMode::TrueColor;
+                // `termscreen=` has no options
+                for option in options.iter()
+                                     .flat_map(|o| o.split(','))
+                                     .filter(|o| !o.is_empty()) {
+                    match option {
+                        "pixels" => pixels = true,
+                        "256" => colors = ansi::ColorMode::Ansi256,
+                        _ => try!(Err(ErrorKind::UnknownOption("termscreen".into(),
+                                                               option.into()))),
+                    }
+                }
+                Ok(DeviceDescription::Termscreen {
+                    pixels: pixels,
+                    colors: colors,
+                })
+            }
+            ("recorder", Some(output)) => Ok(DeviceDescription::Recorder {
+                output: output.into(),
+                frame_ticks: default_frame_ticks(),
+                scale: default_scale(),
+            }),
+            ("sped3", output) => Ok(DeviceDescription::Sped3 {
+                output: output.map(|o| o.into()),
+                frame_ticks: default_frame_ticks(),
+                scale: default_scale(),
+            }),
+            ("m35fd", mut floppy) => {
+                let mut write_protected = false;
+                let mut mode = WriteMode::CopyOnWrite;
+                let mut create = false;
+                while let Some((path, option)) = floppy.and_then(|f| {
+                    f.rfind(',').map(|i| (&f[..i], &f[i + 1..]))
+                }) {
+                    match option {
+                        "ro" => write_protected = true,
+                        "wb" => mode = WriteMode::WriteBack,
+                        "create" => create = true,
+                        _ => break,
+                    }
+                    floppy = Some(path);
+                }
+                Ok(DeviceDescription::M35fd {
+                    floppy: floppy.map(|f| f.into()),
+                    write_protected: write_protected,
+                    mode: mode,
+                    create: create,
+                    eeprom: false,
+                })
+            }
+            ("hmd2043", media) => {
+                let (media, mode) = write_mode_option(media);
+                Ok(DeviceDescription::Hmd2043 {
+                    media: media.map(|m| m.into()),
+                    geometry: default_hmd2043_geometry(),
+                    mode: mode,
+                })
+            }
+            ("m525hd", image) => {
+                let (image, mode) = write_mode_option(image);
+                Ok(DeviceDescription::M525hd {
+                    image: image.map(|i| i.into()),
+                    geometry: default_m525hd_geometry(),
+                    mode: mode,
+                })
+            }
+            ("hic", ports) => {
+                let ports = match ports {
+                    Some(p) => try!(p.parse().map_err(|_| {
+                        ErrorKind::UnknownDevice(s.into())
+                    })),
+                    None => default_hic_ports(),
+                };
+                Ok(DeviceDescription::Hic {
+                    ports: ports,
+                    eeprom: false,
+                })
+            }
+            _ => Err(ErrorKind::UnknownDevice(s.into()).into()),
+        }
+    }
+}
+
+/// Split the `,wb` option from the end of a `hmd2043` or `m525hd` image.
+fn write_mode_option(image: Option<&str>) -> (Option<&str>, WriteMode) {
+    match image {
+        Some(image) if image.ends_with(",wb") => {
+            (Some(&image[..image.len() - 3]), WriteMode::WriteBack)
+        }
+        _ => (image, WriteMode::CopyOnWrite),
+    }
+}
+
+fn check_geometry(geometry: Geometry) -> Result<()> {
+    if geometry.is_valid() {
+        Ok(())
+    } else {
+        Err(ErrorKind::InvalidGeometry(geometry).into())
+    }
+}
+
+fn recorder(output: record::Output,
+            frame_ticks: u64,
+            scale: usize) -> Result<RecordBackend> {
+    if frame_ticks == 0 || scale == 0 {
+        try!(Err(ErrorKind::InvalidRecording(frame_ticks, scale)));
+    }
+    if !cfg!(feature = "images") && output != record::Output::Nothing {
+        try!(Err(ErrorKind::Disabled("recorder".into(), "images".into())));
+    }
+    Ok(RecordBackend::new(output, frame_ticks, scale))
+}
+
+fn wrap<D: Device>(device: D, with_eeprom: bool) -> Box<Device> {
+    if with_eeprom {
+        Box::new(eeprom::Eeprom::new(device))
+    } else {
+        Box::new(device)
+    }
+}
+
+#[cfg(feature = "glium")]
+fn keyscreen() -> Result<Vec<Box<Device>>> {
+    let (screen_backend, kb_backend) = glium_backend::start();
+    Ok(vec![Box::new(keyboard::Keyboard::new(kb_backend)),
+            Box::new(lem1802::LEM1802::new(screen_backend))])
+}
+
+#[cfg(not(feature = "glium"))]
+fn keyscreen() -> Result<Vec<Box<Device>>> {
+    Err(ErrorKind::Disabled("keyscreen".into(), "glium".into()).into())
+}
+
+#[cfg(feature = "termscreen")]
+fn termscreen(options: ansi::Options) -> Result<Vec<Box<Device>>> {
+    let (screen_backend, kb_backend) = term_backend::start(options);
+    Ok(vec![Box::new(keyboard::Keyboard::new(kb_backend)),
+            Box::new(lem1802::LEM1802::new(screen_backend))])
+}
+
+#[cfg(not(feature = "termscreen"))]
+fn termscreen(_: ansi::Options) -> Result<Vec<Box<Device>>> {
+    Err(ErrorKind::Disabled("termscreen".into(), "termscreen".into()).into())
+}
+
+/// Assemble a cpu, its ram content and its devices.
+pub struct ComputerBuilder {
+    cpu: Cpu,
+    devices: Vec<Box<Device>>,
+    /// End of the last loaded ROM.
+    image_end: usize,
+    booted: bool,
+}
+
+impl ComputerBuilder {
+    pub fn new() -> ComputerBuilder {
+        ComputerBuilder {
+            cpu: Cpu::default(),
+            devices: vec![],
+            image_end: 0,
+            booted: false,
+        }
+    }
+
+    pub fn rom(mut self, rom: &[u16], address: u16) -> ComputerBuilder {
+        self.cpu.load(rom, address);
+        self.image_end = cmp::max(self.image_end,
+                                  cmp::min(address as usize + rom.len(),
+                                           0x10000));
+        self
+    }
+
+    pub fn rom_file<P: AsRef<Path>>(self,
+                                    path: P,
+                                    address: u16) -> Result<ComputerBuilder> {
+        let mut input = try!(File::open(path));
+        let rom: Vec<u16> = input.iter_items::<u16, byteorder::LittleEndian>()
+                                 .collect();
+        Ok(self.rom(&rom, address))
+    }
+
+    pub fn device(mut self, device: Box<Device>) -> ComputerBuilder {
+        self.devices.push(device);
+        self
+    }
+
+    pub fn device_description<P: AsRef<Path>>(mut self,
+                                              description: &DeviceDescription,
+                                              base: P)
+                                              -> Result<ComputerBuilder> {
+        self.devices.extend(try!(description.build(base)));
+        Ok(self)
+    }
+
+    /// Load the ROMs and add the devices of `machine`. Relative paths start
+    /// from `base`.
+    pub fn machine<P: AsRef<Path>>(mut self,
+                                   machine: &Machine,
+                                   base: P) -> Result<ComputerBuilder> {
+        let base = base.as_ref();
+        for rom in &machine.roms {
+            self = try!(self.rom_file(base.join(&rom.path), rom.address));
+        }
+        for device in &machine.devices {
+            self = try!(self.device_description(device, base));
+        }
+        if machine.boot {
+            self = try!(self.boot());
+        }
+        Ok(self)
+    }
+
+    /// Load the boot sector of the first bootable m35fd at address 0 and
+    /// install the BBOS services if the device is plugged.
+    pub fn boot(mut self) -> Result<ComputerBuilder> {
+        try!(bbos::boot(&mut self.cpu, &mut self.devices));
+        self.image_end = cmp::max(self.image_end, bbfs::SECTOR_SIZE);
+        self.booted = true;
+        Ok(self)
+    }
+
+    /// Content of the ram up to the end of the last ROM, to reload it on
+    /// power cycles.
+    pub fn image(&self) -> Vec<u16> {
+        self.cpu.ram.iter().take(self.image_end).cloned().collect()
+    }
+
+    pub fn into_parts(self) -> (Cpu, Vec<Box<Device>>) {
+        (self.cpu, self.devices)
+    }
+
+    /// Whether `boot` was called, so resets should boot again.
+    pub fn is_booted(&self) -> bool {
+        self.booted
+    }
+
+    pub fn build(self) -> Computer {
+        let mut computer = Computer::new(self.cpu, self.devices);
+        computer.boot_on_reset(self.booted);
+        computer
+    }
+
+    pub fn build_debugger(self) -> Debugger {
+        let image = self.image();
+        let mut debugger = Debugger::new(self.cpu, self.devices);
+        debugger.rom(image);
+        debugger.boot_on_reset(self.booted);
+        debugger
+    }
+}
+
+impl Default for ComputerBuilder {
+    fn default() -> ComputerBuilder {
+        ComputerBuilder::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json;
+
+    use super::*;
+
+    fn build_error(description: DeviceDescription) -> ErrorKind {
+        match description.build("") {
+            Ok(_) => panic!("{:?} was built", description),
+            Err(Error(kind, _)) => kind,
+        }
+    }
+
+    #[test]
+    fn test_machine_json() {
+        let json = r#"{
+            "roms": [{"path": "bios.bin"}, {"path": "data.bin", "address": 4096}],
+            "devices": [
+                {"clock": {}},
+                {"m35fd": {"floppy": "disk.img", "mode": "write_back"}},
+                {"hmd2043": {"geometry": {"tracks": 80,
+                                          "sectors_per_track": 18,
+                                          "words_per_sector": 512}}},
+                {"recorder": {"output": "session.gif", "scale": 1}},
+                "keyscreen",
+                "bbos"
+            ],
+            "boot": true
+        }"#;
+        let machine: Machine = serde_json::from_str(json).unwrap();
+        assert_eq!(machine.roms[1],
+                   Rom {
+                       path: "data.bin".into(),
+                       address: 4096,
+                   });
+        assert_eq!(machine.devices[0],
+                   DeviceDescription::Clock {
+                       ticks_per_second: 100_000,
+                       eeprom: true,
+                   });
+        assert_eq!(machine.devices[1],
+                   DeviceDescription::M35fd {
+                       floppy: Some("disk.img".into()),
+                       write_protected: false,
+                       mode: WriteMode::WriteBack,
+                       create: false,
+                       eeprom: false,
+                   });
+        assert_eq!(machine.devices[3],
+                   DeviceDescription::Recorder {
+                       output: "session.gif".into(),
+                       frame_ticks: 10_000,
+                       scale: 1,
+                   });
+        assert!(machine.boot);
+
+        let json = serde_json::to_string(&machine).unwrap();
+        assert_eq!(serde_json::from_str::<Machine>(&json).unwrap(), machine);
+        assert_eq!(serde_json::from_str::<Machine>("{}").unwrap(), Machine::default());
+
+        for json in &[r#"{"devices": ["floppy"]}"#,
+                      r#"{"devices": [{"recorder": {}}]}"#,
+                      r#"{"roms": [{"address": 0}]}"#,
+                      r#"{"devices": [{"m35fd": {"mode": "read_only"}}]}"#] {
+            assert!(serde_json::from_str::<Machine>(json).is_err(), "{}", json);
+        }
+    }
+
+    #[test]
+    fn test_device_from_str() {
+        assert_eq!("clock".parse::<DeviceDescription>().unwrap(),
+                   DeviceDescription::Clock {
+                       ticks_per_second: 100_000,
+                       eeprom: true,
+                   });
+        assert_eq!("m35fd=disk.img,ro,wb".parse::<DeviceDescription>().unwrap(),
+                   DeviceDescription::M35fd {
+                       floppy: Some("disk.img".into()),
+                       write_protected: true,
+                       mode: WriteMode::WriteBack,
+                       create: false,
+                       eeprom: false,
+                   });
+        assert_eq!("m35fd=a,ro.img,create".parse::<DeviceDescription>().unwrap(),
+                   DeviceDescription::M35fd {
+                       floppy: Some("a,ro.img".into()),
+                       write_protected: false,
+                       mode: WriteMode::CopyOnWrite,
+                       create: true,
+                       eeprom: false,
+                   });
+        assert_eq!("m525hd=hd.img,wb".parse::<DeviceDescription>().unwrap(),
+                   DeviceDescription::M525hd {
+                       image: Some("hd.img".into()),
+                       geometry: m525hd::M525HD,
+                       mode: WriteMode::WriteBack,
+                   });
+        assert_eq!("termscreen=".parse::<DeviceDescription>().unwrap(),
+                   DeviceDescription::Termscreen {
+                       pixels: false,
+                       colors: ansi::ColorMode::TrueColor,
+                   });
+        assert_eq!("hic=16".parse::<DeviceDescription>().unwrap(),
+                   DeviceDescription::Hic {
+                       ports: 16,
+                       eeprom: false,
+                   });
+        assert_eq!("sped3".parse::<DeviceDescription>().unwrap(),
+                   DeviceDescription::Sped3 {
+                       output: None,
+                       frame_ticks: 10_000,
+                       scale: 2,
+                   });
+
+        for s in &["floppy", "clock=1", "recorder", "hic=many"] {
+            match s.parse::<DeviceDescription>() {
+                Err(Error(ErrorKind::UnknownDevice(ref d), _)) if d == s => (),
+                r => panic!("{}: {:?}", s, r),
+            }
+        }
+        match "termscreen=pixels,16".parse::<DeviceDescription>() {
+            Err(Error(ErrorKind::UnknownOption(ref d, ref o), _))
+                if d == "termscreen" && o == "16" => (),
+            r => panic!("{:?}", r),
+        }
+    }
+
+    #[test]
+    fn test_build_errors() {
+        match build_error(DeviceDescription::Hic {
+            ports: 3,
+            eeprom: false,
+        }) {
+            ErrorKind::InvalidHicPorts(3) => (),
+            e => panic!("{:?}", e),
+        }
+        match build_error(DeviceDescription::Recorder {
+            output: "session.gif".into(),
+            frame_ticks: 0,
+            scale: 2,
+        }) {
+            ErrorKind::InvalidRecording(0, 2) => (),
+            e => panic!("{:?}", e),
+        }
+        match build_error(DeviceDescription::Sped3 {
+            output: None,
+            frame_ticks: 10_000,
+            scale: 0,
+        }) {
+            ErrorKind::InvalidRecording(10_000, 0) => (),
+            e => panic!("{:?}", e),
+        }
+        let geometry = Geometry {
+            tracks: 0x100,
+            sectors_per_track: 0x101,
+            words_per_sector: 512,
+        };
+        match build_error(DeviceDescription::M525hd {
+            image: None,
+            geometry: geometry,
+            mode: WriteMode::CopyOnWrite,
+        }) {
+            ErrorKind::InvalidGeometry(g) if g == geometry => (),
+            e => panic!("{:?}", e),
+        }
+        match build_error(DeviceDescription::Hmd2043 {
+            media: None,
+            geometry: Geometry { tracks: 0, ..geometry },
+            mode: WriteMode::CopyOnWrite,
+        }) {
+            ErrorKind::InvalidGeometry(_) => (),
+            e => panic!("{:?}", e),
+        }
+    }
+}
@@ -1,12 +1,23 @@
+pub mod bbfs;
 pub mod cpu;
 mod computer;
+pub mod coverage;
 pub mod debugger;
 pub mod device;
+pub mod golden;
+pub mod machine;
+pub mod profiler;
 mod ram;
 mod registers;
+pub mod symbols;
+#[cfg(test)]
+pub mod test_utils;
+pub mod tester;
+pub mod trace;
 
 pub use emulator::cpu::Cpu;
-pub use emulator::computer::Computer;
+pub use emulator::computer::{Computer, ExitConditions, ExitReason};
 pub use emulator::debugger::Debugger;
+pub use emulator::machine::ComputerBuilder;
 pub use emulator::ram::Ram;
 pub use emulator::registers::Registers;
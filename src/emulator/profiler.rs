@@ -0,0 +1,415 @@
+//! Cycle profiler following the `JSR` call stacks.
+//!
+//! In exact mode, every executed instruction is counted with its cycles and
+//! the call stack is followed through `JSR`, interrupts and returns (the
+//! stack pointer going back over the return address). In sampling mode, the
+//! current PC is sampled every few ticks and the callers are found by looking
+//! for return addresses of `JSR` instructions in the stack, which is much
+//! cheaper but can be fooled by data looking like return addresses.
+//!
+//! The results are the cycles per PC, the inclusive and exclusive cycles per
+//! function (the label at or before an address) and the folded stacks used by
+//! flamegraph tools:
+//!
+//! ```text
+//! main;update;draw_sprite 1234
+//! ```
+
+use std::cmp;
+use std::collections::{HashMap, HashSet};
+use std::io::{self, Write};
+
+use assembler::types::Globals;
+use emulator::cpu::Cpu;
+use emulator::symbols::SymbolMap;
+use emulator::trace::{Entry, Event};
+use types::{Instruction, SpecialOp};
+
+/// Pseudo function counting the ticks spent in `HLT`.
+const HALTED: u32 = 0x10000;
+/// Maximum number of stack words scanned for return addresses in sampling
+/// mode.
+const MAX_SAMPLED_WORDS: usize = 256;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Mode {
+    /// Count each instruction.
+    Exact,
+    /// Sample the stack every `n` ticks.
+    Sampling(u64),
+}
+
+/// A `JSR` or an interrupt being executed.
+#[derive(Debug, Clone)]
+struct Frame {
+    /// Address of the call, or of the instruction interrupted.
+    call_site: u16,
+    /// Stack pointer after pushing the return address.
+    sp: u16,
+}
+
+/// Cycles spent in a function.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FunctionCycles {
+    pub name: String,
+    /// Cycles spent in the function and the ones it calls.
+    pub inclusive: u64,
+    /// Cycles spent in the function itself.
+    pub exclusive: u64,
+}
+
+pub struct Profiler {
+    mode: Mode,
+    symbols: SymbolMap,
+    frames: Vec<Frame>,
+    ticks: u64,
+    /// Cycles by PC.
+    pcs: HashMap<u16, u64>,
+    /// Cycles by call stack, outermost function first. Functions are
+    /// identified by the address of their label.
+    stacks: HashMap<Vec<u32>, u64>,
+}
+
+impl Profiler {
+    pub fn new(mode: Mode) -> Profiler {
+        Profiler {
+            mode: mode,
+            symbols: SymbolMap::default(),
+            frames: vec![],
+            ticks: 0,
+            pcs: HashMap::new(),
+            stacks: HashMap::new(),
+        }
+    }
+
+    pub fn symbols(&mut self, symbols: &Globals) {
+        self.symbols = SymbolMap::new(symbols);
+    }
+
+    pub fn mode(&self) -> Mode {
+        self.mode
+    }
+
+    /// Record one tick. `steps` are the trace entries of the tick, which
+    /// are only needed in exact mode.
+    pub fn tick(&mut self, cpu: &Cpu, steps: &[Entry]) {
+        self.ticks += 1;
+        match self.mode {
+            Mode::Exact => {
+                let mut executed = false;
+                for step in steps {
+                    executed |= self.record_step(cpu, step);
+                }
+                if !executed && cpu.halted {
+                    let callers = self.callers();
+                    self.add(callers, HALTED, None, 1);
+                }
+            }
+            Mode::Sampling(period) => if self.ticks % cmp::max(period, 1) == 0 {
+                self.sample(cpu, period);
+            },
+        }
+    }
+
+    /// Return if `step` is an executed instruction.
+    fn record_step(&mut self, cpu: &Cpu, step: &Entry) -> bool {
+        let instruction = match step.event {
+            Event::Instruction(instruction) => instruction,
+            Event::Interrupt(_) => {
+                // PC and A are pushed before running the handler
+                if let Some(&(sp, _)) = step.memory.get(1) {
+                    self.frames.push(Frame {
+                        call_site: step.pc,
+                        sp: sp,
+                    });
+                }
+                return false;
+            }
+            Event::Devices => return false,
+        };
+
+        let callers = self.callers();
+        let function = self.function_of(step.pc);
+        let cycles = cmp::max(instruction.delay(), 1) as u64;
+        self.add(callers, function, Some(step.pc), cycles);
+
+        if let Instruction::SpecialOp(SpecialOp::JSR, _) = instruction {
+            self.frames.push(Frame {
+                call_site: step.pc,
+                sp: cpu.sp.0,
+            });
+        }
+        while self.frames.last().map_or(false, |f| cpu.sp.0 > f.sp) {
+            self.frames.pop();
+        }
+        true
+    }
+
+    fn sample(&mut self, cpu: &Cpu, period: u64) {
+        let mut callers = vec![];
+        let sp = cpu.sp.0;
+        // The stack goes from SP to 0xffff, and is empty when SP is 0
+        let words = cmp::min((0x10000 - sp as usize) % 0x10000, MAX_SAMPLED_WORDS);
+        for offset in 0..words {
+            let ret = cpu.ram[sp.wrapping_add(offset as u16)];
+            if let Some(call_site) = jsr_before(cpu, ret) {
+                callers.push(self.function_of(call_site));
+            }
+        }
+        callers.reverse();
+        if cpu.halted {
+            self.add(callers, HALTED, None, period);
+        } else {
+            let function = self.function_of(cpu.pc.0);
+            self.add(callers, function, Some(cpu.pc.0), period);
+        }
+    }
+
+    fn callers(&self) -> Vec<u32> {
+        self.frames.iter().map(|f| self.function_of(f.call_site)).collect()
+    }
+
+    fn function_of(&self, addr: u16) -> u32 {
+        match self.symbols.label_of(addr) {
+            Some((label, _)) => label as u32,
+            None => addr as u32,
+        }
+    }
+
+    fn add(&mut self, mut stack: Vec<u32>, function: u32, pc: Option<u16>, cycles: u64) {
+        if let Some(pc) = pc {
+            *self.pcs.entry(pc).or_insert(0) += cycles;
+        }
+        stack.push(function);
+        *self.stacks.entry(stack).or_insert(0) += cycles;
+    }
+
+    fn name(&self, function: u32) -> String {
+        if function == HALTED {
+            return "[halted]".into();
+        }
+        match self.symbols.label_at(function as u16) {
+            Some(name) => name.into(),
+            None => format!("0x{:04x}", function),
+        }
+    }
+
+    pub fn total_cycles(&self) -> u64 {
+        self.stacks.values().sum()
+    }
+
+    /// Cycles by PC, the most used first.
+    pub fn pcs(&self) -> Vec<(u16, u64)> {
+        let mut pcs: Vec<(u16, u64)> = self.pcs
+                                           .iter()
+                                           .map(|(&pc, &cycles)| (pc, cycles))
+                                           .collect();
+        pcs.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+        pcs
+    }
+
+    /// Cycles by function, the highest exclusive time first.
+    pub fn functions(&self) -> Vec<FunctionCycles> {
+        let mut inclusive = HashMap::new();
+        let mut exclusive = HashMap::new();
+        for (stack, &cycles) in &self.stacks {
+            *exclusive.entry(*stack.last().unwrap()).or_insert(0) += cycles;
+            // Recursive functions are counted once
+            let functions: HashSet<&u32> = stack.iter().collect();
+            for &function in functions {
+                *inclusive.entry(function).or_insert(0) += cycles;
+            }
+        }
+        let mut functions: Vec<FunctionCycles> =
+            inclusive.iter()
+                     .map(|(&function, &inclusive)| FunctionCycles {
+                         name: self.name(function),
+                         inclusive: inclusive,
+                         exclusive: *exclusive.get(&function).unwrap_or(&0),
+                     })
+                     .collect();
+        functions.sort_by(|a, b| {
+            b.exclusive
+             .cmp(&a.exclusive)
+             .then(b.inclusive.cmp(&a.inclusive))
+             .then(a.name.cmp(&b.name))
+        });
+        functions
+    }
+
+    /// Write the folded stacks, one `f1;f2;f3 cycles` line per stack.
+    pub fn write_folded<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut lines: Vec<String> =
+            self.stacks
+                .iter()
+                .map(|(stack, cycles)| {
+                    let names: Vec<String> = stack.iter()
+                                                  .map(|&f| self.name(f))
+                                                  .collect();
+                    format!("{} {}", names.join(";"), cycles)
+                })
+                .collect();
+        lines.sort();
+        for line in lines {
+            try!(writeln!(output, "{}", line));
+        }
+        Ok(())
+    }
+
+    /// Write the `top` hottest functions and instructions.
+    pub fn write_report<W: Write>(&self, output: &mut W, top: usize) -> io::Result<()> {
+        let total = cmp::max(self.total_cycles(), 1);
+        let percent = |n: u64| n as f64 * 100. / total as f64;
+
+        try!(writeln!(output, "Total: {} cycles", self.total_cycles()));
+        try!(writeln!(output, ""));
+        try!(writeln!(output,
+                      "{:>20} {:>20}  function",
+                      "inclusive",
+                      "exclusive"));
+        for f in self.functions().iter().take(top) {
+            try!(writeln!(output,
+                          "{:>12} {:>6.2}% {:>12} {:>6.2}%  {}",
+                          f.inclusive,
+                          percent(f.inclusive),
+                          f.exclusive,
+                          percent(f.exclusive),
+                          f.name));
+        }
+        try!(writeln!(output, ""));
+        try!(writeln!(output, "{:>20}  instruction", "cycles"));
+        for (pc, cycles) in self.pcs().into_iter().take(top) {
+            try!(writeln!(output,
+                          "{:>12} {:>6.2}%  {:04x} {}",
+                          cycles,
+                          percent(cycles),
+                          pc,
+                          self.symbols.describe(pc)));
+        }
+        Ok(())
+    }
+}
+
+/// Address of the `JSR` returning to `ret`, if there is one.
+fn jsr_before(cpu: &Cpu, ret: u16) -> Option<u16> {
+    for len in 1..3 {
+        let addr = ret.wrapping_sub(len);
+        let words = [cpu.ram[addr],
+                     cpu.ram[addr.wrapping_add(1)],
+                     cpu.ram[addr.wrapping_add(2)]];
+        match Instruction::decode(&words) {
+            Ok((used, Instruction::SpecialOp(SpecialOp::JSR, _))) if used == len => {
+                return Some(addr)
+            }
+            _ => (),
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::types::{Globals, LabelInfos};
+    use emulator::{Computer, ExitConditions, ExitReason};
+    use types::{BasicOp, Register, Value};
+
+    fn computer() -> (Computer, Globals) {
+        // main: JSR f; JSR g; BRK 0
+        // f:    JSR g; SET PC, POP
+        // g:    ADD A, 1; SET PC, POP
+        let ops = [Instruction::SpecialOp(SpecialOp::JSR, Value::Litteral(4)),
+                   Instruction::SpecialOp(SpecialOp::JSR, Value::Litteral(6)),
+                   Instruction::SpecialOp(SpecialOp::BRK, Value::Litteral(0)),
+                   Instruction::SpecialOp(SpecialOp::BRK, Value::Litteral(0)),
+                   Instruction::SpecialOp(SpecialOp::JSR, Value::Litteral(6)),
+                   Instruction::BasicOp(BasicOp::SET, Value::PC, Value::Push),
+                   Instruction::BasicOp(BasicOp::ADD,
+                                        Value::Reg(Register::A),
+                                        Value::Litteral(1)),
+                   Instruction::BasicOp(BasicOp::SET, Value::PC, Value::Push)];
+        let mut cpu = Cpu::default();
+        cpu.load_ops(&ops, 0);
+        let mut globals = Globals::new();
+        for &(name, addr) in &[("main", 0), ("f", 4), ("g", 6)] {
+            globals.insert(name.into(), LabelInfos {
+                addr: addr,
+                .. LabelInfos::default()
+            });
+        }
+        (Computer::new(cpu, vec![]), globals)
+    }
+
+    #[test]
+    fn test_exact() {
+        let (mut computer, globals) = computer();
+        let mut profiler = Profiler::new(Mode::Exact);
+        profiler.symbols(&globals);
+        computer.profile(Some(profiler));
+        assert_eq!(computer.run(&ExitConditions::default(), |_| ()),
+                   ExitReason::Break(0));
+        let profiler = computer.profile(None).unwrap();
+
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded).unwrap();
+        // JSR: 3, SET PC, POP: 1, ADD: 2, BRK: 1
+        assert_eq!(String::from_utf8(folded).unwrap(),
+                   "main 7\nmain;f 4\nmain;f;g 3\nmain;g 3\n");
+
+        let functions = profiler.functions();
+        assert_eq!(functions[0],
+                   FunctionCycles {
+                       name: "main".into(),
+                       inclusive: 17,
+                       exclusive: 7,
+                   });
+        assert_eq!(functions[1],
+                   FunctionCycles {
+                       name: "g".into(),
+                       inclusive: 6,
+                       exclusive: 6,
+                   });
+        assert_eq!(profiler.pcs()[0], (6, 4));
+    }
+
+    #[test]
+    fn test_sampling() {
+        let (mut computer, globals) = computer();
+        let mut profiler = Profiler::new(Mode::Sampling(1));
+        profiler.symbols(&globals);
+        computer.profile(Some(profiler));
+        computer.run(&ExitConditions::default(), |_| ());
+        let profiler = computer.profile(None).unwrap();
+
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded.contains("main;f;g "));
+        // Same as the exact profile, the tick of BRK is not in current_tick
+        assert_eq!(profiler.total_cycles(), 17);
+    }
+
+    #[test]
+    fn test_sample_empty_stack() {
+        let (computer, globals) = computer();
+        let mut cpu = computer.cpu;
+        // Return addresses of main everywhere, but nothing in the stack
+        for addr in 0x100..0x200 {
+            cpu.ram[addr] = 1;
+        }
+        cpu.pc.0 = 6;
+        let mut profiler = Profiler::new(Mode::Sampling(1));
+        profiler.symbols(&globals);
+        profiler.sample(&cpu, 1);
+        cpu.sp.0 = 0x100;
+        profiler.sample(&cpu, 1);
+
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded.lines().any(|l| l == "g 1"), "{}", folded);
+        let depth = cmp::min(MAX_SAMPLED_WORDS, 0x100);
+        let stack = format!("{}g 1", "main;".repeat(depth));
+        assert!(folded.lines().any(|l| l == stack), "{}", folded);
+    }
+}
@@ -0,0 +1,82 @@
+use assembler::types::Globals;
+
+/// Labels sorted by address, to find the one containing an address.
+#[derive(Debug, Clone, Default)]
+pub struct SymbolMap {
+    /// Sorted by address, then by name.
+    labels: Vec<(u16, String)>,
+}
+
+impl SymbolMap {
+    pub fn new(globals: &Globals) -> SymbolMap {
+        let mut labels: Vec<(u16, String)> =
+            globals.iter()
+                   .map(|(name, infos)| (infos.addr, name.clone()))
+                   .collect();
+        labels.sort();
+        SymbolMap { labels: labels }
+    }
+
+    /// Nearest label at or before `addr`. When several labels share an
+    /// address, the first one by name is used.
+    pub fn label_of(&self, addr: u16) -> Option<(u16, &str)> {
+        // Number of labels at or before `addr`
+        let end = match self.labels.binary_search_by(|&(a, _)| {
+            if a <= addr {
+                ::std::cmp::Ordering::Less
+            } else {
+                ::std::cmp::Ordering::Greater
+            }
+        }) {
+            Ok(i) | Err(i) => i,
+        };
+        if end == 0 {
+            return None;
+        }
+        let label_addr = self.labels[end - 1].0;
+        let first = self.labels[..end]
+                        .iter()
+                        .position(|&(a, _)| a == label_addr)
+                        .unwrap();
+        Some((label_addr, &self.labels[first].1))
+    }
+
+    /// Label at exactly `addr`.
+    pub fn label_at(&self, addr: u16) -> Option<&str> {
+        match self.label_of(addr) {
+            Some((a, name)) if a == addr => Some(name),
+            _ => None,
+        }
+    }
+
+    /// `label+offset`, `label` or an empty string.
+    pub fn describe(&self, addr: u16) -> String {
+        match self.label_of(addr) {
+            Some((a, name)) if a == addr => name.into(),
+            Some((a, name)) => format!("{}+{}", name, addr - a),
+            None => String::new(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::types::{Globals, LabelInfos};
+
+    #[test]
+    fn test_label_of() {
+        let mut globals = Globals::new();
+        for &(name, addr) in &[("start", 0), ("main", 4), ("alias", 4)] {
+            globals.insert(name.into(), LabelInfos {
+                addr: addr,
+                .. LabelInfos::default()
+            });
+        }
+        let symbols = SymbolMap::new(&globals);
+        assert_eq!(symbols.label_of(2), Some((0, "start")));
+        assert_eq!(symbols.label_of(4), Some((4, "alias")));
+        assert_eq!(symbols.describe(7), "alias+3");
+        assert_eq!(symbols.label_at(3), None);
+    }
+}
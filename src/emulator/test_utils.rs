@@ -0,0 +1,32 @@
+//! Helpers shared by the tests.
+
+use std::env;
+use std::path::PathBuf;
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use emulator::cpu::Cpu;
+use emulator::device::{Device, InterruptDelay};
+use types::Register;
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// Path in the temporary directory ending with `name`, unique to this call
+/// and this process.
+pub fn temp_path(name: &str) -> PathBuf {
+    let n = TEMP_COUNTER.fetch_add(1, Ordering::SeqCst);
+    env::temp_dir().join(format!("dcpu-test-{}-{}-{}", process::id(), n, name))
+}
+
+/// Send a hardware interrupt to `device` with `a` in A and the other
+/// `registers` set.
+pub fn command<D: Device>(device: &mut D,
+                          cpu: &mut Cpu,
+                          a: u16,
+                          registers: &[(Register, u16)]) -> InterruptDelay {
+    cpu.registers[Register::A] = a;
+    for &(r, value) in registers {
+        cpu.registers[r] = value;
+    }
+    device.interrupt(cpu).unwrap()
+}
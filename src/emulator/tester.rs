@@ -0,0 +1,207 @@
+//! Unit tests of assembly programs: each test label is called with `JSR` on a
+//! freshly reset computer, after an optional setup label.
+
+use std::fmt;
+use std::num::Wrapping;
+
+use assembler::types::Globals;
+use emulator::computer::{Computer, ExitConditions, ExitReason};
+use emulator::debugger::LogMessage;
+use emulator::device;
+
+/// Return address of the tests, reaching it ends the test.
+pub const SENTINEL: u16 = 0xffff;
+
+/// Why a test failed.
+#[derive(Debug)]
+pub enum Failure {
+    /// `BRK` with a message other than 0.
+    Break(u16),
+    Halted,
+    /// Still running after this number of ticks.
+    TickLimit(u64),
+    Log(u16),
+    Error(String),
+    /// The devices could not be reset before the test.
+    Reset(device::Error),
+    /// The setup label failed.
+    Setup(Box<Failure>),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Failure::Break(msg) => write!(f, "BRK 0x{:x}", msg),
+            Failure::Halted => write!(f, "halted"),
+            Failure::TickLimit(ticks) => {
+                write!(f, "still running after {} ticks", ticks)
+            }
+            Failure::Log(msg) => write!(f, "stopped by LOG 0x{:x}", msg),
+            Failure::Error(ref e) => write!(f, "{}", e),
+            Failure::Reset(ref e) => write!(f, "{}", e),
+            Failure::Setup(ref e) => write!(f, "setup: {}", e),
+        }
+    }
+}
+
+/// Labels starting with `prefix`, sorted by address.
+pub fn find_tests(symbols: &Globals, prefix: &str) -> Vec<(String, u16)> {
+    let mut tests: Vec<(String, u16)> =
+        symbols.iter()
+               .filter(|&(name, _)| name.starts_with(prefix))
+               .map(|(name, infos)| (name.clone(), infos.addr))
+               .collect();
+    tests.sort_by_key(|&(_, addr)| addr);
+    tests
+}
+
+pub struct Runner {
+    /// Loaded at address 0 before each test.
+    pub image: Vec<u16>,
+    pub setup: Option<u16>,
+    pub max_ticks: u64,
+    /// Fill the `litteral` of the logged messages.
+    pub log_litterals: bool,
+}
+
+impl Runner {
+    pub fn new(image: Vec<u16>) -> Runner {
+        Runner {
+            image: image,
+            setup: None,
+            max_ticks: 1_000_000,
+            log_litterals: false,
+        }
+    }
+
+    /// Reload the image, then call the setup label and the test at `addr`.
+    pub fn run(&self,
+               computer: &mut Computer,
+               addr: u16,
+               logs: &mut Vec<LogMessage>) -> Result<(), Failure> {
+        try!(computer.power_cycle(&self.image).map_err(Failure::Reset));
+        if let Some(setup) = self.setup {
+            try!(self.call(computer, setup, logs)
+                     .map_err(|e| Failure::Setup(Box::new(e))));
+        }
+        self.call(computer, addr, logs)
+    }
+
+    fn call(&self,
+            computer: &mut Computer,
+            addr: u16,
+            logs: &mut Vec<LogMessage>) -> Result<(), Failure> {
+        {
+            let cpu = &mut computer.cpu;
+            cpu.sp -= Wrapping(1);
+            cpu.ram[cpu.sp] = SENTINEL;
+            cpu.pc = Wrapping(addr);
+        }
+        let conditions = ExitConditions {
+            max_ticks: Some(computer.current_tick + self.max_ticks),
+            pcs: vec![SENTINEL],
+            logs: vec![],
+        };
+        let log_litterals = self.log_litterals;
+        let reason = computer.run(&conditions, |cpu| {
+            let values: Vec<u16> = cpu.log_queue.drain(..).collect();
+            for value in values {
+                logs.push(LogMessage {
+                    value: value,
+                    litteral: if log_litterals {
+                        Some(cpu.get_str(value))
+                    } else {
+                        None
+                    },
+                });
+            }
+        });
+        match reason {
+            ExitReason::Pc(_) | ExitReason::Break(0) => Ok(()),
+            ExitReason::Break(msg) => Err(Failure::Break(msg)),
+            ExitReason::Halted => Err(Failure::Halted),
+            ExitReason::TickLimit => Err(Failure::TickLimit(self.max_ticks)),
+            ExitReason::Error(e) => Err(Failure::Error(e)),
+            ExitReason::Log(msg) => Err(Failure::Log(msg)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use assembler::types::LabelInfos;
+    use emulator::cpu::Cpu;
+    use types::{BasicOp, Instruction, Register, SpecialOp, Value};
+
+    use super::*;
+
+    #[test]
+    fn test_runner() {
+        let ret = Instruction::BasicOp(BasicOp::SET, Value::PC, Value::Push);
+        let special = |op, v| Instruction::SpecialOp(op, Value::Litteral(v));
+        let labels = [("setup", 0), ("test_pass", 2), ("test_brk", 5),
+                      ("test_halt", 6), ("test_loop", 7), ("test_log", 8),
+                      ("other", 9)];
+        let ops = [// setup
+                   Instruction::BasicOp(BasicOp::SET,
+                                        Value::Reg(Register::X),
+                                        Value::Litteral(5)),
+                   ret,
+                   // test_pass, fails without the setup
+                   Instruction::BasicOp(BasicOp::IFN,
+                                        Value::Reg(Register::X),
+                                        Value::Litteral(5)),
+                   special(SpecialOp::BRK, 1),
+                   ret,
+                   special(SpecialOp::BRK, 2),
+                   special(SpecialOp::HLT, 0),
+                   Instruction::BasicOp(BasicOp::SET, Value::PC, Value::Litteral(7)),
+                   special(SpecialOp::LOG, 0x10),
+                   ret];
+        let mut cpu = Cpu::default();
+        cpu.load_ops(&ops, 0);
+        let image = cpu.ram[0..10].to_vec();
+        let symbols: Globals = labels.iter()
+                                     .map(|&(name, addr)| {
+                                         (name.into(),
+                                          LabelInfos {
+                                              addr: addr,
+                                              locals: HashMap::new(),
+                                          })
+                                     })
+                                     .collect();
+
+        let tests = find_tests(&symbols, "test_");
+        assert_eq!(tests.iter().map(|&(_, addr)| addr).collect::<Vec<_>>(),
+                   [2, 5, 6, 7, 8]);
+
+        let mut runner = Runner::new(image);
+        runner.max_ticks = 100;
+        let mut computer = Computer::new(Cpu::default(), vec![]);
+        let mut logs = vec![];
+        match runner.run(&mut computer, 2, &mut logs) {
+            Err(Failure::Break(1)) => (),
+            r => panic!("{:?}", r),
+        }
+        runner.setup = Some(0);
+        let results: Vec<String> = tests.iter()
+                                        .map(|&(_, addr)| {
+                                            match runner.run(&mut computer,
+                                                             addr,
+                                                             &mut logs) {
+                                                Ok(()) => "ok".into(),
+                                                Err(e) => e.to_string(),
+                                            }
+                                        })
+                                        .collect();
+        assert_eq!(results,
+                   ["ok", "BRK 0x2", "halted", "still running after 100 ticks", "ok"]);
+        assert_eq!(logs,
+                   [LogMessage {
+                       value: 0x10,
+                       litteral: None,
+                   }]);
+    }
+}
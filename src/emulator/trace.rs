@@ -0,0 +1,338 @@
+//! Instruction-level execution trace.
+//!
+//! When `Cpu::trace` is set, the cpu records for each executed instruction
+//! the registers it changed and the memory it wrote. The dispatch of an
+//! interrupt and the memory written by the devices outside of an instruction
+//! get their own entries. `Computer` and `Debugger` send these entries to a
+//! `Tracer`, which writes them either as text:
+//!
+//! ```text
+//! 0000000004 0002 loop+1              ADD A, 1                 A=0002 EX=0000
+//! 0000000006 0003 loop+2              SET PUSH, A              SP=fffe [fffe]=0002
+//! 0000000008 0004 loop+3              interrupt 0x0001         A=0001 SP=fffc [fffd]=0004 [fffc]=0002
+//! 0000000010 0020 handler             devices                  [1000]=cafe
+//! ```
+//!
+//! or in a compact binary format made of little-endian words: the tick (4
+//! words, low word first), the PC, the kind of entry (0 for an instruction
+//! followed by its length and words, 1 for an interrupt followed by its
+//! message, 2 for devices), the number of changed registers followed by
+//! `(register, value)` pairs, and the number of memory writes followed by
+//! `(address, value)` pairs.
+
+use std::fmt;
+use std::io::{self, Read, Write};
+use std::str::FromStr;
+
+use assembler::types::Globals;
+use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
+use emulator::symbols::SymbolMap;
+use types::Instruction;
+
+/// Registers recorded in the trace. PC is not included since it is given by
+/// the next entry.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum RegisterId {
+    A,
+    B,
+    C,
+    X,
+    Y,
+    Z,
+    I,
+    J,
+    SP,
+    EX,
+    IA,
+}
+
+pub const REGISTERS: [RegisterId; 11] = [RegisterId::A,
+                                         RegisterId::B,
+                                         RegisterId::C,
+                                         RegisterId::X,
+                                         RegisterId::Y,
+                                         RegisterId::Z,
+                                         RegisterId::I,
+                                         RegisterId::J,
+                                         RegisterId::SP,
+                                         RegisterId::EX,
+                                         RegisterId::IA];
+
+/// What changed the registers and the memory of an entry.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Event {
+    Instruction(Instruction<u16>),
+    /// Dispatch of an interrupt with this message, pushing PC and A.
+    Interrupt(u16),
+    /// Memory written by the devices between two instructions.
+    Devices,
+}
+
+impl fmt::Display for Event {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Event::Instruction(ref i) => write!(f, "{}", i),
+            Event::Interrupt(msg) => write!(f, "interrupt 0x{:04x}", msg),
+            Event::Devices => write!(f, "devices"),
+        }
+    }
+}
+
+/// Effects of one executed instruction, interrupt dispatch or device
+/// writes.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Entry {
+    pub tick: u64,
+    /// Address of the instruction, or of the next one for the other events.
+    pub pc: u16,
+    pub event: Event,
+    pub registers: Vec<(RegisterId, u16)>,
+    /// Memory written, in order.
+    pub memory: Vec<(u16, u16)>,
+}
+
+impl Entry {
+    /// Write the entry as one line. `symbol` is the label of the PC.
+    pub fn write_text<W: Write>(&self, output: &mut W, symbol: &str) -> io::Result<()> {
+        let mut line = format!("{:010} {:04x} {:<20} {:<24}",
+                               self.tick,
+                               self.pc,
+                               symbol,
+                               self.event.to_string());
+        for &(r, value) in &self.registers {
+            line.push_str(&format!(" {:?}={:04x}", r, value));
+        }
+        for &(addr, value) in &self.memory {
+            line.push_str(&format!(" [{:04x}]={:04x}", addr, value));
+        }
+        writeln!(output, "{}", line.trim_right())
+    }
+
+    pub fn write_binary<W: Write>(&self, output: &mut W) -> io::Result<()> {
+        let mut words = vec![];
+        for i in 0..4 {
+            words.push((self.tick >> (16 * i)) as u16);
+        }
+        words.push(self.pc);
+        match self.event {
+            Event::Instruction(ref instruction) => {
+                let mut encoded = [0; 3];
+                let len = instruction.encode(&mut encoded);
+                words.push(0);
+                words.push(len);
+                words.extend_from_slice(&encoded[..len as usize]);
+            }
+            Event::Interrupt(msg) => {
+                words.push(1);
+                words.push(msg);
+            }
+            Event::Devices => words.push(2),
+        }
+        words.push(self.registers.len() as u16);
+        for &(r, value) in &self.registers {
+            words.push(r as u16);
+            words.push(value);
+        }
+        words.push(self.memory.len() as u16);
+        for &(addr, value) in &self.memory {
+            words.push(addr);
+            words.push(value);
+        }
+        output.write_all_items::<u16, LittleEndian>(&words)
+    }
+
+    /// Read an entry written by `write_binary`, `None` at the end of the
+    /// input.
+    pub fn read_binary<R: Read>(input: &mut R) -> io::Result<Option<Entry>> {
+        let mut tick = match input.read_item::<u16, LittleEndian>() {
+            Ok(w) => w as u64,
+            Err(ref e) if e.kind() == io::ErrorKind::UnexpectedEof => {
+                return Ok(None)
+            }
+            Err(e) => return Err(e),
+        };
+        for i in 1..4 {
+            tick |= (try!(input.read_item::<u16, LittleEndian>()) as u64) << (16 * i);
+        }
+        let pc = try!(input.read_item::<u16, LittleEndian>());
+        let event = match try!(input.read_item::<u16, LittleEndian>()) {
+            0 => {
+                let len = try!(input.read_item::<u16, LittleEndian>());
+                if len == 0 || len > 3 {
+                    return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                              "invalid instruction length"));
+                }
+                let mut encoded = [0; 3];
+                for word in encoded[..len as usize].iter_mut() {
+                    *word = try!(input.read_item::<u16, LittleEndian>());
+                }
+                let (_, instruction) = try!(Instruction::decode(&encoded).map_err(|e| {
+                    io::Error::new(io::ErrorKind::InvalidData, e.to_string())
+                }));
+                Event::Instruction(instruction)
+            }
+            1 => Event::Interrupt(try!(input.read_item::<u16, LittleEndian>())),
+            2 => Event::Devices,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                           "invalid entry kind")),
+        };
+        let mut registers = vec![];
+        for _ in 0..try!(input.read_item::<u16, LittleEndian>()) {
+            let r = try!(input.read_item::<u16, LittleEndian>());
+            let value = try!(input.read_item::<u16, LittleEndian>());
+            match REGISTERS.get(r as usize) {
+                Some(&r) => registers.push((r, value)),
+                None => return Err(io::Error::new(io::ErrorKind::InvalidData,
+                                                  "invalid register")),
+            }
+        }
+        let mut memory = vec![];
+        for _ in 0..try!(input.read_item::<u16, LittleEndian>()) {
+            let addr = try!(input.read_item::<u16, LittleEndian>());
+            let value = try!(input.read_item::<u16, LittleEndian>());
+            memory.push((addr, value));
+        }
+        Ok(Some(Entry {
+            tick: tick,
+            pc: pc,
+            event: event,
+            registers: registers,
+            memory: memory,
+        }))
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Format {
+    Text,
+    Binary,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Format, String> {
+        match s {
+            "text" => Ok(Format::Text),
+            "binary" => Ok(Format::Binary),
+            _ => Err(format!("unknown trace format \"{}\"", s)),
+        }
+    }
+}
+
+impl fmt::Display for Format {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Format::Text => write!(f, "text"),
+            Format::Binary => write!(f, "binary"),
+        }
+    }
+}
+
+/// Write trace entries to an output.
+pub struct Tracer {
+    output: Box<Write + Send>,
+    format: Format,
+    symbols: SymbolMap,
+}
+
+impl Tracer {
+    pub fn new(output: Box<Write + Send>, format: Format) -> Tracer {
+        Tracer {
+            output: output,
+            format: format,
+            symbols: SymbolMap::default(),
+        }
+    }
+
+    /// Show the PC as `label+offset` in the text format.
+    pub fn symbols(&mut self, symbols: &Globals) {
+        self.symbols = SymbolMap::new(symbols);
+    }
+
+    pub fn record(&mut self, entry: &Entry) -> io::Result<()> {
+        match self.format {
+            Format::Text => {
+                let symbol = self.symbols.describe(entry.pc);
+                entry.write_text(&mut self.output, &symbol)
+            }
+            Format::Binary => entry.write_binary(&mut self.output),
+        }
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.output.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use types::{BasicOp, Register, Value};
+
+    #[test]
+    fn test_binary_roundtrip() {
+        let entry = Entry {
+            tick: 0x1_0002_0003,
+            pc: 0x42,
+            event: Event::Instruction(Instruction::BasicOp(BasicOp::SET,
+                                                           Value::AtAddr(0x1000),
+                                                           Value::Litteral(0x1234))),
+            registers: vec![(RegisterId::SP, 0xfffe), (RegisterId::IA, 3)],
+            memory: vec![(0x1000, 0x1234)],
+        };
+        let interrupt = Entry {
+            event: Event::Interrupt(7),
+            registers: vec![(RegisterId::A, 7)],
+            ..entry.clone()
+        };
+        let devices = Entry {
+            event: Event::Devices,
+            registers: vec![],
+            ..entry.clone()
+        };
+        let mut output = vec![];
+        for e in &[&entry, &interrupt, &devices] {
+            e.write_binary(&mut output).unwrap();
+        }
+        let mut input = &output[..];
+        assert_eq!(Entry::read_binary(&mut input).unwrap(), Some(entry));
+        assert_eq!(Entry::read_binary(&mut input).unwrap(), Some(interrupt));
+        assert_eq!(Entry::read_binary(&mut input).unwrap(), Some(devices));
+        assert_eq!(Entry::read_binary(&mut input).unwrap(), None);
+    }
+
+    #[test]
+    fn test_binary_invalid_length() {
+        for &len in &[0, 4] {
+            // tick, pc, instruction kind, length and 4 words
+            let words = [1, 0, 0, 0, 0x42, 0, len, 0x7c01, 0x1234, 0, 0];
+            let mut input = vec![];
+            input.write_all_items::<u16, LittleEndian>(&words).unwrap();
+            match Entry::read_binary(&mut &input[..]) {
+                Err(ref e) if e.kind() == io::ErrorKind::InvalidData => (),
+                r => panic!("{}: {:?}", len, r),
+            }
+        }
+    }
+
+    #[test]
+    fn test_text() {
+        let entry = Entry {
+            tick: 12,
+            pc: 3,
+            event: Event::Instruction(Instruction::BasicOp(BasicOp::ADD,
+                                                           Value::Reg(Register::A),
+                                                           Value::Litteral(1))),
+            registers: vec![(RegisterId::A, 2), (RegisterId::EX, 0)],
+            memory: vec![(0xfffe, 2)],
+        };
+        let mut output = vec![];
+        entry.write_text(&mut output, "loop+1").unwrap();
+        assert_eq!(String::from_utf8(output).unwrap(),
+                   format!("0000000012 0003 {:<20} {:<24} A=0002 EX=0000 \
+                            [fffe]=0002\n",
+                           "loop+1",
+                           "ADD A, 1"));
+    }
+}
@@ -14,6 +14,8 @@ extern crate error_chain;
 #[macro_use]
 extern crate serde_derive;
 extern crate serde;
+#[cfg(test)]
+extern crate serde_json;
 
 #[cfg(feature = "clap")]
 #[macro_use]
@@ -26,11 +28,17 @@ extern crate enum_primitive;
 extern crate log;
 #[macro_use]
 extern crate nom;
+#[cfg(feature = "gif")]
+extern crate gif;
 #[cfg(feature = "glium")]
 #[macro_use]
 extern crate glium;
+#[cfg(feature = "png")]
+extern crate png;
 #[cfg(feature = "rustyline")]
 extern crate rustyline;
+#[cfg(feature = "termion")]
+extern crate termion;
 extern crate time;
 
 pub mod assembler;